@@ -0,0 +1,373 @@
+// 認証情報ファイルの 1 アカウント分の設定（パスワードと、ユーザごとの宛先の制限・転送量の上限・利用できる時間帯）
+use std::net::IpAddr;
+
+use crate::protocol::Dst;
+use crate::{Cidr, Config};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Account {
+    // bcrypt ハッシュ（allow_plaintext_passwords なら平文も可）
+    pub password: String,
+    // 宛先の制限（None なら全体の設定だけで判定する）
+    pub acl: Option<Acl>,
+    // 期間ごとの転送量の上限（None なら制限しない）
+    pub quota: Option<Quota>,
+    // 利用できる時間帯（どれかに入っていれば許す。空ならいつでも許す）
+    pub hours: Vec<TimeWindow>,
+    // 接続ごと・方向ごとの転送速度の上限（バイト/秒。None なら全体の rate_limit だけ）
+    pub rate: Option<u64>,
+}
+
+// ユーザごとに接続を許す宛先（全体の遮断設定に加えて判定する）
+// 空の一覧はその項目を制限しない（networks だけ・ports だけの指定もできる）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub networks: Vec<Cidr>,
+    pub ports: Vec<u16>,
+}
+
+// 転送量の上限（両方向の合計バイト数）と、それを数え直す期間
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub bytes: u64,
+    pub period: QuotaPeriod,
+}
+
+// 転送量を数え直す期間（UTC の暦に合わせる。週は月曜日から）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    Day,
+    Week,
+    #[default]
+    Month,
+}
+
+// 曜日と時刻の範囲（時刻は utc_offset を足した現地時刻、0 時からの分）
+// end が start 以前なら日付をまたぐ範囲（22:00-06:00 は、その曜日の 22 時から翌朝 6 時まで）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    // 月曜日を 0 とする曜日ごとの有無
+    pub days: [bool; 7],
+    pub start: u32,
+    pub end: u32,
+}
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+impl TimeWindow {
+    // weekday（月曜日が 0）の minute（0 時からの分）がこの範囲に入るか
+    pub fn contains(&self, weekday: usize, minute: u32) -> bool {
+        if self.start < self.end {
+            return self.days[weekday] && (self.start..self.end).contains(&minute);
+        }
+        // 日付をまたぐ範囲は、その日の start 以降と、前の日から続く end より前
+        (self.days[weekday] && minute >= self.start)
+            || (self.days[(weekday + 6) % 7] && minute < self.end)
+    }
+
+    // "Mon-Fri/09:00-18:00" や "Sat/10:00-14:00"、"*/22:00-06:00"（毎日）の形を解釈する
+    fn parse(s: &str) -> Result<Self, String> {
+        let bad = || format!("invalid time window {s:?} (expected e.g. Mon-Fri/09:00-18:00)");
+        let (days, times) = s.split_once('/').ok_or_else(bad)?;
+        let weekday = |d: &str| WEEKDAYS.iter().position(|w| w.eq_ignore_ascii_case(d));
+        let mut selected = [false; 7];
+        if days == "*" {
+            selected = [true; 7];
+        } else if let Some((first, last)) = days.split_once('-') {
+            // Fri-Mon のように週末をまたぐ指定も許す
+            let mut d = weekday(first).ok_or_else(bad)?;
+            let last = weekday(last).ok_or_else(bad)?;
+            loop {
+                selected[d] = true;
+                if d == last {
+                    break;
+                }
+                d = (d + 1) % 7;
+            }
+        } else {
+            selected[weekday(days).ok_or_else(bad)?] = true;
+        }
+        let (start, end) = times.split_once('-').ok_or_else(bad)?;
+        let minute = |t: &str| {
+            let (h, m) = t.split_once(':')?;
+            let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+            // 終わりの時刻には 24:00 も書ける
+            (m < 60 && (h < 24 || (h == 24 && m == 0))).then_some(h * 60 + m)
+        };
+        let (start, end) = (minute(start).ok_or_else(bad)?, minute(end).ok_or_else(bad)?);
+        if start == end || start >= 24 * 60 {
+            return Err(bad());
+        }
+        Ok(TimeWindow { days: selected, start, end })
+    }
+}
+
+impl Account {
+    // 利用できる時間帯の指定があれば、unix_secs（UNIX 時刻の秒）の現地時刻が入っているか
+    // 現地時刻は utc_offset（UTC からの分）で決める
+    pub(crate) fn allowed_at(&self, unix_secs: u64, utc_offset: i32) -> bool {
+        if self.hours.is_empty() {
+            return true;
+        }
+        let local = unix_secs as i64 + i64::from(utc_offset) * 60;
+        let days = local.div_euclid(86_400);
+        let minute = (local.rem_euclid(86_400) / 60) as u32;
+        // 1970-01-01 は木曜日
+        let weekday = (days + 3).rem_euclid(7) as usize;
+        self.hours.iter().any(|w| w.contains(weekday, minute))
+    }
+
+    // 認証情報ファイルの "user:password" の後ろに空白区切りで続く key=value を解釈する
+    // dst=CIDR,... と ports=PORT,... を指定したアカウントには Acl を付ける
+    // quota=BYTES（K/M/G/T の接尾辞は 1024 倍）と quota_period=day|week|month（既定 month）で
+    // 転送量の上限を付ける
+    // hours=Mon-Fri/09:00-18:00,... で利用できる時間帯を付ける
+    // rate=BYTES（接尾辞は quota と同じ）で接続ごと・方向ごとの転送速度（バイト/秒）の上限を付ける
+    pub(crate) fn parse(password: &str, options: &[&str]) -> Result<Self, String> {
+        let mut acl: Option<Acl> = None;
+        let mut hours = Vec::new();
+        let mut quota_bytes = None;
+        let mut period = None;
+        let mut rate = None;
+        for option in options {
+            let Some((key, value)) = option.split_once('=') else {
+                return Err(format!("expected key=value, got {option:?}"));
+            };
+            let list = value.split(',').map(str::trim).filter(|v| !v.is_empty());
+            match key {
+                "dst" => {
+                    let networks = list.map(str::parse).collect::<Result<Vec<Cidr>, _>>()?;
+                    acl.get_or_insert_default().networks.extend(networks);
+                }
+                "ports" => {
+                    let ports = list
+                        .map(|p| {
+                            p.parse::<u16>()
+                                .ok()
+                                .filter(|&p| p > 0)
+                                .ok_or_else(|| format!("invalid port {p:?}"))
+                        })
+                        .collect::<Result<Vec<u16>, _>>()?;
+                    acl.get_or_insert_default().ports.extend(ports);
+                }
+                "hours" => {
+                    let windows = list.map(TimeWindow::parse).collect::<Result<Vec<_>, _>>()?;
+                    hours.extend(windows);
+                }
+                "quota" => quota_bytes = Some(parse_bytes(value)?),
+                "rate" => {
+                    rate = Some(
+                        parse_bytes(value)
+                            .ok()
+                            .filter(|&r| r > 0)
+                            .ok_or_else(|| format!("invalid rate {value:?}"))?,
+                    );
+                }
+                "quota_period" => {
+                    period = Some(match value {
+                        "day" => QuotaPeriod::Day,
+                        "week" => QuotaPeriod::Week,
+                        "month" => QuotaPeriod::Month,
+                        _ => return Err(format!("invalid quota_period {value:?}")),
+                    })
+                }
+                _ => return Err(format!("unknown option {key:?}")),
+            }
+        }
+        let quota = match (quota_bytes, period) {
+            (Some(bytes), period) => Some(Quota { bytes, period: period.unwrap_or_default() }),
+            (None, Some(_)) => return Err("quota_period needs quota".to_string()),
+            (None, None) => None,
+        };
+        Ok(Account { password: password.to_string(), acl, quota, hours, rate })
+    }
+}
+
+// UTC からの時差（"+09:00"・"-05:30"・"+9" など）を分にする
+pub(crate) fn parse_utc_offset(s: &str) -> Result<i32, String> {
+    let bad = || format!("invalid UTC offset {s:?} (expected e.g. +09:00)");
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(bad()),
+    };
+    let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+    let (h, m) = (h.parse::<i32>().map_err(|_| bad())?, m.parse::<i32>().map_err(|_| bad())?);
+    if !(0..=14).contains(&h) || !(0..60).contains(&m) {
+        return Err(bad());
+    }
+    Ok(sign * (h * 60 + m))
+}
+
+// "500M" や "10G" のようなバイト数（接尾辞 K/M/G/T は 1024 倍ずつ）
+pub(crate) fn parse_bytes(value: &str) -> Result<u64, String> {
+    let (digits, shift) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 10),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 20),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 30),
+        Some(b'T' | b't') => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid byte count {value:?}"))
+}
+
+impl Acl {
+    // 宛先がこの ACL で許されていなければ、その理由を返す
+    // ドメイン名の宛先のアドレスはここでは判定せず、接続する直前に名前解決の結果を denied_address で判定する
+    // （判定と接続で別々に名前解決すると、その間に DNS の応答が変わり得る）
+    // 上位プロキシに名前解決を任せる設定（remote_dns）ではアドレスが分からないため、
+    // networks を指定したアカウントのドメイン名の宛先は拒否する
+    pub(crate) fn denied_reason(&self, dst: &Dst, config: &Config) -> Option<String> {
+        let port = dst.port();
+        if !self.ports.is_empty() && !self.ports.contains(&port) {
+            return Some(format!("port {port} not allowed for this user"));
+        }
+        match dst {
+            Dst::Domain(host, _) => {
+                // ゾーン付きの IPv6 アドレス（"fe80::1%eth0"）はアドレス部分で判定する
+                match host.split('%').next().and_then(|ip| ip.parse().ok()) {
+                    Some(ip) => self.denied_address(ip),
+                    None if !self.networks.is_empty()
+                        && config.upstream.is_some()
+                        && config.remote_dns =>
+                    {
+                        Some(format!(
+                            "domain {host} cannot be checked against this user's networks \
+                             (resolved by the upstream proxy)"
+                        ))
+                    }
+                    None => None,
+                }
+            }
+            _ => dst.ip().and_then(|ip| self.denied_address(ip)),
+        }
+    }
+
+    // 接続先のアドレスが networks に含まれていなければ、その理由を返す
+    pub(crate) fn denied_address(&self, ip: IpAddr) -> Option<String> {
+        if self.networks.is_empty() || self.networks.iter().any(|c| c.contains(ip)) {
+            return None;
+        }
+        Some(format!("address {ip} not allowed for this user"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(options: &[&str]) -> Result<Account, String> {
+        Account::parse("secret", options)
+    }
+
+    #[test]
+    fn parses_account_options() {
+        let plain = Account { password: "secret".into(), ..Account::default() };
+        assert_eq!(parse(&[]).unwrap(), plain);
+        let account = parse(&[
+            "dst=10.0.0.0/8,192.168.1.0/24",
+            "ports=80,443",
+            "dst=2001:db8::1",
+            "quota=500M",
+            "quota_period=week",
+            "hours=Mon-Fri/09:00-18:00,Sat/10:00-14:00",
+            "rate=64K",
+        ])
+        .unwrap();
+        let acl = account.acl.unwrap();
+        let networks: Vec<String> = acl.networks.iter().map(Cidr::to_string).collect();
+        assert_eq!(networks, ["10.0.0.0/8", "192.168.1.0/24", "2001:db8::1/128"]);
+        assert_eq!(acl.ports, [80, 443]);
+        assert_eq!(account.quota, Some(Quota { bytes: 500 << 20, period: QuotaPeriod::Week }));
+        assert_eq!(account.hours.len(), 2);
+        assert_eq!(account.rate, Some(64 << 10));
+        // quota_period を省略すると月ごと
+        let quota = parse(&["quota=1G"]).unwrap().quota.unwrap();
+        assert_eq!(quota.period, QuotaPeriod::Month);
+    }
+
+    #[test]
+    fn rejects_bad_account_options() {
+        let cases = [
+            ("dst", "expected key=value"),
+            ("dst=10.0.0.0/33", "invalid CIDR"),
+            ("ports=0", "invalid port"),
+            ("ports=80,http", "invalid port"),
+            ("quota=lots", "invalid byte count"),
+            ("quota=99999999T", "invalid byte count"),
+            ("rate=0", "invalid rate"),
+            ("quota_period=day", "quota_period needs quota"),
+            ("hours=Mon", "invalid time window"),
+            ("color=blue", "unknown option"),
+        ];
+        for (option, message) in cases {
+            let err = parse(&[option]).unwrap_err();
+            assert!(err.contains(message), "{option:?}: {err}");
+        }
+        let err = parse(&["quota=1G", "quota_period=year"]).unwrap_err();
+        assert!(err.contains("invalid quota_period"), "{err}");
+    }
+
+    #[test]
+    fn parses_time_windows() {
+        let w = TimeWindow::parse("Mon-Fri/09:00-18:00").unwrap();
+        assert_eq!(w.days, [true, true, true, true, true, false, false]);
+        assert_eq!((w.start, w.end), (9 * 60, 18 * 60));
+        // 週末をまたぐ曜日の範囲と、24:00 の終わり
+        let w = TimeWindow::parse("fri-mon/20:00-24:00").unwrap();
+        assert_eq!(w.days, [true, false, false, false, true, true, true]);
+        assert_eq!(w.end, 24 * 60);
+        assert_eq!(TimeWindow::parse("*/22:00-06:00").unwrap().days, [true; 7]);
+        for bad in [
+            "Mon/09:00",
+            "Mon-Funday/09:00-18:00",
+            "Mon/09:00-09:00",
+            "Mon/24:00-06:00",
+            "Mon/09:60-18:00",
+            "Mon/9-18",
+            "09:00-18:00",
+        ] {
+            assert!(TimeWindow::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn time_windows_can_run_past_midnight() {
+        let w = TimeWindow::parse("Fri/22:00-06:00").unwrap();
+        assert!(w.contains(4, 23 * 60));
+        assert!(w.contains(5, 5 * 60 + 59));
+        assert!(!w.contains(5, 6 * 60));
+        assert!(!w.contains(4, 5 * 60));
+        assert!(!w.contains(3, 23 * 60));
+    }
+
+    #[test]
+    fn allowed_at_uses_the_local_time() {
+        let account = parse(&["hours=Mon-Fri/09:00-18:00"]).unwrap();
+        // 1970-01-05 は月曜日。UTC 08:30 は +09:00 では 17:30
+        let monday_0830 = 4 * 86_400 + 8 * 3600 + 30 * 60;
+        assert!(!account.allowed_at(monday_0830, 0));
+        assert!(account.allowed_at(monday_0830, 9 * 60));
+        assert!(!account.allowed_at(monday_0830 + 3600, 9 * 60));
+        assert!(parse(&[]).unwrap().allowed_at(monday_0830, 0));
+    }
+
+    #[test]
+    fn parses_utc_offsets_and_byte_counts() {
+        assert_eq!(parse_utc_offset("+09:00"), Ok(540));
+        assert_eq!(parse_utc_offset("-05:30"), Ok(-330));
+        assert_eq!(parse_utc_offset("+9"), Ok(540));
+        for bad in ["09:00", "+15:00", "+09:60", "+", "UTC"] {
+            assert!(parse_utc_offset(bad).is_err(), "{bad:?}");
+        }
+        assert_eq!(parse_bytes("1500"), Ok(1500));
+        assert_eq!(parse_bytes("2k"), Ok(2048));
+        assert_eq!(parse_bytes("1T"), Ok(1 << 40));
+        assert!(parse_bytes("").is_err());
+        assert!(parse_bytes("1.5G").is_err());
+    }
+}
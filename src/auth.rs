@@ -0,0 +1,315 @@
+// RFC1929（ユーザ/パスワード認証）と認証試行の監査記録
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account::Account;
+use crate::error::SocksError;
+use crate::Config;
+use crate::stream::{ClientStream, peer_label};
+use crate::log::{self, Field};
+use crate::{authban, bcrypt, metrics, ratelimit};
+
+// テナントタグモード（tenant_tags）が認証の設定と両立するかを確かめる
+// このモードは認証ではなく任意のユーザ名/パスワードを受け入れるため、認証情報ファイルや
+// 認証の必須化と組み合わせると、それらの設定が黙って無効になる。組み合わせはエラーにする
+pub(crate) fn check_tenant_tags(config: &Config) -> io::Result<()> {
+    if config.tenant_tags && (config.credentials.is_some() || config.require_auth) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "tenant tags cannot be combined with an auth file or require_auth",
+        ));
+    }
+    Ok(())
+}
+
+// RFC1929: ユーザ/パスワード認証のサブネゴシエーション
+// 成功時はクライアントが名乗ったユーザ名を返す
+// 途中で止まったクライアントがスレッドを占有しないよう、読み込みに auth_timeout を適用する
+// 時間切れの場合は接続を閉じ、ErrorKind::TimedOut を返す
+// peer は元のクライアントのアドレス（PROXY protocol で受け取った場合はそのアドレス）
+pub(crate) fn perform_userpass_auth_inline<S: ClientStream>(
+    stream: &mut S,
+    peer: Option<SocketAddr>,
+    config: &Config,
+) -> io::Result<String> {
+    let previous = stream.read_timeout()?;
+    if !config.auth_timeout.is_zero() {
+        stream.set_read_timeout(Some(config.auth_timeout))?;
+    }
+    let res = userpass_auth(stream, peer, config);
+    stream.set_read_timeout(previous)?;
+    res.map_err(|e| {
+        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+            let _ = stream.shutdown(Shutdown::Both);
+            io::Error::new(ErrorKind::TimedOut, "authentication timed out")
+        } else {
+            e
+        }
+    })
+}
+
+fn userpass_auth<S: ClientStream>(
+    stream: &mut S,
+    peer: Option<SocketAddr>,
+    config: &Config,
+) -> io::Result<String> {
+    // クライアントから: ver(1)=0x01, ulen(1), uname, plen(1), passwd
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    let ver = header[0];
+    let ulen = header[1] as usize;
+    if ver != 0x01 {
+        // バージョン不正
+        let _ = stream.write_all(&[0x01, 0x01]);
+        let _ = stream.flush();
+        return Err(SocksError::UnsupportedVersion(format!(
+            "malformed at auth request byte 0: VER=0x{ver:02X}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+
+    let mut uname_buf = vec![0u8; ulen];
+    if ulen > 0 {
+        stream.read_exact(&mut uname_buf)?;
+    }
+
+    let mut plen_buf = [0u8; 1];
+    stream.read_exact(&mut plen_buf)?;
+    let plen = plen_buf[0] as usize;
+
+    let mut pass_buf = vec![0u8; plen];
+    if plen > 0 {
+        stream.read_exact(&mut pass_buf)?;
+    }
+
+    let username = String::from_utf8_lossy(&uname_buf).to_string();
+    let password = String::from_utf8_lossy(&pass_buf).to_string();
+
+    // タグモード: パスワードは検証せず、ユーザ名をテナントタグとして受け入れる
+    // 認証情報ファイルがある場合は（check_tenant_tags を通らずに組み込まれても）必ず照合する
+    if config.tenant_tags && config.credentials.is_none() {
+        stream.write_all(&[0x01, 0x00])?; // success
+        stream.flush()?;
+        info!("Accepted tenant tag '{username}' (not authenticated)");
+        audit_auth_attempt(peer, &username, "tag");
+        return Ok(username);
+    }
+
+    // 認証情報ファイル（--auth-file）があればそれで照合し、
+    // 無ければ環境変数の 1 組（未設定時はデフォルト）で照合する
+    let mut account = None;
+    let valid = match &config.credentials {
+        Some(credentials) => {
+            account = read_credentials(credentials).get(&username).cloned();
+            account.as_ref().is_some_and(|a| password_matches(&password, &a.password))
+        }
+        None => {
+            let expected_user = env::var("PROXY_USERNAME").unwrap_or_else(|_| "user".to_string());
+            let expected_pass =
+                env::var("PROXY_PASSWORD").unwrap_or_else(|_| "password".to_string());
+            username == expected_user && password == expected_pass
+        }
+    };
+
+    if valid {
+        if let Some(peer) = peer {
+            authban::record_success(peer.ip());
+        }
+        // 利用できる時間帯（hours=）の外なら、パスワードが正しくても失敗を返す
+        // （締め出しの回数には数えない）
+        if let Some(account) = &account
+            && !account.allowed_at(unix_now(), config.utc_offset)
+        {
+            warn!("rejected user '{username}': outside the allowed hours");
+            audit_auth_attempt(peer, &username, "outside_hours");
+            stream.write_all(&[0x01, 0x01])?; // failure
+            stream.flush()?;
+            return Err(SocksError::AuthFailed(format!(
+                "user '{username}' is outside the allowed hours"
+            ))
+            .into_io(ErrorKind::PermissionDenied));
+        }
+        stream.write_all(&[0x01, 0x00])?; // success
+        stream.flush()?;
+        info!("Authenticated user '{username}' successfully");
+        audit_auth_attempt(peer, &username, "success");
+        Ok(username)
+    } else {
+        metrics::auth_failed();
+        if let Some(peer) = peer {
+            authban::record_failure(peer.ip(), config);
+        }
+        audit_auth_attempt(peer, &username, "failure");
+        stream.write_all(&[0x01, 0x01])?; // failure
+        stream.flush()?;
+        Err(SocksError::AuthFailed("invalid credentials".to_string())
+            .into_io(ErrorKind::PermissionDenied))
+    }
+}
+
+// 保存されたパスワードと照合する（bcrypt ハッシュならハッシュで検証、それ以外は平文比較）
+fn password_matches(password: &str, stored: &str) -> bool {
+    if bcrypt::is_bcrypt_hash(stored) {
+        bcrypt::verify(password, stored)
+    } else {
+        password == stored
+    }
+}
+
+// 認証情報ファイルを読み込む（1 行に 1 組の user:password。password は bcrypt ハッシュも可）
+// 空行と # で始まる行は無視する。パスワードには ':' を含めてもよいが、空白は含められない
+// user:password の後ろには空白区切りで宛先の制限（dst=CIDR,... ports=PORT,...）を書ける
+// allow_plaintext が false のとき、平文のパスワードの行はエラーにする
+pub(crate) fn load_credentials(
+    path: &str,
+    allow_plaintext: bool,
+) -> io::Result<HashMap<String, Account>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {path}: {e}")))?;
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(ErrorKind::InvalidData, format!("{path}:{line}: {msg}"))
+    };
+    let mut credentials = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let entry = fields.next().unwrap_or_default();
+        let options: Vec<&str> = fields.collect();
+        match entry.split_once(':') {
+            Some((user, pass)) if !user.is_empty() => {
+                if !allow_plaintext && !bcrypt::is_bcrypt_hash(pass) {
+                    return Err(invalid(
+                        i + 1,
+                        format!(
+                            "password for {user:?} is not a bcrypt hash \
+                             (use --allow-plaintext-passwords to accept it)"
+                        ),
+                    ));
+                }
+                let account = Account::parse(pass, &options)
+                    .map_err(|e| invalid(i + 1, format!("{user:?}: {e}")))?;
+                credentials.insert(user.to_string(), account);
+            }
+            _ => return Err(invalid(i + 1, "expected user:password".to_string())),
+        }
+    }
+    Ok(credentials)
+}
+
+// 認証済みのユーザのアカウント（宛先の制限・転送量の上限。認証情報ファイルを使わない場合は None）
+pub(crate) fn user_account(config: &Config, username: &str) -> Option<Account> {
+    let credentials = config.credentials.as_ref()?;
+    read_credentials(credentials).get(username).cloned()
+}
+
+// 共有の認証情報を読む（書き込み中に panic したスレッドがあっても、そのまま使う）
+fn read_credentials(
+    credentials: &RwLock<HashMap<String, Account>>,
+) -> RwLockReadGuard<'_, HashMap<String, Account>> {
+    match credentials.read() {
+        Ok(c) => c,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// SIGHUP: 認証情報ファイルを読み直し、共有の認証情報を丸ごと置き換える
+// 処理中の接続には影響せず、以後の認証から新しい内容を使う（転送速度の上限 rate= だけは処理中の接続にも効く）
+// 読み込みに失敗した場合は、それまでの認証情報を使い続ける
+pub(crate) fn reload_credentials(config: &Config) {
+    let (Some(path), Some(credentials)) = (&config.auth_file, &config.credentials) else {
+        warn!("SIGHUP received but no auth file is configured; nothing to reload");
+        return;
+    };
+    match load_credentials(path, config.allow_plaintext_passwords) {
+        Ok(loaded) => {
+            let count = loaded.len();
+            ratelimit::update_user_rates(|user| loaded.get(user).and_then(|a| a.rate));
+            match credentials.write() {
+                Ok(mut c) => *c = loaded,
+                Err(poisoned) => *poisoned.into_inner() = loaded,
+            }
+            info!("reloaded {count} credential(s) from {path}");
+        }
+        Err(e) => {
+            let count = read_credentials(credentials).len();
+            error!("failed to reload credentials: {e}; keeping the previous {count} credential(s)");
+        }
+    }
+}
+
+// 認証試行の監査記録（成功・失敗とも必ず記録する。出力先は log::audit を参照）
+fn audit_auth_attempt(peer: Option<SocketAddr>, username: &str, result: &str) {
+    let client = peer_label(peer);
+    log::audit(
+        "auth",
+        &[
+            ("event", Field::Str("auth")),
+            ("client", Field::Str(&client)),
+            ("user", Field::Str(username)),
+            ("result", Field::Str(result)),
+        ],
+    );
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // text を一時ファイルに書いて読み込む
+    fn load(name: &str, text: &str, allow_plaintext: bool) -> io::Result<HashMap<String, Account>> {
+        let path = env::temp_dir().join(format!("credentials-{name}-{}.txt", process::id()));
+        fs::write(&path, text).unwrap();
+        let result = load_credentials(path.to_str().unwrap(), allow_plaintext);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn loads_accounts_with_options() {
+        let credentials = load(
+            "valid",
+            "# users\n\
+             \n\
+             alice:secret\n\
+             bob:pa:ss dst=10.0.0.0/8 ports=443 hours=Mon-Fri/09:00-18:00\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(credentials.len(), 2);
+        assert_eq!(credentials["alice"], Account::parse("secret", &[]).unwrap());
+        let bob = &credentials["bob"];
+        assert_eq!(bob.password, "pa:ss");
+        assert_eq!(bob.acl.as_ref().unwrap().ports, [443]);
+        assert_eq!(bob.hours.len(), 1);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_entry() {
+        let path_suffix = |e: io::Error| e.to_string().split_once(".txt:").unwrap().1.to_string();
+        let err = load("no-colon", "alice:secret\nbob\n", true).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(path_suffix(err), "2: expected user:password");
+        let err = load("no-user", ":secret\n", true).unwrap_err();
+        assert_eq!(path_suffix(err), "1: expected user:password");
+        let err = load("option", "# c\nalice:secret ports=0\n", true).unwrap_err();
+        assert_eq!(path_suffix(err), "2: \"alice\": invalid port \"0\"");
+        let err = load("plaintext", "alice:secret\n", false).unwrap_err();
+        assert!(path_suffix(err).starts_with("1: password for \"alice\" is not a bcrypt hash"));
+    }
+}
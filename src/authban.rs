@@ -0,0 +1,75 @@
+// 認証失敗の回数をクライアント IP ごとに数え、多すぎる IP を一定時間締め出す（総当たり対策）
+// auth_fail_window の間に auth_fail_limit 回失敗したら、auth_ban_duration の間は Greeting を読まずに切断する
+// 記録は全ての接続で共有する
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Config, deadline_after};
+
+// これを超えたら、期限切れの記録（失敗の窓も締め出しも終わった IP）を捨てる
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Record {
+    failures: u32,
+    window_start: Instant,
+    banned_until: Option<Instant>,
+}
+
+static RECORDS: LazyLock<Mutex<HashMap<IpAddr, Record>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock() -> MutexGuard<'static, HashMap<IpAddr, Record>> {
+    match RECORDS.lock() {
+        Ok(r) => r,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// ip が締め出し中なら、残りの時間を返す
+pub(crate) fn banned(ip: IpAddr) -> Option<Duration> {
+    let now = Instant::now();
+    let until = lock().get(&ip)?.banned_until?;
+    until.checked_duration_since(now).filter(|d| !d.is_zero())
+}
+
+// 認証の失敗を記録する（上限に達したら締め出しを始める）
+pub(crate) fn record_failure(ip: IpAddr, config: &Config) {
+    if config.auth_fail_limit == 0 {
+        return;
+    }
+    let now = Instant::now();
+    let mut records = lock();
+    if records.len() >= MAX_TRACKED_CLIENTS {
+        let window = config.auth_fail_window;
+        records.retain(|_, r| {
+            r.banned_until.is_some_and(|t| t > now) || now.duration_since(r.window_start) < window
+        });
+    }
+    let record = records.entry(ip).or_insert(Record {
+        failures: 0,
+        window_start: now,
+        banned_until: None,
+    });
+    if now.duration_since(record.window_start) >= config.auth_fail_window {
+        record.failures = 0;
+        record.window_start = now;
+    }
+    record.failures += 1;
+    if record.failures >= config.auth_fail_limit {
+        warn!(
+            "banning {ip} for {}s after {} failed authentication attempts",
+            config.auth_ban_duration.as_secs(),
+            record.failures
+        );
+        record.failures = 0;
+        record.window_start = now;
+        record.banned_until = Some(deadline_after(now, config.auth_ban_duration));
+    }
+}
+
+// 認証に成功したら、それまでの失敗の記録を消す
+pub(crate) fn record_success(ip: IpAddr) {
+    lock().remove(&ip);
+}
@@ -0,0 +1,144 @@
+// 組み込み側が差し込む接続ごとの許可判定（Config::authorizer と Config::accept_filter）
+// authorizer は Request を読んで宛先を決めた後、組み込みの遮断ルール・ACL より先に呼ぶ
+// 宛先を書き換えた場合は、書き換えた後の宛先に組み込みのルールを適用して接続する
+// accept_filter は接続を受け付けた直後、ハンドシェイクより前に接続元のアドレスだけで判定する
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::Config;
+use crate::protocol::Dst;
+use crate::session::Session;
+
+// 判定に渡す接続の情報
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub conn_id: u64,
+    // 接続元のアドレス（Unix ドメインソケットで分からなければ None）
+    pub addr: Option<SocketAddr>,
+    // 認証したユーザ名（認証なしなら None）
+    pub user: Option<String>,
+    // SOCKS5 の CMD（0x01=CONNECT, 0x02=BIND, 0x03=UDP ASSOCIATE）。SOCKS4・HTTP CONNECT は 0x01
+    pub command: u8,
+}
+
+// 要求された宛先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+// 判定の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    // この REP で拒否する（SOCKS4 は CD=91、HTTP CONNECT は 403 で返す）
+    Deny(u8),
+    // 宛先を置き換えて続ける
+    Rewrite(Destination),
+}
+
+type DecideFn = dyn Fn(&ClientInfo, &Destination) -> Decision + Send + Sync;
+
+// 判定関数（全ての接続のスレッドから呼ぶため Send + Sync）
+#[derive(Clone)]
+pub struct Authorizer(Arc<DecideFn>);
+
+impl Authorizer {
+    pub fn new<F>(decide: F) -> Self
+    where
+        F: Fn(&ClientInfo, &Destination) -> Decision + Send + Sync + 'static,
+    {
+        Authorizer(Arc::new(decide))
+    }
+}
+
+impl fmt::Debug for Authorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Authorizer(..)")
+    }
+}
+
+impl From<&Dst> for Destination {
+    fn from(dst: &Dst) -> Self {
+        match dst {
+            Dst::V4(ip, port) => Destination::Addr(SocketAddr::from((*ip, *port))),
+            Dst::V6(ip, port) => Destination::Addr(SocketAddr::from((*ip, *port))),
+            Dst::Domain(host, port) => Destination::Domain(host.clone(), *port),
+        }
+    }
+}
+
+impl From<Destination> for Dst {
+    fn from(dst: Destination) -> Self {
+        match dst {
+            Destination::Addr(addr) => Dst::from(addr),
+            Destination::Domain(host, port) => Dst::Domain(host, port),
+        }
+    }
+}
+
+// config.authorizer があれば判定し、拒否なら REP を返す（書き換えなら dst を置き換える）
+// 元の宛先は session.dst に残り、アクセスログにはそちらが出る
+pub(crate) fn authorize(
+    config: &Config,
+    session: &Session,
+    command: u8,
+    dst: &mut Dst,
+) -> Option<u8> {
+    let authorizer = config.authorizer.as_ref()?;
+    let info = ClientInfo {
+        conn_id: session.id,
+        addr: session.peer,
+        user: session.user.clone(),
+        command,
+    };
+    match (authorizer.0)(&info, &Destination::from(&*dst)) {
+        Decision::Allow => None,
+        Decision::Deny(rep) => {
+            info!("denied by authorizer: {dst} (REP=0x{rep:02X})");
+            // REP=0x00 は成功の意味になるため、General failure (0x01) として返す
+            Some(if rep == 0x00 { 0x01 } else { rep })
+        }
+        Decision::Rewrite(new) => {
+            let new = Dst::from(new);
+            info!("destination rewritten by authorizer: {dst} -> {new}");
+            *dst = new;
+            None
+        }
+    }
+}
+
+type FilterFn = dyn Fn(SocketAddr) -> bool + Send + Sync;
+
+// 接続元のアドレスで受け付けるかを決める関数（true なら受け付ける。accept ループから呼ぶため Send + Sync）
+#[derive(Clone)]
+pub struct AcceptFilter(Arc<FilterFn>);
+
+impl AcceptFilter {
+    pub fn new<F>(accept: F) -> Self
+    where
+        F: Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    {
+        AcceptFilter(Arc::new(accept))
+    }
+}
+
+// 何も指定しなければ全て受け付ける
+impl Default for AcceptFilter {
+    fn default() -> Self {
+        AcceptFilter::new(|_| true)
+    }
+}
+
+impl fmt::Debug for AcceptFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AcceptFilter(..)")
+    }
+}
+
+// config.accept_filter が peer を拒否すれば false
+pub(crate) fn accept(config: &Config, peer: SocketAddr) -> bool {
+    config.accept_filter.as_ref().is_none_or(|filter| (filter.0)(peer))
+}
@@ -0,0 +1,119 @@
+// BIND (CMD=0x02) の処理
+use std::io::{self, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{Dst, build_error_reply, build_success_reply, reply_code_for};
+use crate::relay::splice;
+use crate::session::Session;
+use crate::{Config, deadline_after, dns};
+
+// BIND (CMD=0x02) の処理（RFC1928 の 2 段階応答）
+// 1 回目の応答で待ち受けアドレスを、2 回目の応答で接続してきた相手のアドレスを返し、
+// その後は CONNECT と同じように転送する
+// dst は接続してくる予定の相手。そのアドレス以外からの接続は閉じて、config.bind_timeout まで待ち続ける
+// （0.0.0.0 / :: なら誰からの接続でも受け付ける）
+pub(crate) fn handle_bind(
+    mut client: &TcpStream,
+    dst: &Dst,
+    tag: &str,
+    config: &Config,
+    session: &Session,
+) -> io::Result<()> {
+    let expected = match expected_peers(dst, config) {
+        Ok(expected) => expected,
+        Err(e) => {
+            let rep = build_error_reply(reply_code_for(&e), 0x01);
+            let _ = client.write_all(&rep);
+            let _ = client.flush();
+            return Err(e);
+        }
+    };
+
+    // 制御接続と同じローカル IP に、空きポート（bind_port_hint なら DST.PORT）で待ち受ける
+    let local_ip = client.local_addr()?.ip();
+    let listener = match listen(local_ip, dst, config) {
+        Ok(listener) => listener,
+        Err(e) => {
+            let rep = build_error_reply(reply_code_for(&e), 0x01);
+            let _ = client.write_all(&rep);
+            let _ = client.flush();
+            return Err(e);
+        }
+    };
+    let bound_addr = listener.local_addr()?;
+    info!("BIND listening on {bound_addr}");
+
+    // 1 回目の応答: BND = 待ち受けアドレス
+    client.write_all(&build_success_reply(bound_addr))?;
+    client.flush()?;
+
+    // タイムアウト付きで accept する（ノンブロッキングにして期限まで待つ）
+    listener.set_nonblocking(true)?;
+    let deadline = deadline_after(Instant::now(), config.bind_timeout);
+    let (remote, peer) = loop {
+        match listener.accept() {
+            Ok((stream, peer)) if expected.as_ref().is_none_or(|ips| ips.contains(&peer.ip())) => {
+                break (stream, peer);
+            }
+            // 予定の相手以外からの接続は、すぐに閉じて待ち続ける
+            Ok((_, peer)) => warn!("BIND: ignored connection from unexpected peer {peer}"),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    // 期限切れは TTL expired (0x06) を返す
+                    let rep = build_error_reply(0x06, 0x01);
+                    let _ = client.write_all(&rep);
+                    let _ = client.flush();
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        "BIND: no incoming connection before timeout",
+                    ));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => {
+                let rep = build_error_reply(reply_code_for(&e), 0x01);
+                let _ = client.write_all(&rep);
+                let _ = client.flush();
+                return Err(e);
+            }
+        }
+    };
+    drop(listener);
+    remote.set_nonblocking(false)?;
+    info!("BIND accepted connection from {peer}");
+
+    // 2 回目の応答: BND = 接続してきた相手のアドレス
+    client.write_all(&build_success_reply(peer))?;
+    client.flush()?;
+
+    splice(client, remote, tag, config, session)
+}
+
+// 待ち受けのソケットを作る
+// bind_port_hint が有効で DST.PORT が 0 でなければそのポートを使い、
+// 特権ポートなら PermissionDenied（REP=0x02）、使用中なら AddrInUse（REP=0x01）を返す
+fn listen(local_ip: IpAddr, dst: &Dst, config: &Config) -> io::Result<TcpListener> {
+    let port = if config.bind_port_hint { dst.port() } else { 0 };
+    if port != 0 && port < 1024 {
+        info!("BIND: refused privileged port hint {port}");
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("BIND: port {port} is privileged"),
+        ));
+    }
+    TcpListener::bind(SocketAddr::new(local_ip, port))
+        .inspect_err(|e| info!("BIND: cannot listen on port {port}: {e}"))
+}
+
+// 接続を受け付ける相手のアドレス（None なら誰でもよい）。ドメイン名なら名前解決したアドレスのどれか
+fn expected_peers(dst: &Dst, config: &Config) -> io::Result<Option<Vec<IpAddr>>> {
+    match dst {
+        Dst::Domain(host, port) => {
+            let addrs = dns::resolve(host, *port, config)?;
+            Ok(Some(addrs.iter().map(SocketAddr::ip).collect()))
+        }
+        _ => Ok(dst.ip().filter(|ip| !ip.is_unspecified()).map(|ip| vec![ip])),
+    }
+}
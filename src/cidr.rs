@@ -0,0 +1,135 @@
+// CIDR 表記のアドレス範囲（例: 192.168.0.0/16, 2001:db8::/32。プレフィックス省略時は単一アドレス）
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    // addr がこの範囲に含まれるか（IPv4 射影 IPv6 アドレスは IPv4 として扱う）
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => {
+                prefix_match(&net.octets(), &a.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                prefix_match(&net.octets(), &a.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+
+    // 範囲の最初と最後のアドレス（IPv4 は IPv4 射影 IPv6 アドレスとして数える）
+    pub(crate) fn range(&self) -> (u128, u128) {
+        let (addr, prefix) = match self.network {
+            IpAddr::V4(v4) => (u128::from(v4.to_ipv6_mapped()), self.prefix + 96),
+            IpAddr::V6(v6) => (u128::from(v6), self.prefix),
+        };
+        let host = u128::MAX.checked_shr(u32::from(prefix)).unwrap_or(0);
+        (addr & !host, addr | host)
+    }
+}
+
+// 先頭 prefix ビットが一致するか
+fn prefix_match(net: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    let rest = prefix % 8;
+    if net[..full] != addr[..full] {
+        return false;
+    }
+    if rest == 0 {
+        return true;
+    }
+    let mask = 0xFFu8 << (8 - rest);
+    net[full] & mask == addr[full] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid CIDR {s:?}: bad address"))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|&p| p <= max)
+                .ok_or_else(|| format!("invalid CIDR {s:?}: prefix must be 0-{max}"))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks_and_single_addresses() {
+        assert_eq!(cidr("192.168.0.0/16").to_string(), "192.168.0.0/16");
+        assert_eq!(cidr("2001:db8::/32").to_string(), "2001:db8::/32");
+        assert_eq!(cidr("10.1.2.3").to_string(), "10.1.2.3/32");
+        assert_eq!(cidr("::1").to_string(), "::1/128");
+        assert_eq!(cidr("0.0.0.0/0").range(), (0xFFFF_0000_0000, 0xFFFF_FFFF_FFFF));
+    }
+
+    #[test]
+    fn rejects_bad_addresses_and_prefixes() {
+        let cases = [
+            ("", "bad address"),
+            ("10.0.0/8", "bad address"),
+            ("example.com/24", "bad address"),
+            ("[::1]/128", "bad address"),
+            ("10.0.0.0/33", "prefix must be 0-32"),
+            ("2001:db8::/129", "prefix must be 0-128"),
+            ("10.0.0.0/", "prefix must be 0-32"),
+            ("10.0.0.0/-1", "prefix must be 0-32"),
+            ("10.0.0.0/8/8", "prefix must be 0-32"),
+        ];
+        for (input, message) in cases {
+            let err = input.parse::<Cidr>().unwrap_err();
+            assert!(err.contains(message), "{input:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn contains_checks_the_prefix_bits() {
+        let net = cidr("10.20.0.0/14");
+        assert!(net.contains(ip("10.20.0.1")));
+        assert!(net.contains(ip("10.23.255.255")));
+        assert!(!net.contains(ip("10.24.0.0")));
+        // IPv4 射影 IPv6 アドレスは IPv4 として比べ、他のファミリには一致しない
+        assert!(net.contains(ip("::ffff:10.21.0.1")));
+        assert!(!net.contains(ip("2001:db8::1")));
+        assert!(!cidr("::/0").contains(ip("10.20.0.1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.1")));
+        assert!(cidr("fe80::/10").contains(ip("febf::1")));
+        assert!(!cidr("fe80::/10").contains(ip("fec0::1")));
+    }
+}
@@ -0,0 +1,539 @@
+// コマンドライン引数の解析（外部クレートを使わない簡易版）
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::{Config, DenyMode, ShedPolicy};
+use crate::account::{parse_bytes, parse_utc_offset};
+use crate::auth::{check_tenant_tags, load_credentials};
+use crate::log::{self, LogFormat};
+use crate::protocol::parse_method;
+
+// --help で表示する一覧（引数の追加・変更時はここも更新する）
+const USAGE: &str = "\
+Usage: basic|intermediate|advanced [OPTIONS]
+
+General:
+  --config PATH                 read settings from a TOML file (later flags override it)
+  --listen ADDR                 listen address, repeatable (default 127.0.0.1:8080;
+                                unix:PATH for a Unix domain socket)
+  --reuse-port                  set SO_REUSEPORT to share the listen port (Linux)
+  --check                       validate the configuration and exit
+  --workers N                   worker threads
+  --max-connections N           concurrent connection limit (0 = unlimited)
+  --queue-capacity N            connections waiting for a free worker (default 64)
+  --shed-policy POLICY          close the newest (default) or oldest waiting connection
+                                when the queue is full
+  --shutdown-grace SECS         wait this long for connections at shutdown (default 10)
+  --metrics-listen ADDR         serve Prometheus metrics at http://ADDR/metrics
+  --statsd ADDR                 send the metrics as StatsD UDP packets to ADDR
+  --statsd-interval SECS        seconds between StatsD packets (default 10)
+  --geoip-db PATH               CSV of network,country,asn to tag destinations with
+  --slow-connects N             keep the N slowest connects for /slow (default 10, 0 = off)
+  --stats-interval SECS         log transfer progress every SECS seconds (0 = off)
+  --log-format FORMAT           log as text (default) or json (one object per line)
+  --log-file PATH               write logs to PATH instead of stdout/stderr
+  --log-max-size BYTES          rotate the log file at this size (default 10M, 0 = never)
+  --log-max-files N             rotated log files to keep (default 5)
+  --syslog[=FACILITY]           send logs to the local syslog (default facility daemon)
+  --audit-log PATH              write authentication audit records to PATH
+  -h, --help                    print this help and exit
+  -V, --version                 print the version and exit
+
+Authentication:
+  --auth-file PATH              user:password file (enables username/password auth)
+  --allow-plaintext-passwords   accept non-bcrypt passwords in the auth file
+  --quota-file PATH             JSON file that keeps per-user transfer totals
+  --quota-save-interval SECS    how often to write the quota file (0 = at exit only)
+  --utc-offset +HH:MM           time zone for the per-user access hours (default +00:00)
+  --require-auth                refuse No Auth and SOCKS4 clients
+  --tenant-tags                 accept any username as a tenant tag (no authentication)
+  --methods LIST                method preference, e.g. none,userpass
+                                (default gssapi,userpass,none)
+  --handshake-timeout SECS      limit for the handshake before forwarding (0 = none)
+  --auth-timeout SECS           limit for the username/password exchange (0 = none)
+  --auth-fail-limit N           ban a client IP after N failures (0 = off)
+  --auth-fail-window SECS       period in which failures are counted
+  --auth-ban SECS               ban duration
+
+Clients:
+  --allow-client CIDR           allow only these client ranges (repeatable)
+  --deny-client CIDR            deny these client ranges (repeatable)
+  --conn-rate N                 new connections per second per client IP (0 = off)
+  --conn-burst N                connections allowed in a burst
+  --accept-proxy-protocol       read a PROXY protocol v1/v2 header from each client
+  --http-connect                also accept HTTP CONNECT tunnels on the same port
+  --max-methods N               largest NMETHODS accepted in a greeting (default 32)
+  --max-domain-len N            longest domain name accepted in a request (default 253)
+  --strict-ordering             refuse clients that send data before the reply
+  --welcome-delay MS            wait before reading the greeting (default 0)
+
+Destinations:
+  --block-cidr CIDR             block a destination range (repeatable)
+  --rewrite FROM=TO             redirect HOST[:PORT] to HOST[:PORT] (repeatable)
+  --block-domain DOMAIN         block a domain and its subdomains (repeatable)
+  --block-port PORT             block a destination port (repeatable)
+  --allow-ports LIST            allow only these destination ports (comma-separated)
+  --block-private               block private, loopback and link-local destinations
+  --ipv6-zone IFACE             zone for link-local IPv6 destinations
+  --validate-hostnames          refuse domain names that are not valid DNS names
+  --deny-mode MODE              answer denied clients with an error reply (reply, default),
+                                close without a reply (silent) or send a RST (reset)
+
+Outgoing connections:
+  --bind-source IP              source address for outgoing connections
+  --connect-timeout SECS        timeout of each connection attempt (default 10)
+  --happy-eyeballs-delay MS     delay before trying the next address
+  --connect-attempts N          addresses to try per connection (0 = all)
+  --connect-parallelism N       attempts in flight at once (0 = no limit, 1 = one by one)
+  --connect-deadline SECS       give up connecting after this long in total (0 = off)
+  --dns-cache-ttl SECS          DNS cache lifetime (0 = off)
+  --dns-cache-size N            DNS cache entries (0 = off)
+  --upstream HOST:PORT          chain CONNECT through another SOCKS5 proxy
+  --upstream-auth USER:PASS     credentials for the upstream proxy
+  --remote-dns                  let the upstream resolve domain names (default)
+  --no-remote-dns               resolve domain names locally
+  --send-proxy-protocol         send a PROXY protocol v1 header to destinations
+
+Forwarding:
+  --buffer-size BYTES           relay buffer size
+  --rate-limit BYTES            per-direction bandwidth limit in bytes/s (0 = off)
+  --total-rate-limit BYTES      bandwidth limit for all connections in bytes/s (0 = off)
+  --no-tcp-nodelay              keep Nagle's algorithm enabled
+  --idle-timeout SECS           end a direction that reads nothing this long (0 = off)
+  --tunnel-idle-timeout SECS    close a tunnel idle in both directions (0 = off)
+  --max-lifetime SECS           close a tunnel SECS after it opened (0 = off)
+  --lifetime-window SECS        sliding lifetime: extend the deadline by SECS on every
+                                transfer, up to --max-lifetime (0 = fixed lifetime)
+  --bind-timeout SECS           how long BIND waits for the peer to connect (default 60)
+  --bind-port-hint              listen on the BIND request's DST.PORT when it is nonzero
+  --linger SECS                 SO_LINGER for tunnel sockets (0 = reset on close;
+                                default: OS behaviour)
+  --keepalive-idle SECS         TCP keepalive idle time (0 = off)
+  --keepalive-interval SECS     TCP keepalive probe interval
+  --keepalive-probes N          TCP keepalive probes before dropping
+  --tcp-user-timeout SECS       drop tunnels with data unacknowledged this long (Linux)
+";
+
+// 引数の解析結果。--help・--version ではサーバを起動せず、表示する文字列だけを返す
+// （表示して終了するのは呼び出し側のバイナリ）
+pub enum Parsed {
+    Run(Box<Config>),
+    Help(&'static str),
+    Version(String),
+}
+
+impl Config {
+    // 引数（プログラム名を除く）を解析し、指定された項目で設定を上書きする
+    // 対応する形式: --name VALUE と --name=VALUE
+    pub fn apply_args<I: IntoIterator<Item = String>>(mut self, args: I) -> io::Result<Parsed> {
+        let mut args = args.into_iter();
+        // --listen は繰り返し指定でき、最初の指定で既定値を置き換える
+        let mut listen_given = false;
+        let mut auth_file = None;
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((n, v)) => (n.to_string(), Some(v.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| invalid(format!("{name} requires a value")))
+            };
+            match name.as_str() {
+                // 使い方・バージョン（残りの引数は解析しない）
+                "-h" | "--help" => return Ok(Parsed::Help(USAGE)),
+                "-V" | "--version" => {
+                    let (pkg, version) = (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                    return Ok(Parsed::Version(format!("{pkg} {version}")));
+                }
+                // 設定ファイル（TOML）。この位置で読み込むので、後に書いた引数が優先される
+                "--config" => {
+                    let path = value()?;
+                    self = self.load_file(&path)?;
+                }
+                // 待ち受けアドレス（例: 0.0.0.0:1080, [::1]:1080, unix:/run/socks.sock）
+                "--listen" => {
+                    let addr = value()?;
+                    if !listen_given {
+                        self.listen.clear();
+                        listen_given = true;
+                    }
+                    self.listen.push(addr);
+                }
+                // 待ち受けソケットの SO_REUSEPORT（複数のインスタンスで同じポートを共有する）
+                "--reuse-port" => self.reuse_port = true,
+                // 認証情報ファイル（1 行に 1 組の user:password）。指定すると認証を有効にする
+                // 読み込みは --allow-plaintext-passwords の有無が確定する引数解析の後で行う
+                "--auth-file" => auth_file = Some(value()?),
+                // 認証を必須にする（No Auth と SOCKS4 を拒否する）
+                "--require-auth" => {
+                    self.require_auth = true;
+                    self.auth = true;
+                }
+                // ユーザ名をテナントタグとして受け入れる（認証しない）
+                "--tenant-tags" => {
+                    self.tenant_tags = true;
+                    self.auth = true;
+                }
+                // 転送を始めるまでのハンドシェイクのタイムアウト（秒。0 で無制限）
+                "--handshake-timeout" => {
+                    self.handshake_timeout = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // 認証のサブネゴシエーションのタイムアウト（秒。0 で無制限）
+                "--auth-timeout" => {
+                    self.auth_timeout = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // 認証失敗による締め出し（回数・数える期間の秒数・締め出す秒数）
+                "--auth-fail-limit" => {
+                    self.auth_fail_limit = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of attempts")))?;
+                }
+                "--auth-fail-window" => {
+                    self.auth_fail_window = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                "--auth-ban" => {
+                    self.auth_ban_duration = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // 認証情報ファイルで平文のパスワードを許可する（bcrypt への移行用）
+                "--allow-plaintext-passwords" => self.allow_plaintext_passwords = true,
+                // ユーザごとの転送量の記録を保存するファイルと、保存の間隔（0 なら終了時だけ）
+                "--quota-file" => self.quota_file = Some(value()?),
+                "--quota-save-interval" => {
+                    self.quota_save_interval =
+                        Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // 利用できる時間帯（認証情報ファイルの hours=）を判定する現地時刻の時差（+09:00 など）
+                "--utc-offset" => self.utc_offset = parse_utc_offset(&value()?).map_err(invalid)?,
+                // 接続を許可・拒否するクライアントのアドレス範囲（繰り返し指定可）
+                "--allow-client" => self.client_allow.push(value()?.parse().map_err(invalid)?),
+                "--deny-client" => self.client_deny.push(value()?.parse().map_err(invalid)?),
+                // 遮断する宛先（アドレス範囲・ドメイン名・ポート。繰り返し指定可）
+                "--block-cidr" => self.blocked_cidrs.push(value()?.parse().map_err(invalid)?),
+                // 宛先の書き換え（FROM=TO。繰り返し指定可）
+                "--rewrite" => self.rewrites.push(value()?.parse().map_err(invalid)?),
+                "--block-domain" => self.blocked_suffixes.push(value()?),
+                "--block-port" => {
+                    let port = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a port number")))?;
+                    self.blocked_ports.push(port);
+                }
+                // 許可する宛先ポート（カンマ区切り。繰り返し指定可）
+                "--allow-ports" => {
+                    for port in value()?.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                        let port = port
+                            .parse()
+                            .map_err(|_| invalid(format!("{name} must be a list of port numbers")))?;
+                        self.allowed_ports.push(port);
+                    }
+                }
+                // 名前解決後のアドレスがプライベート等の宛先を拒否する
+                "--block-private" => self.block_private = true,
+                // ワーカースレッド数
+                "--workers" => {
+                    self.workers = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid(format!("{name} must be a positive integer")))?;
+                }
+                // 空きワーカーを待つ接続の上限と、満杯のときに閉じる接続（newest・oldest）
+                "--queue-capacity" => {
+                    self.queue_capacity = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of connections")))?;
+                }
+                "--shed-policy" => {
+                    let v = value()?;
+                    self.shed_policy = ShedPolicy::parse(&v)
+                        .ok_or_else(|| invalid(format!("{name} must be newest or oldest")))?;
+                }
+                // 停止時に処理中の接続の終了を待つ上限（秒）
+                "--shutdown-grace" => {
+                    self.shutdown_grace = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // 同時接続数の上限（0 で無制限）
+                "--max-connections" => {
+                    self.max_connections = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of connections")))?;
+                }
+                // メトリクスの HTTP エンドポイントの待ち受けアドレス
+                "--metrics-listen" => self.metrics_listen = Some(value()?),
+                // メトリクスを StatsD で送る先と、その間隔（秒）
+                "--statsd" => self.statsd = Some(value()?),
+                // 宛先の国と AS 番号のデータベース（CSV）
+                "--geoip-db" => self.geoip_db = Some(value()?),
+                // 遅かった接続を覚えておく件数（0 で無効）
+                "--slow-connects" => {
+                    self.slow_connects = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of connects")))?;
+                }
+                "--statsd-interval" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    if secs == 0 {
+                        return Err(invalid(format!("{name} must be a positive number of seconds")));
+                    }
+                    self.statsd_interval = Duration::from_secs(secs);
+                }
+                // 転送バッファのサイズ（バイト）
+                "--buffer-size" => {
+                    self.buffer_size = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid(format!("{name} must be a positive integer")))?;
+                }
+                // 接続ごと・方向ごとの転送速度の上限（バイト/秒。0 で無効）
+                "--rate-limit" => {
+                    self.rate_limit = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be bytes per second")))?;
+                }
+                // 全ての接続の合計の転送速度の上限（バイト/秒。0 で無効）
+                "--total-rate-limit" => {
+                    self.total_rate_limit = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be bytes per second")))?;
+                }
+                // TCP_NODELAY を設定しない（小さな書き込みをまとめて送る）
+                "--no-tcp-nodelay" => self.tcp_nodelay = false,
+                // BIND で相手からの接続を待つ上限時間（秒）
+                "--bind-timeout" => {
+                    self.bind_timeout = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                // BIND の DST.PORT を待ち受けのポートとして使う
+                "--bind-port-hint" => self.bind_port_hint = true,
+                // ソケットごとの無通信タイムアウト（秒。0 で無効）
+                "--idle-timeout" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.idle_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                // トンネル全体の無通信タイムアウト（秒。0 で無効）
+                "--tunnel-idle-timeout" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.tunnel_idle_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                // トンネルの寿命の上限と、スライディング方式で期限を延ばす幅（秒。0 で無効）
+                "--max-lifetime" | "--lifetime-window" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    let limit = (secs > 0).then(|| Duration::from_secs(secs));
+                    match name.as_str() {
+                        "--max-lifetime" => self.max_lifetime = limit,
+                        _ => self.lifetime_window = limit,
+                    }
+                }
+                // 転送するソケットの SO_LINGER（秒。0 で RST による即時の切断）
+                "--linger" => {
+                    self.linger = Some(Duration::from_secs(parse_secs(&name, &value()?)?));
+                }
+                // TCP keepalive（秒。--keepalive-idle 0 で無効）
+                "--keepalive-idle" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.keepalive_idle = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--keepalive-interval" => {
+                    self.keepalive_interval = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                "--keepalive-probes" => {
+                    self.keepalive_probes = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid(format!("{name} must be a positive integer")))?;
+                }
+                // 転送するソケットの TCP_USER_TIMEOUT（秒。0 で OS の既定のまま）
+                "--tcp-user-timeout" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.tcp_user_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                // 名前解決のキャッシュ（保持する秒数と最大件数。どちらも 0 で無効）
+                "--dns-cache-ttl" => {
+                    self.dns_cache_ttl = Duration::from_secs(parse_secs(&name, &value()?)?);
+                }
+                "--dns-cache-size" => {
+                    self.dns_cache_size = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of entries")))?;
+                }
+                // 宛先への接続の送信元アドレス
+                "--bind-source" => {
+                    let ip = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be an IP address")))?;
+                    self.bind_source = Some(ip);
+                }
+                // ゾーンの無いリンクローカル IPv6 の宛先に使うゾーン（インターフェース名か番号）
+                "--ipv6-zone" => self.ipv6_zone = Some(value()?),
+                // 複数アドレスの宛先で、次のアドレスへの接続を始めるまでの待ち時間（ミリ秒）
+                "--happy-eyeballs-delay" => {
+                    let ms = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of milliseconds")))?;
+                    self.connect.delay = Duration::from_millis(ms);
+                }
+                // 1 回の接続の試行のタイムアウト（秒）
+                "--connect-timeout" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    if secs == 0 {
+                        return Err(invalid(format!("{name} must be a positive number of seconds")));
+                    }
+                    self.connect.timeout = Duration::from_secs(secs);
+                }
+                // 試すアドレスの数と、同時に進める試行の数（どちらも 0 で無制限）
+                "--connect-attempts" | "--connect-parallelism" => {
+                    let n = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number")))?;
+                    match name.as_str() {
+                        "--connect-attempts" => self.connect.max_attempts = n,
+                        _ => self.connect.parallelism = n,
+                    }
+                }
+                // 接続全体の期限（秒。0 で無し）。過ぎたら残りの試行をやめる
+                "--connect-deadline" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.connect.deadline = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                // CONNECT を上位の SOCKS5 プロキシ経由にする（host:port）と、その認証情報（user:password）
+                "--upstream" => self.upstream = Some(value()?),
+                "--upstream-auth" => {
+                    let v = value()?;
+                    let (user, pass) = v
+                        .split_once(':')
+                        .ok_or_else(|| invalid(format!("{name} must be USER:PASSWORD")))?;
+                    self.upstream_auth = Some((user.to_string(), pass.to_string()));
+                }
+                // 上位プロキシ経由のとき、ドメイン名を上位で名前解決させるか（既定）、手元で名前解決するか
+                "--remote-dns" => self.remote_dns = true,
+                "--no-remote-dns" => self.remote_dns = false,
+                // 宛先への接続の先頭に PROXY protocol v1 のヘッダを付ける
+                "--send-proxy-protocol" => self.send_proxy_protocol = true,
+                // クライアントからの接続の先頭で PROXY protocol のヘッダ（v1・v2）を受け取る
+                "--accept-proxy-protocol" => self.accept_proxy_protocol = true,
+                // 同じポートで HTTP CONNECT のトンネルも受け付ける
+                "--http-connect" => self.http_connect = true,
+                // ログの書式（text または json）。以後の引数の解析中のログから切り替える
+                "--log-format" => {
+                    let v = value()?;
+                    self.log_format = LogFormat::parse(&v)
+                        .ok_or_else(|| invalid(format!("{name} must be text or json")))?;
+                    log::set_format(self.log_format);
+                }
+                // ログを書くファイルと、切り替える大きさ（K/M/G の接尾辞が使える）・残す古いファイルの数
+                "--log-file" => self.log_file = Some(value()?),
+                // 認証の試行の監査記録を書くファイル
+                "--audit-log" => self.audit_log = Some(value()?),
+                "--log-max-size" => {
+                    self.log_max_size = parse_bytes(&value()?)
+                        .map_err(|e| invalid(format!("{name}: {e}")))?;
+                }
+                "--log-max-files" => {
+                    self.log_max_files = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of files")))?;
+                }
+                // ログを syslog へ送る（--syslog=local0 のように facility も指定できる。既定は daemon）
+                "--syslog" => {
+                    let facility = inline.clone().unwrap_or_else(|| "daemon".to_string());
+                    if log::syslog_facility(&facility).is_none() {
+                        return Err(invalid(format!("{name}: unknown facility {facility:?}")));
+                    }
+                    self.syslog = Some(facility);
+                }
+                // Greeting の NMETHODS と、Request のドメイン名の長さの上限
+                "--max-methods" => {
+                    self.max_methods = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of methods")))?;
+                }
+                "--max-domain-len" => {
+                    self.max_domain_len = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of bytes")))?;
+                }
+                // DNS 名として不正なホスト名を名前解決せずに拒否する
+                "--validate-hostnames" => self.validate_hostnames = true,
+                // 拒否した接続への応じ方（reply・silent・reset）
+                "--deny-mode" => {
+                    let v = value()?;
+                    self.deny_mode = DenyMode::parse(&v)
+                        .ok_or_else(|| invalid(format!("{name} must be reply, silent or reset")))?;
+                }
+                // 成功応答より前にデータを送ってきたクライアントを拒否する
+                "--strict-ordering" => self.strict_ordering = true,
+                // Greeting を読む前に挟む遅延（ミリ秒）
+                "--welcome-delay" => {
+                    let ms = value()?
+                        .parse()
+                        .map_err(|_| invalid(format!("{name} must be a number of milliseconds")))?;
+                    self.welcome_delay = Duration::from_millis(ms);
+                }
+                // 認証方法の優先順（カンマ区切り。例: none,userpass）
+                "--methods" => {
+                    self.methods = value()?
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|m| !m.is_empty())
+                        .map(|m| {
+                            parse_method(m)
+                                .ok_or_else(|| invalid(format!("{name}: unknown method {m:?}")))
+                        })
+                        .collect::<io::Result<_>>()?;
+                }
+                // 起動せずに設定を確認して終了する（終了コードで結果を返す）
+                "--check" => self.check_only = true,
+                // 転送の途中経過をログに出す間隔（秒。0 で無効）
+                "--stats-interval" => {
+                    let secs = parse_secs(&name, &value()?)?;
+                    self.stats_interval = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                // クライアント IP ごとの新規接続数の制限（1 秒あたりの接続数と、連続で許す接続数）
+                "--conn-rate" => {
+                    self.conn_rate = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&r: &f64| r >= 0.0 && r.is_finite())
+                        .ok_or_else(|| invalid(format!("{name} must be a non-negative number")))?;
+                }
+                "--conn-burst" => {
+                    self.conn_burst = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| invalid(format!("{name} must be a positive integer")))?;
+                }
+                _ => return Err(invalid(format!("unknown argument: {name}"))),
+            }
+        }
+        if let Some(path) = auth_file {
+            let credentials = load_credentials(&path, self.allow_plaintext_passwords)?;
+            info!("loaded {} credential(s) from {path}", credentials.len());
+            self.credentials = Some(Arc::new(RwLock::new(credentials)));
+            self.auth_file = Some(path);
+            self.auth = true;
+        }
+        check_tenant_tags(&self)?;
+        // スライディング方式には、延ばせる上限が要る
+        if self.lifetime_window.is_some() && self.max_lifetime.is_none() {
+            let msg = "--lifetime-window needs --max-lifetime as its ceiling";
+            return Err(invalid(msg.to_string()));
+        }
+        Ok(Parsed::Run(Box::new(self)))
+    }
+}
+
+fn parse_secs(name: &str, value: &str) -> io::Result<u64> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{name} must be a number of seconds")))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,555 @@
+// 設定ファイル（TOML）の読み込み（外部クレートを使わない簡易版）
+// 対応するのは TOML のうち、トップレベルの key = value のみ（テーブルは未対応）
+// TOML と同じく、同じキーを 2 回書くとエラーにする
+// 値は文字列（"..." / '...'）・整数・小数・真偽値と、それらの配列（複数行可）
+// キーは Config のフィールド名と同じ。時間は秒で指定する（小数可。0 は無効を表す項目がある）
+// ただし Config::connect の各項目は connect_timeout・connect_attempts・connect_parallelism・
+// connect_deadline・happy_eyeballs_delay のキーで指定する
+// ただし happy_eyeballs_delay と welcome_delay は、引数と同じくミリ秒で指定する
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::{Cidr, Config, DenyMode, Rewrite, ShedPolicy};
+use crate::account::{parse_bytes, parse_utc_offset};
+use crate::auth::load_credentials;
+use crate::log::{self, LogFormat};
+use crate::protocol::parse_method;
+
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Config {
+    // 設定ファイルを読み、書かれている項目で設定を上書きする
+    // エラーには「ファイル名:行番号」を付ける
+    pub fn load_file(mut self, path: &str) -> io::Result<Config> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot read {path}: {e}")))?;
+        let mut auth_file = None;
+        for (line, key, value) in parse(&text).map_err(|(n, msg)| invalid(path, n, &msg))? {
+            let bad = |msg: &str| invalid(path, line, &format!("{key}: {msg}"));
+            // 値を変換し、型が合わなければ「expected ...」のエラーにする
+            macro_rules! get {
+                ($conv:expr, $what:literal) => {
+                    $conv(value).ok_or_else(|| bad(concat!("expected ", $what)))?
+                };
+            }
+            match key.as_str() {
+                "listen" => self.listen = get!(strings, "strings"),
+                "reuse_port" => self.reuse_port = get!(boolean, "a boolean"),
+                "auth" => self.auth = get!(boolean, "a boolean"),
+                "require_auth" => {
+                    self.require_auth = get!(boolean, "a boolean");
+                    self.auth |= self.require_auth;
+                }
+                "tenant_tags" => {
+                    self.tenant_tags = get!(boolean, "a boolean");
+                    self.auth |= self.tenant_tags;
+                }
+                "handshake_timeout" => self.handshake_timeout = get!(seconds, "seconds"),
+                "auth_timeout" => self.auth_timeout = get!(seconds, "seconds"),
+                "auth_fail_limit" => {
+                    self.auth_fail_limit = u32::try_from(get!(count, "a number of attempts"))
+                        .map_err(|_| bad("expected a number of attempts"))?;
+                }
+                "auth_fail_window" => self.auth_fail_window = get!(seconds, "seconds"),
+                "auth_ban_duration" => self.auth_ban_duration = get!(seconds, "seconds"),
+                "auth_file" => auth_file = Some(get!(string, "a string")),
+                "allow_plaintext_passwords" => {
+                    self.allow_plaintext_passwords = get!(boolean, "a boolean");
+                }
+                "quota_file" => self.quota_file = Some(get!(string, "a string")),
+                "quota_save_interval" => self.quota_save_interval = get!(seconds, "seconds"),
+                "utc_offset" => {
+                    self.utc_offset =
+                        parse_utc_offset(&get!(string, "a string")).map_err(|e| bad(&e))?;
+                }
+                "blocked_suffixes" => self.blocked_suffixes = get!(strings, "strings"),
+                "blocked_cidrs" | "client_allow" | "client_deny" => {
+                    let cidrs = get!(strings, "strings")
+                        .iter()
+                        .map(|s| s.parse::<Cidr>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| bad(&e))?;
+                    match key.as_str() {
+                        "blocked_cidrs" => self.blocked_cidrs = cidrs,
+                        "client_allow" => self.client_allow = cidrs,
+                        _ => self.client_deny = cidrs,
+                    }
+                }
+                "rewrites" => {
+                    self.rewrites = get!(strings, "strings")
+                        .iter()
+                        .map(|s| s.parse::<Rewrite>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| bad(&e))?;
+                }
+                "blocked_ports" | "allowed_ports" => {
+                    let ports = get!(integers, "port numbers")
+                        .into_iter()
+                        .map(u16::try_from)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| bad("expected port numbers"))?;
+                    match key.as_str() {
+                        "blocked_ports" => self.blocked_ports = ports,
+                        _ => self.allowed_ports = ports,
+                    }
+                }
+                "block_private" => self.block_private = get!(boolean, "a boolean"),
+                "dns_cache_ttl" => self.dns_cache_ttl = get!(seconds, "seconds"),
+                "dns_cache_size" => self.dns_cache_size = get!(count, "a count"),
+                "connect_timeout" => {
+                    self.connect.timeout = get!(seconds, "seconds");
+                    if self.connect.timeout.is_zero() {
+                        return Err(bad("expected a positive number of seconds"));
+                    }
+                }
+                "connect_attempts" => self.connect.max_attempts = get!(count, "a count"),
+                "connect_parallelism" => self.connect.parallelism = get!(count, "a count"),
+                "connect_deadline" => self.connect.deadline = get!(optional_seconds, "seconds"),
+                "bind_timeout" => self.bind_timeout = get!(seconds, "seconds"),
+                "bind_port_hint" => self.bind_port_hint = get!(boolean, "a boolean"),
+                "happy_eyeballs_delay" => {
+                    self.connect.delay =
+                        Duration::from_millis(get!(count, "a number of milliseconds") as u64);
+                }
+                "bind_source" => {
+                    let ip = get!(string, "an IP address")
+                        .parse()
+                        .map_err(|_| bad("expected an IP address"))?;
+                    self.bind_source = Some(ip);
+                }
+                "ipv6_zone" => self.ipv6_zone = Some(get!(string, "a string")),
+                "idle_timeout" => self.idle_timeout = get!(optional_seconds, "seconds"),
+                "linger" => self.linger = Some(get!(seconds, "seconds")),
+                "tunnel_idle_timeout" => {
+                    self.tunnel_idle_timeout = get!(optional_seconds, "seconds")
+                }
+                "max_lifetime" => self.max_lifetime = get!(optional_seconds, "seconds"),
+                "lifetime_window" => self.lifetime_window = get!(optional_seconds, "seconds"),
+                "buffer_size" => self.buffer_size = get!(positive, "a positive integer"),
+                "stats_interval" => self.stats_interval = get!(optional_seconds, "seconds"),
+                "rate_limit" => self.rate_limit = get!(count, "bytes per second") as u64,
+                "total_rate_limit" => {
+                    self.total_rate_limit = get!(count, "bytes per second") as u64;
+                }
+                "tcp_nodelay" => self.tcp_nodelay = get!(boolean, "a boolean"),
+                "keepalive_idle" => self.keepalive_idle = get!(optional_seconds, "seconds"),
+                "keepalive_interval" => self.keepalive_interval = get!(seconds, "seconds"),
+                "tcp_user_timeout" => self.tcp_user_timeout = get!(optional_seconds, "seconds"),
+                "keepalive_probes" => {
+                    self.keepalive_probes = u32::try_from(get!(positive, "a positive integer"))
+                        .map_err(|_| bad("expected a positive integer"))?;
+                }
+                "shutdown_grace" => self.shutdown_grace = get!(seconds, "seconds"),
+                "workers" => self.workers = get!(positive, "a positive integer"),
+                "queue_capacity" => self.queue_capacity = get!(count, "a count"),
+                "shed_policy" => {
+                    self.shed_policy = ShedPolicy::parse(&get!(string, "newest or oldest"))
+                        .ok_or_else(|| bad("expected newest or oldest"))?;
+                }
+                "conn_rate" => {
+                    self.conn_rate = number(value)
+                        .filter(|&r| r >= 0.0 && r.is_finite())
+                        .ok_or_else(|| bad("expected a non-negative number"))?;
+                }
+                "conn_burst" => {
+                    self.conn_burst = u32::try_from(get!(positive, "a positive integer"))
+                        .map_err(|_| bad("expected a positive integer"))?;
+                }
+                "max_connections" => self.max_connections = get!(count, "a count"),
+                "metrics_listen" => self.metrics_listen = Some(get!(string, "a string")),
+                "statsd" => self.statsd = Some(get!(string, "a string")),
+                "geoip_db" => self.geoip_db = Some(get!(string, "a string")),
+                "slow_connects" => self.slow_connects = get!(count, "a count"),
+                "statsd_interval" => {
+                    self.statsd_interval = get!(seconds, "seconds");
+                    if self.statsd_interval.is_zero() {
+                        return Err(bad("expected a positive number of seconds"));
+                    }
+                }
+                "upstream" => self.upstream = Some(get!(string, "a string")),
+                "upstream_auth" => {
+                    let v = get!(string, "USER:PASSWORD");
+                    let (user, pass) = v
+                        .split_once(':')
+                        .ok_or_else(|| bad("expected USER:PASSWORD"))?;
+                    self.upstream_auth = Some((user.to_string(), pass.to_string()));
+                }
+                "send_proxy_protocol" => self.send_proxy_protocol = get!(boolean, "a boolean"),
+                "accept_proxy_protocol" => {
+                    self.accept_proxy_protocol = get!(boolean, "a boolean")
+                }
+                "http_connect" => self.http_connect = get!(boolean, "a boolean"),
+                "methods" => {
+                    self.methods = get!(strings, "strings")
+                        .iter()
+                        .map(|m| {
+                            parse_method(m).ok_or_else(|| bad(&format!("unknown method {m:?}")))
+                        })
+                        .collect::<io::Result<_>>()?;
+                }
+                "max_methods" => self.max_methods = get!(count, "a count"),
+                "max_domain_len" => self.max_domain_len = get!(count, "a count"),
+                "validate_hostnames" => self.validate_hostnames = get!(boolean, "a boolean"),
+                "deny_mode" => {
+                    self.deny_mode = DenyMode::parse(&get!(string, "reply, silent or reset"))
+                        .ok_or_else(|| bad("expected reply, silent or reset"))?;
+                }
+                "strict_ordering" => self.strict_ordering = get!(boolean, "a boolean"),
+                "welcome_delay" => {
+                    self.welcome_delay =
+                        Duration::from_millis(get!(count, "a number of milliseconds") as u64);
+                }
+                "log_file" => self.log_file = Some(get!(string, "a string")),
+                "audit_log" => self.audit_log = Some(get!(string, "a string")),
+                "log_max_size" => self.log_max_size = get!(bytes, "a byte count"),
+                "log_max_files" => self.log_max_files = get!(count, "a count"),
+                "syslog" => {
+                    self.syslog = get!(facility, "a boolean or a facility name");
+                    if let Some(f) = &self.syslog
+                        && log::syslog_facility(f).is_none()
+                    {
+                        return Err(bad(&format!("unknown facility {f:?}")));
+                    }
+                }
+                "log_format" => {
+                    self.log_format = LogFormat::parse(&get!(string, "text or json"))
+                        .ok_or_else(|| bad("expected text or json"))?;
+                    log::set_format(self.log_format);
+                }
+                "remote_dns" => self.remote_dns = get!(boolean, "a boolean"),
+                _ => return Err(bad("unknown setting")),
+            }
+        }
+        // 認証情報ファイルは allow_plaintext_passwords の有無が確定してから読む
+        if let Some(file) = auth_file {
+            let credentials = load_credentials(&file, self.allow_plaintext_passwords)?;
+            info!("loaded {} credential(s) from {file}", credentials.len());
+            self.credentials = Some(Arc::new(RwLock::new(credentials)));
+            self.auth_file = Some(file);
+            self.auth = true;
+        }
+        Ok(self)
+    }
+}
+
+fn invalid(path: &str, line: usize, msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{path}:{line}: {msg}"))
+}
+
+fn string(value: Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn boolean(value: Value) -> Option<bool> {
+    match value {
+        Value::Bool(b) => Some(b),
+        _ => None,
+    }
+}
+
+fn number(value: Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(n as f64),
+        Value::Float(f) => Some(f),
+        _ => None,
+    }
+}
+
+fn count(value: Value) -> Option<usize> {
+    match value {
+        Value::Int(n) => usize::try_from(n).ok(),
+        _ => None,
+    }
+}
+
+// true（既定の daemon）・false（送らない）、または facility の名前
+fn facility(value: Value) -> Option<Option<String>> {
+    match value {
+        Value::Bool(b) => Some(b.then(|| "daemon".to_string())),
+        Value::Str(s) => Some(Some(s)),
+        _ => None,
+    }
+}
+
+// 整数、または "10M" のような接尾辞付きの文字列
+fn bytes(value: Value) -> Option<u64> {
+    match value {
+        Value::Int(n) => u64::try_from(n).ok(),
+        Value::Str(s) => parse_bytes(&s).ok(),
+        _ => None,
+    }
+}
+
+fn positive(value: Value) -> Option<usize> {
+    count(value).filter(|&n| n > 0)
+}
+
+// 負の値・Duration に収まらない大きな値は None
+fn seconds(value: Value) -> Option<Duration> {
+    number(value).and_then(|s| Duration::try_from_secs_f64(s).ok())
+}
+
+// 0 なら無効（None）
+fn optional_seconds(value: Value) -> Option<Option<Duration>> {
+    seconds(value).map(|d| (!d.is_zero()).then_some(d))
+}
+
+// 文字列 1 つ、または文字列の配列
+fn strings(value: Value) -> Option<Vec<String>> {
+    match value {
+        Value::Str(s) => Some(vec![s]),
+        Value::Array(items) => items.into_iter().map(string).collect(),
+        _ => None,
+    }
+}
+
+fn integers(value: Value) -> Option<Vec<i64>> {
+    match value {
+        Value::Int(n) => Some(vec![n]),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|v| match v {
+                Value::Int(n) => Some(n),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+// (行番号, キー, 値)
+type Entry = (usize, String, Value);
+
+// key = value の列に分解する。エラーは (行番号, 内容)
+fn parse(text: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut entries = Vec::new();
+    let mut seen = HashMap::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let n = i + 1;
+        let line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err((n, "tables are not supported".to_string()));
+        }
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| (n, "expected key = value".to_string()))?;
+        let key = key.trim().to_string();
+        let valid_key = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if key.is_empty() || !key.chars().all(valid_key) {
+            return Err((n, format!("invalid key: {key:?}")));
+        }
+        if let Some(first) = seen.insert(key.clone(), n) {
+            return Err((n, format!("duplicate key {key} (first set on line {first})")));
+        }
+        // 配列は閉じ括弧まで次の行を読み足す
+        let mut raw = rest.trim().to_string();
+        while bracket_depth(&raw) > 0 {
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| (n, "unterminated array".to_string()))?;
+            raw.push(' ');
+            raw.push_str(strip_comment(next).trim());
+        }
+        let mut chars = raw.chars().peekable();
+        let value = parse_value(&mut chars).map_err(|e| (n, e))?;
+        if chars.any(|c| !c.is_whitespace()) {
+            return Err((n, "unexpected characters after the value".to_string()));
+        }
+        entries.push((n, key, value));
+    }
+    Ok(entries)
+}
+
+// 文字列の外にある # 以降を取り除く
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+// 文字列の外にある括弧の深さ（開き括弧が多ければ正）
+fn bracket_depth(s: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in s.chars() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' => depth -= 1,
+            None => {}
+        }
+    }
+    depth
+}
+
+fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Result<Value, String> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    match chars.peek() {
+        None => Err("missing value".to_string()),
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next().ok_or("unterminated string")? {
+                    '"' => return Ok(Value::Str(s)),
+                    '\\' => match chars.next().ok_or("unterminated string")? {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        '"' => s.push('"'),
+                        '\\' => s.push('\\'),
+                        c => return Err(format!("unsupported escape: \\{c}")),
+                    },
+                    c => s.push(c),
+                }
+            }
+        }
+        Some('\'') => {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next().ok_or("unterminated string")? {
+                    '\'' => return Ok(Value::Str(s)),
+                    c => s.push(c),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if chars.next_if_eq(&']').is_some() {
+                    return Ok(Value::Array(items));
+                }
+                items.push(parse_value(chars)?);
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                match chars.next() {
+                    Some(',') => {}
+                    Some(']') => return Ok(Value::Array(items)),
+                    _ => return Err("expected , or ] in array".to_string()),
+                }
+            }
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != ',' && c != ']') {
+                word.push(c);
+            }
+            let digits = word.replace('_', "");
+            match word.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => digits
+                    .parse()
+                    .map(Value::Int)
+                    .or_else(|_| digits.parse().map(Value::Float))
+                    .map_err(|_| format!("invalid value: {word}")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(text: &str) -> Result<Value, (usize, String)> {
+        let mut entries = parse(text)?;
+        assert_eq!(entries.len(), 1, "{text}");
+        Ok(entries.remove(0).2)
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        parse(text).err().unwrap_or_else(|| panic!("{text:?} should not parse"))
+    }
+
+    #[test]
+    fn parses_values_arrays_and_comments() {
+        let text = "# comment\n\na = 1_000 # trailing\nb = 0.5\nc = true\n\
+                    d = 'x # y'\ne = [\n  \"p\", # first\n  \"q\",\n]\n";
+        let entries = parse(text).unwrap();
+        let keys: Vec<(usize, &str)> = entries.iter().map(|(n, k, _)| (*n, k.as_str())).collect();
+        assert_eq!(keys, [(3, "a"), (4, "b"), (5, "c"), (6, "d"), (7, "e")]);
+        assert!(matches!(entries[0].2, Value::Int(1000)));
+        assert!(matches!(entries[1].2, Value::Float(f) if f == 0.5));
+        assert!(matches!(entries[2].2, Value::Bool(true)));
+        assert!(matches!(&entries[3].2, Value::Str(s) if s == "x # y"));
+        assert_eq!(strings(parse_one("e = [\"p\", \"q\"]").unwrap()).unwrap(), ["p", "q"]);
+    }
+
+    #[test]
+    fn parses_escapes_in_basic_strings_only() {
+        let value = parse_one(r#"a = "tab\tquote\"back\\slash\n""#).unwrap();
+        assert!(matches!(value, Value::Str(s) if s == "tab\tquote\"back\\slash\n"));
+        // リテラル文字列（'...'）ではエスケープしない
+        let value = parse_one(r"a = 'C:\path\n'").unwrap();
+        assert!(matches!(value, Value::Str(s) if s == r"C:\path\n"));
+        // エスケープした引用符の後の # はコメントではない
+        let value = parse_one(r#"a = "\" # not a comment""#).unwrap();
+        assert!(matches!(value, Value::Str(s) if s == "\" # not a comment"));
+
+        assert_eq!(error(r#"a = "\x41""#), (1, "unsupported escape: \\x".to_string()));
+    }
+
+    #[test]
+    fn rejects_unterminated_strings_and_arrays() {
+        for text in [r#"a = "open"#, "a = 'open", r#"a = "ends with \""#] {
+            assert_eq!(error(text).1, "unterminated string", "{text}");
+        }
+        assert_eq!(error("a = [1,\n2,\n"), (1, "unterminated array".to_string()));
+        // 文字列の中の ] では配列は閉じない
+        assert_eq!(error(r#"a = ["x]"#).1, "unterminated array");
+        assert_eq!(error("a = [1 2]").1, "expected , or ] in array");
+    }
+
+    #[test]
+    fn rejects_duplicate_keys() {
+        let err = error("a = 1\nb = 2\n\na = 3\n");
+        assert_eq!(err, (4, "duplicate key a (first set on line 1)".to_string()));
+    }
+
+    #[test]
+    fn rejects_tables_and_malformed_lines() {
+        assert_eq!(error("a = 1\n[server]\nb = 2"), (2, "tables are not supported".to_string()));
+        assert_eq!(error("[[listeners]]").1, "tables are not supported");
+        assert_eq!(error("a.b = 1").1, r#"invalid key: "a.b""#);
+        assert_eq!(error("= 1").1, r#"invalid key: """#);
+        assert_eq!(error("listen").1, "expected key = value");
+        assert_eq!(error("a =").1, "missing value");
+        assert_eq!(error("a = yes").1, "invalid value: yes");
+        assert_eq!(error(r#"a = "x" y"#).1, "unexpected characters after the value");
+    }
+}
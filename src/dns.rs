@@ -0,0 +1,102 @@
+// 名前解決のキャッシュ（ホスト名ごとに解決済みのアドレスを一定時間保持する）
+// システムの名前解決は TTL を返さないため、保持期間は dns_cache_ttl で一律に決める
+// 件数が dns_cache_size に達したら、最も長く使われていないもの（LRU）から捨てる
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+use crate::{Config, deadline_after};
+
+struct Entry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+    // 最後に使った順番（大きいほど新しい）
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+// 全ての接続で共有する
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(|| Mutex::new(Cache::default()));
+
+fn lock() -> std::sync::MutexGuard<'static, Cache> {
+    match CACHE.lock() {
+        Ok(c) => c,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// host:port を名前解決する（キャッシュにあればそれを使い、無ければシステムの名前解決の結果を保存する）
+// dns_cache_ttl か dns_cache_size が 0 ならキャッシュを使わない
+pub(crate) fn resolve(host: &str, port: u16, config: &Config) -> io::Result<Vec<SocketAddr>> {
+    let enabled = !config.dns_cache_ttl.is_zero() && config.dns_cache_size > 0;
+    if !enabled {
+        return Ok((host, port).to_socket_addrs()?.collect());
+    }
+
+    let key = host.to_ascii_lowercase();
+    if let Some(addrs) = lookup(&key) {
+        debug!("dns cache hit: {host}");
+        return Ok(addrs.into_iter().map(|ip| SocketAddr::new(ip, port)).collect());
+    }
+
+    // 名前解決の間はロックを持たない（遅い問い合わせで他の接続を待たせないように）
+    let resolved: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    if resolved.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::AddrNotAvailable,
+            format!("no addresses resolved for {host}"),
+        ));
+    }
+    insert(key, resolved.iter().map(|a| a.ip()).collect(), config);
+    Ok(resolved)
+}
+
+fn lookup(key: &str) -> Option<Vec<IpAddr>> {
+    let mut cache = lock();
+    cache.clock += 1;
+    let clock = cache.clock;
+    let entry = cache.entries.get_mut(key)?;
+    if entry.expires <= Instant::now() {
+        cache.entries.remove(key);
+        return None;
+    }
+    entry.last_used = clock;
+    Some(entry.addrs.clone())
+}
+
+fn insert(key: String, addrs: Vec<IpAddr>, config: &Config) {
+    let now = Instant::now();
+    let mut cache = lock();
+    if !cache.entries.contains_key(&key) && cache.entries.len() >= config.dns_cache_size {
+        // まず期限切れを捨て、それでも満杯なら最も長く使われていないものを捨てる
+        cache.entries.retain(|_, e| e.expires > now);
+        while cache.entries.len() >= config.dns_cache_size {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+            match oldest {
+                Some(k) => cache.entries.remove(&k),
+                None => break,
+            };
+        }
+    }
+    cache.clock += 1;
+    let last_used = cache.clock;
+    cache.entries.insert(
+        key,
+        Entry {
+            addrs,
+            expires: deadline_after(now, config.dns_cache_ttl),
+            last_used,
+        },
+    );
+}
@@ -0,0 +1,86 @@
+// 接続の処理（handle_connection）が失敗した理由
+// 内部では io::Error のまま扱い、プロトコル上の失敗は io::Error の中にこの値を入れて返す
+// （ErrorKind と表示は元のまま）。handle_connection の戻り値で取り出し、入っていなければ Io にする
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+#[derive(Debug)]
+pub enum SocksError {
+    // VER が対応していない値（メッセージには失敗した段階とバイト位置が入る）
+    UnsupportedVersion(String),
+    // クライアントが提示した中に、使える認証方法が無い（METHOD=0xFF を返した）
+    NoAcceptableMethods,
+    // 認証に失敗した（パスワードの誤り・利用できない時間帯など）
+    AuthFailed(String),
+    // 未対応の CMD（REP=0x07 を返した）
+    UnsupportedCommand(u8),
+    // 未知の ATYP（REP=0x08 を返した）
+    UnsupportedAtyp(u8),
+    // その他の不正なメッセージ（予約バイト・長さの誤り・不正なホスト名など）
+    MalformedRequest(String),
+    // 遮断規則・ユーザの ACL・転送量の上限・authorizer で宛先を拒否した
+    // （送信元の許可・拒否と、認証の失敗による締め出しで接続元を拒否した場合も）
+    DestinationDenied(String),
+    // 宛先（または上位プロキシ）へ接続できなかった
+    ConnectFailed(io::Error),
+    // 読み書きの失敗など、上のどれでもないもの
+    Io(io::Error),
+}
+
+impl SocksError {
+    // kind の io::Error に入れる（内部の処理はこれまでどおり io::Error として扱える）
+    pub(crate) fn into_io(self, kind: ErrorKind) -> io::Error {
+        io::Error::new(kind, self)
+    }
+
+    // 宛先への接続の失敗を分類する（接続の直前の遮断規則・ACL による拒否は DestinationDenied）
+    pub(crate) fn connect_failed(e: io::Error) -> io::Error {
+        let kind = e.kind();
+        match kind {
+            ErrorKind::PermissionDenied => SocksError::DestinationDenied(e.to_string()),
+            _ => SocksError::ConnectFailed(e),
+        }
+        .into_io(kind)
+    }
+}
+
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocksError::UnsupportedVersion(msg)
+            | SocksError::AuthFailed(msg)
+            | SocksError::MalformedRequest(msg)
+            | SocksError::DestinationDenied(msg) => f.write_str(msg),
+            SocksError::NoAcceptableMethods => f.write_str("no acceptable method"),
+            SocksError::UnsupportedCommand(cmd) => write!(f, "unsupported command: 0x{cmd:02X}"),
+            SocksError::UnsupportedAtyp(atyp) => {
+                write!(f, "malformed at request header byte 3: ATYP=0x{atyp:02X}")
+            }
+            SocksError::ConnectFailed(e) | SocksError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SocksError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SocksError::ConnectFailed(e) | SocksError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SocksError {
+    fn from(e: io::Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<SocksError>()) {
+            return SocksError::Io(e);
+        }
+        let kind = e.kind();
+        match e.into_inner().map(|inner| inner.downcast::<SocksError>()) {
+            Some(Ok(inner)) => *inner,
+            // 上で確かめたため来ない
+            _ => SocksError::Io(kind.into()),
+        }
+    }
+}
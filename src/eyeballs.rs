@@ -0,0 +1,201 @@
+// 複数アドレスへの並行接続（RFC 8305 Happy Eyeballs）
+// 片方のアドレスファミリーが壊れている環境でも、接続タイムアウトまで待たされないようにする
+// 試行の数・並行数・全体の期限は ConnectStrategy にまとめる
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{deadline_after, log, sockopt};
+
+// 宛先への接続の進め方（試すアドレスの数・並行数・1 回の試行のタイムアウト・全体の期限）
+// 宛先が IPv4・IPv6・ドメイン名のどれでも、上位プロキシへの接続でも、この設定で接続する
+// 制限がぶつかるときは deadline が最優先で、過ぎたら残りの試行は始めず、進行中の試行も待たない
+// 次に max_attempts が試すアドレスを減らし、parallelism が同時に進める試行を減らす
+// 各試行のタイムアウトは timeout と deadline までの残り時間の短い方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectStrategy {
+    // 1 つのアドレスへの接続のタイムアウト（応答しない宛先でスレッドが滞留しないように）
+    pub timeout: Duration,
+    // 前の試行が終わらないまま次のアドレスへの接続を始めるまでの待ち時間（Happy Eyeballs）
+    pub delay: Duration,
+    // 試すアドレスの数の上限（0 なら名前解決で得られた全て）
+    pub max_attempts: usize,
+    // 同時に進める試行の数の上限（0 なら無制限。1 なら 1 つずつ順に試す）
+    pub parallelism: usize,
+    // 接続全体の期限（None なら無し。各試行の timeout だけが効く）
+    pub deadline: Option<Duration>,
+}
+
+impl Default for ConnectStrategy {
+    fn default() -> Self {
+        ConnectStrategy {
+            timeout: Duration::from_secs(10),
+            delay: Duration::from_millis(250),
+            max_attempts: 0,
+            parallelism: 0,
+            deadline: None,
+        }
+    }
+}
+
+impl ConnectStrategy {
+    // addrs への接続を少しずつずらして並行に試し、最初に成功した接続を返す
+    // 前の試行が delay 以内に終わらなければ（または失敗したらすぐに）次のアドレスを試し始める
+    // 遅れて成功した接続は、受け取る側がいないのでそのまま閉じられる
+    // source を指定すると、各接続をその送信元アドレスから行う
+    // すべて失敗した場合は最後のエラーを、deadline が過ぎた場合は ErrorKind::TimedOut を返す
+    pub(crate) fn connect(
+        &self,
+        addrs: Vec<SocketAddr>,
+        source: Option<IpAddr>,
+    ) -> io::Result<TcpStream> {
+        let mut addrs = interleave(addrs);
+        if self.max_attempts > 0 {
+            addrs.truncate(self.max_attempts);
+        }
+        let deadline = self.deadline.map(|limit| (limit, deadline_after(Instant::now(), limit)));
+        let (tx, rx) = mpsc::channel();
+        let conn_id = log::conn_id();
+        let mut started = 0;
+        let mut pending = 0;
+        let mut last_err = None;
+        loop {
+            // 期限までの残り時間（None なら期限なし）
+            let remaining = match deadline {
+                Some((limit, at)) => match at.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => {
+                        let total = addrs.len();
+                        info!("connect deadline exceeded after {started} of {total} attempts");
+                        return Err(io::Error::new(
+                            ErrorKind::TimedOut,
+                            format!("connect deadline of {limit:?} exceeded"),
+                        ));
+                    }
+                },
+                None => None,
+            };
+            let has_room = self.parallelism == 0 || pending < self.parallelism;
+            if has_room && let Some(&addr) = addrs.get(started) {
+                let timeout = remaining.map_or(self.timeout, |left| left.min(self.timeout));
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    log::set_conn_id(conn_id);
+                    let _ = tx.send((addr, sockopt::connect_timeout(&addr, source, timeout)));
+                });
+                started += 1;
+                pending += 1;
+            }
+            if pending == 0 {
+                break;
+            }
+            // 次の試行を始められるなら delay だけ、そうでなければ進行中の試行の結果を待つ
+            let can_start = started < addrs.len()
+                && (self.parallelism == 0 || pending < self.parallelism);
+            let wait = match (can_start.then_some(self.delay), remaining) {
+                (Some(delay), Some(left)) => Some(delay.min(left)),
+                (wait, None) => wait,
+                (None, left) => left,
+            };
+            let result = match wait {
+                Some(wait) => rx.recv_timeout(wait),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match result {
+                Ok((_, Ok(stream))) => return Ok(stream),
+                Ok((addr, Err(e))) => {
+                    info!("connect to {addr} failed: {e}");
+                    last_err = Some(e);
+                    pending -= 1;
+                }
+                // 次のアドレスを試し始めるか、期限を確かめる
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(ErrorKind::AddrNotAvailable, "no addresses resolved")
+        }))
+    }
+}
+
+// 先頭のアドレスのファミリーから始めて、IPv6 と IPv4 を交互に並べる（同じファミリー内の順序は保つ）
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let prefer_v6 = addrs.first().is_some_and(|a| a.is_ipv6());
+    let (first, second): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == prefer_v6);
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // 接続しても完了しない宛先と、それを満杯に保つ接続を返す
+    // 完了待ちの列（Linux の既定の backlog は 128）が満杯の待ち受けへの SYN は捨てられる
+    fn unresponsive() -> (TcpListener, Vec<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut queued = Vec::new();
+        while let Ok(stream) = TcpStream::connect_timeout(&addr, Duration::from_millis(200)) {
+            queued.push(stream);
+        }
+        (listener, queued)
+    }
+
+    #[test]
+    fn deadline_cuts_off_the_remaining_attempts() {
+        let (listener, _queued) = unresponsive();
+        let addr = listener.local_addr().unwrap();
+        let strategy = ConnectStrategy {
+            timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            parallelism: 1,
+            deadline: Some(Duration::from_millis(500)),
+            ..ConnectStrategy::default()
+        };
+        // deadline が無ければ 3 つの試行を順に 5 秒ずつ待つ
+        let started = Instant::now();
+        let err = strategy.connect(vec![addr; 3], None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().contains("deadline"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn deadline_caps_the_timeout_of_a_single_attempt() {
+        let (listener, _queued) = unresponsive();
+        let strategy = ConnectStrategy {
+            timeout: Duration::from_secs(5),
+            deadline: Some(Duration::from_millis(300)),
+            ..ConnectStrategy::default()
+        };
+        let started = Instant::now();
+        let err = strategy.connect(vec![listener.local_addr().unwrap()], None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn max_attempts_skips_the_remaining_addresses() {
+        // 閉じた待ち受けのポートへの接続はすぐに拒否される
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![refused, listener.local_addr().unwrap()];
+        let strategy = ConnectStrategy { max_attempts: 1, ..ConnectStrategy::default() };
+        let err = strategy.connect(addrs.clone(), None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(ConnectStrategy::default().connect(addrs, None).is_ok());
+    }
+}
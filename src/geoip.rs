@@ -0,0 +1,126 @@
+// 宛先の IP アドレスの国と AS 番号（--geoip-db）
+// 起動時にファイル全体をメモリに読み込み、接続した宛先ごとに二分探索で引く（ファイルは読み直さない）
+// 形式は 1 行 1 ネットワークの CSV: network,country,asn（GeoLite2 の CSV を 1 つにまとめた形）
+//   network は CIDR 表記、country は英数字、asn は空でもよく、AS を付けてもよい
+//   4 列目以降（組織名など）は無視する
+//   空行・# で始まる行と、先頭の network で始まる見出しの行は読み飛ばす
+// ネットワーク同士が重なっているファイルは拒否する（どちらを使うかが決まらないため）
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::IpAddr;
+use std::sync::{PoisonError, RwLock};
+
+use crate::Cidr;
+
+// 1 つのネットワークの情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Geo {
+    // ISO 3166-1 の国コード（例: JP）
+    pub(crate) country: String,
+    pub(crate) asn: Option<u32>,
+}
+
+// アドレスの範囲（IPv4 は IPv4 射影 IPv6 アドレスとして数える）の始まりで並べた一覧
+static DB: RwLock<Vec<(u128, u128, Geo)>> = RwLock::new(Vec::new());
+
+// path のデータベースを読み込み、これまでのものと置き換える。読み込んだネットワークの数を返す
+pub(crate) fn load(path: &str) -> io::Result<usize> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {path}: {e}")))?;
+    let ranges = parse(&text)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{path}: {e}")))?;
+    let count = ranges.len();
+    *DB.write().unwrap_or_else(PoisonError::into_inner) = ranges;
+    Ok(count)
+}
+
+// addr を含むネットワークの情報（読み込んでいないか、どれにも含まれなければ None）
+pub(crate) fn lookup(addr: IpAddr) -> Option<Geo> {
+    let ip = to_u128(addr);
+    let db = DB.read().unwrap_or_else(PoisonError::into_inner);
+    let i = db.partition_point(|(start, _, _)| *start <= ip);
+    let (_, end, geo) = db.get(i.checked_sub(1)?)?;
+    (ip <= *end).then(|| geo.clone())
+}
+
+fn to_u128(addr: IpAddr) -> u128 {
+    match addr.to_canonical() {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<(u128, u128, Geo)>, String> {
+    let mut ranges = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("network")) {
+            continue;
+        }
+        let bad = |msg: String| format!("line {}: {msg}", i + 1);
+        let mut fields = line.split(',').map(str::trim);
+        let network: Cidr = fields.next().unwrap_or_default().parse().map_err(bad)?;
+        let country = match fields.next() {
+            Some(c) if !c.is_empty() && c.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+                c.to_string()
+            }
+            Some("") | None => return Err(bad("missing country".to_string())),
+            Some(c) => return Err(bad(format!("invalid country {c:?}"))),
+        };
+        let asn = match fields.next().map(|a| a.trim_start_matches("AS")) {
+            None | Some("") => None,
+            Some(a) => Some(a.parse().map_err(|_| bad(format!("invalid ASN {a:?}")))?),
+        };
+        let (start, end) = network.range();
+        ranges.push((start, end, Geo { country, asn }, i + 1));
+    }
+    ranges.sort_by_key(|&(start, ..)| start);
+    for pair in ranges.windows(2) {
+        if pair[1].0 <= pair[0].1 {
+            return Err(format!("line {}: overlaps line {}", pair[1].3, pair[0].3));
+        }
+    }
+    Ok(ranges.into_iter().map(|(start, end, geo, _)| (start, end, geo)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geo(country: &str, asn: Option<u32>) -> Geo {
+        Geo { country: country.to_string(), asn }
+    }
+
+    #[test]
+    fn parses_networks_and_finds_the_containing_one() {
+        let ranges = parse(
+            "network,country,asn,organization\n\
+             # comment\n\
+             203.0.113.0/24,JP,AS64500,Example\n\
+             \n\
+             2001:db8::/32,US,\n\
+             198.51.100.7,DE,64501\n",
+        )
+        .unwrap();
+        assert_eq!(ranges.len(), 3);
+        let find = |addr: &str| {
+            let ip = to_u128(addr.parse().unwrap());
+            ranges.iter().find(|(s, e, _)| *s <= ip && ip <= *e).map(|(.., g)| g.clone())
+        };
+        assert_eq!(find("203.0.113.200"), Some(geo("JP", Some(64500))));
+        assert_eq!(find("::ffff:203.0.113.1"), Some(geo("JP", Some(64500))));
+        assert_eq!(find("2001:db8:1::1"), Some(geo("US", None)));
+        assert_eq!(find("198.51.100.7"), Some(geo("DE", Some(64501))));
+        assert_eq!(find("198.51.100.8"), None);
+    }
+
+    #[test]
+    fn rejects_bad_lines_and_overlaps() {
+        assert!(parse("not-an-ip,JP,1\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse("10.0.0.0/8\n").unwrap_err().contains("missing country"));
+        assert!(parse("10.0.0.0/8,JP,ASX\n").unwrap_err().contains("invalid ASN"));
+        assert!(parse("10.0.0.0/8,\"JP\"\n").unwrap_err().contains("invalid country"));
+        let err = parse("10.0.0.0/8,JP,1\n10.1.0.0/16,US,2\n").unwrap_err();
+        assert_eq!(err, "line 2: overlaps line 1");
+    }
+}
@@ -0,0 +1,133 @@
+// GSSAPI 認証（RFC 1961）のサブネゴシエーション（feature "gssapi" で有効）
+// GSS-API の実装（Kerberos など）は持たず、GssapiAcceptor を通して外から差し込む
+//
+// 対応している範囲:
+// - コンテキストの確立（MTYP=0x01 のトークンの往復）。失敗時は MTYP=0xFF を返して閉じる
+// - 保護レベルの交渉（MTYP=0x02）。トークンは GssapiContext::unwrap / wrap で復号・封入する
+//   選べるのは保護なし（0x00。Dante・curl などが使う値）だけで、完全性（0x01）・機密性（0x02）・
+//   メッセージごとの選択（0x03）を求められた場合は MTYP=0xFF を返して閉じる
+// - 交渉の後の Request・Reply・転送データは封入しない（MTYP=0x03 のカプセル化は未対応）
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+// GSSAPI のサブネゴシエーションのバージョンとメッセージの種類
+const VERSION: u8 = 0x01;
+const MTYP_AUTH: u8 = 0x01;
+const MTYP_PROTECTION: u8 = 0x02;
+const MTYP_ABORT: u8 = 0xFF;
+// 対応している保護レベル（保護なし）
+const PROTECTION_NONE: u8 = 0x00;
+
+// 接続ごとのセキュリティコンテキストを作る（プロセスで 1 つを Config に持たせて共有する）
+pub trait GssapiAcceptor: fmt::Debug + Send + Sync {
+    fn new_context(&self) -> Box<dyn GssapiContext>;
+}
+
+// 1 本の接続のセキュリティコンテキスト（gss_accept_sec_context などに当たる操作）
+pub trait GssapiContext: Send {
+    // クライアントのトークンを渡し、返すトークンと、確立したかどうかを受け取る
+    fn accept(&mut self, token: &[u8]) -> io::Result<GssapiStep>;
+    // 保護レベルの交渉のメッセージの封入と復号（gss_wrap / gss_unwrap）
+    fn wrap(&mut self, data: &[u8]) -> io::Result<Vec<u8>>;
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>>;
+    // 確立したコンテキストの利用者の名前（ログ・ユーザごとの制限に使う）
+    fn principal(&self) -> String;
+}
+
+// GssapiContext::accept の結果
+pub enum GssapiStep {
+    // まだ続く（このトークンを返し、クライアントの次のトークンを待つ）
+    Continue(Vec<u8>),
+    // 確立した（トークンが空でなければ、最後にそれを返す）
+    Complete(Vec<u8>),
+}
+
+// 方法 0x01 を選んだ後のサブネゴシエーション。成功時は利用者の名前を返す
+pub(crate) fn negotiate<S: Read + Write>(
+    stream: &mut S,
+    acceptor: &dyn GssapiAcceptor,
+) -> io::Result<String> {
+    let mut context = acceptor.new_context();
+    let res = establish(stream, context.as_mut());
+    if res.is_err() {
+        let _ = stream.write_all(&[VERSION, MTYP_ABORT]);
+        let _ = stream.flush();
+    }
+    res.map(|()| context.principal())
+}
+
+fn establish<S: Read + Write>(stream: &mut S, context: &mut dyn GssapiContext) -> io::Result<()> {
+    // 1) コンテキストの確立
+    loop {
+        let token = read_message(stream, MTYP_AUTH)?;
+        match context.accept(&token)? {
+            GssapiStep::Continue(reply) => write_message(stream, MTYP_AUTH, &reply)?,
+            GssapiStep::Complete(reply) => {
+                if !reply.is_empty() {
+                    write_message(stream, MTYP_AUTH, &reply)?;
+                }
+                break;
+            }
+        }
+    }
+
+    // 2) 保護レベルの交渉（1 バイトの保護レベルを封入したトークン）
+    let token = read_message(stream, MTYP_PROTECTION)?;
+    let level = context.unwrap(&token)?;
+    match level.as_slice() {
+        [PROTECTION_NONE] => {}
+        [level] => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("GSSAPI protection level 0x{level:02X} is not supported"),
+            ));
+        }
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "GSSAPI protection level must be a single byte",
+            ));
+        }
+    }
+    let reply = context.wrap(&[PROTECTION_NONE])?;
+    write_message(stream, MTYP_PROTECTION, &reply)
+}
+
+// [VER, MTYP, LEN(2), TOKEN] を読み、TOKEN を返す（クライアントの中断 MTYP=0xFF はエラー）
+fn read_message<R: Read>(stream: &mut R, mtyp: u8) -> io::Result<Vec<u8>> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    if header[0] != VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("malformed at GSSAPI message byte 0: VER=0x{:02X}", header[0]),
+        ));
+    }
+    if header[1] == MTYP_ABORT {
+        return Err(io::Error::new(
+            ErrorKind::ConnectionAborted,
+            "client aborted GSSAPI negotiation",
+        ));
+    }
+    if header[1] != mtyp {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("malformed at GSSAPI message byte 1: MTYP=0x{:02X}", header[1]),
+        ));
+    }
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut token = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut token)?;
+    Ok(token)
+}
+
+fn write_message<W: Write>(stream: &mut W, mtyp: u8, token: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(token.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "GSSAPI token too long"))?;
+    let mut message = vec![VERSION, mtyp];
+    message.extend_from_slice(&len.to_be_bytes());
+    message.extend_from_slice(token);
+    stream.write_all(&message)?;
+    stream.flush()
+}
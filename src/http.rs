@@ -0,0 +1,168 @@
+// HTTP CONNECT のトンネル（http_connect が有効で、先頭バイトが ASCII の英大文字の接続）
+// SOCKS と同じポートで受け付け、宛先の判定・接続・転送は SOCKS の処理をそのまま使う
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr};
+
+use crate::authorize::authorize;
+use crate::error::SocksError;
+use crate::inspect::{blocked_reason, is_valid_dns_name};
+use crate::protocol::Dst;
+use crate::relay::splice;
+use crate::rewrite::rewrite;
+use crate::session::Session;
+use crate::stream::ClientStream;
+use crate::{
+    Config, DenyMode, close_denied, idna, log_connected, metrics, no_auth_allowed, open_remote,
+    proxyproto,
+};
+
+// リクエスト行とヘッダの合計の上限
+const MAX_HEADER_LEN: usize = 8192;
+
+// 先頭バイトが HTTP のメソッドの始まりに見えるか（SOCKS の VER は 0x04・0x05）
+pub(crate) fn looks_like_http(first: u8) -> bool {
+    first.is_ascii_uppercase()
+}
+
+// CONNECT host:port HTTP/1.x を処理する（ヘッダは読み飛ばす）
+// 成功なら 200 を返してから転送し、失敗は HTTP のステータスで返して閉じる
+pub(crate) fn handle_http_connect<S: ClientStream>(
+    client: &mut S,
+    config: &Config,
+    session: &mut Session,
+) -> io::Result<()> {
+    let header = read_header(client)?;
+    let request_line = header.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = (parts.next(), parts.next(), parts.next());
+    if method != Some("CONNECT") {
+        respond(client, "405 Method Not Allowed")?;
+        return Err(SocksError::MalformedRequest(format!(
+            "unsupported HTTP request: {request_line:?}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    if !version.is_some_and(|v| v.starts_with("HTTP/1.")) || parts.next().is_some() {
+        respond(client, "400 Bad Request")?;
+        return Err(SocksError::MalformedRequest(format!(
+            "malformed HTTP CONNECT request: {request_line:?}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+
+    // HTTP CONNECT では認証を扱わないため、SOCKS5 でも No Auth を選べないときは受け付けない
+    if !no_auth_allowed(config) {
+        warn!("rejected: HTTP CONNECT request while No Auth is not selectable");
+        respond(client, "403 Forbidden")?;
+        return Err(SocksError::AuthFailed(
+            "authentication required (HTTP CONNECT has no authentication)".to_string(),
+        )
+        .into_io(ErrorKind::PermissionDenied));
+    }
+
+    let mut dst = match parse_target(target.unwrap_or_default()) {
+        Ok(dst) => dst,
+        Err(e) => {
+            warn!("invalid HTTP CONNECT target rejected: {e}");
+            respond(client, "400 Bad Request")?;
+            return Err(e);
+        }
+    };
+    session.dst = Some(dst.to_string());
+
+    // 書き換え後のホスト名も validate_hostnames で判定する
+    rewrite(config, &mut dst);
+    if let Dst::Domain(host, _) = &dst
+        && config.validate_hostnames
+        && !is_valid_dns_name(host)
+    {
+        warn!("invalid hostname rejected: {host:?}");
+        respond(client, "400 Bad Request")?;
+        return Err(SocksError::MalformedRequest(format!("invalid hostname: {host:?}"))
+            .into_io(ErrorKind::InvalidData));
+    }
+
+    // 組み込み側の許可判定で拒否されたら 403 を返す
+    if authorize(config, session, 0x01, &mut dst).is_some() {
+        respond(client, "403 Forbidden")?;
+        return Err(SocksError::DestinationDenied(format!(
+            "destination {dst} denied by authorizer"
+        ))
+        .into_io(ErrorKind::PermissionDenied));
+    }
+
+    // SOCKS5 と同じ遮断ルールを適用する
+    if let Some(reason) = blocked_reason(&dst, config) {
+        info!("blocked by ruleset: {dst} ({reason})");
+        if config.deny_mode == DenyMode::Reply {
+            respond(client, "403 Forbidden")?;
+        } else {
+            close_denied(client, config);
+        }
+        return Err(SocksError::DestinationDenied(format!("blocked destination: {dst}"))
+            .into_io(ErrorKind::PermissionDenied));
+    }
+
+    info!("HTTP CONNECT request, destination: {dst}");
+    let (remote, _) = match open_remote(&dst, config, None) {
+        Ok(v) => v,
+        Err(e) => {
+            metrics::connect_failed();
+            let status = match e.kind() {
+                ErrorKind::PermissionDenied => "403 Forbidden",
+                ErrorKind::TimedOut => "504 Gateway Timeout",
+                _ => "502 Bad Gateway",
+            };
+            let _ = respond(client, status);
+            return Err(SocksError::connect_failed(e));
+        }
+    };
+
+    log_connected(&remote, &dst, config, session);
+    if config.send_proxy_protocol {
+        proxyproto::send_v1(&remote, client, session)?;
+    }
+    client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    client.flush()?;
+
+    splice(client, remote, "", config, session)
+}
+
+// 空行（CRLF CRLF）までを読む
+// トンネルのデータを読み込みすぎないよう、1 バイトずつ読む
+fn read_header<R: Read>(client: &mut R) -> io::Result<String> {
+    let mut header = Vec::new();
+    let mut b = [0u8; 1];
+    while !header.ends_with(b"\r\n\r\n") && !header.ends_with(b"\n\n") {
+        if header.len() == MAX_HEADER_LEN {
+            return Err(io::Error::new(ErrorKind::InvalidData, "HTTP request header too long"));
+        }
+        client.read_exact(&mut b)?;
+        header.push(b[0]);
+    }
+    String::from_utf8(header)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "HTTP request header is not UTF-8"))
+}
+
+// CONNECT の宛先（"host:port" または "[IPv6]:port"）
+// ホスト名は国際化ドメイン名を ASCII 形式（xn--）にする
+fn parse_target(target: &str) -> io::Result<Dst> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, format!("bad target {target:?}"));
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().ok().filter(|&p| p > 0).ok_or_else(invalid)?;
+    if let Some(v6) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        let ip: IpAddr = v6.parse().map_err(|_| invalid())?;
+        return Ok(Dst::from(SocketAddr::new(ip, port)));
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip @ IpAddr::V4(_)) => Ok(Dst::from(SocketAddr::new(ip, port))),
+        Ok(IpAddr::V6(_)) => Err(invalid()),
+        Err(_) => Ok(Dst::Domain(idna::to_ascii(host)?, port)),
+    }
+}
+
+fn respond<S: ClientStream>(client: &mut S, status: &str) -> io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    client.write_all(response.as_bytes())?;
+    client.flush()
+}
@@ -0,0 +1,130 @@
+// 国際化ドメイン名（IDN）の ASCII 形式への変換（IDNA の ToASCII と RFC 3492 の Punycode）
+// Unicode の正規化（NFC）や UTS #46 の対応表は持たないため、ラベルの小文字化と
+// 全角ドットなどの区切り文字の置き換えだけを行ってから Punycode にする
+use std::io::{self, ErrorKind};
+
+// ASCII 形式で許すホスト名の最大長（末尾のドットを除く）
+const MAX_NAME_LEN: usize = 253;
+
+// ホスト名を名前解決に使える ASCII 形式にする（非 ASCII のラベルは "xn--" + Punycode）
+// 空のもの、NUL や U+FFFD（不正な UTF-8 を置き換えた文字）を含むもの、長すぎるものはエラーにする
+pub(crate) fn to_ascii(host: &str) -> io::Result<String> {
+    let invalid = |msg: String| io::Error::new(ErrorKind::InvalidData, msg);
+    if host.is_empty() {
+        return Err(invalid("empty hostname".to_string()));
+    }
+    if host.contains('\0') {
+        return Err(invalid(format!("hostname contains NUL: {host:?}")));
+    }
+    if host.contains('\u{FFFD}') {
+        return Err(invalid(format!("hostname is not valid UTF-8: {host:?}")));
+    }
+    if host.is_ascii() {
+        return check_len(host.to_string());
+    }
+
+    // 「。」「．」「｡」もラベルの区切りとして扱う
+    let host: String = host
+        .chars()
+        .map(|c| match c {
+            '\u{3002}' | '\u{FF0E}' | '\u{FF61}' => '.',
+            c => c,
+        })
+        .collect();
+    let labels: Vec<String> = host
+        .split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                return Some(label.to_string());
+            }
+            punycode(&label.to_lowercase()).map(|p| format!("xn--{p}"))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid(format!("hostname cannot be encoded: {host:?}")))?;
+    check_len(labels.join("."))
+}
+
+fn check_len(name: String) -> io::Result<String> {
+    let len = name.strip_suffix('.').unwrap_or(&name).len();
+    if len > MAX_NAME_LEN {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("hostname too long: {len} bytes"),
+        ));
+    }
+    Ok(name)
+}
+
+// RFC 3492 のパラメータ
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+// Punycode の符号化（RFC 3492 6.3）。途中で桁あふれした場合は None
+fn punycode(input: &str) -> Option<String> {
+    let chars: Vec<u32> = input.chars().map(u32::from).collect();
+    let mut output: String = input.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut handled = basic;
+    while (handled as usize) < chars.len() {
+        // まだ符号化していない最小のコードポイントまで進める
+        let m = chars.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &chars {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                // delta を可変長の整数として書き出す
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = k.saturating_sub(bias).clamp(TMIN, TMAX);
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n = n.checked_add(1)?;
+    }
+    Some(output)
+}
+
+// バイアスの調整（RFC 3492 6.1）
+fn adapt(delta: u32, num_points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+// 0〜35 を a〜z, 0〜9 の 1 文字にする
+fn digit(d: u32) -> char {
+    match d {
+        0..=25 => char::from(b'a' + d as u8),
+        _ => char::from(b'0' + (d - 26) as u8),
+    }
+}
@@ -0,0 +1,87 @@
+// 宛先の簡単なインスペクション（宛先の遮断とホスト名の検証）
+use std::net::IpAddr;
+
+use crate::Config;
+use crate::protocol::Dst;
+
+// 宛先が遮断対象なら、その理由を返す（ポート・アドレス範囲・ドメイン名の順に判定）
+// 許可ポートの一覧が空でなければ、一覧に無いポートも遮断する
+pub(crate) fn blocked_reason(dst: &Dst, config: &Config) -> Option<String> {
+    let port = dst.port();
+    if !config.allowed_ports.is_empty() && !config.allowed_ports.contains(&port) {
+        return Some(format!("port {port} not in allowed ports"));
+    }
+    if config.blocked_ports.contains(&port) {
+        return Some(format!("port {port}"));
+    }
+    match dst {
+        Dst::Domain(host, _) => is_blocked(host, &config.blocked_suffixes)
+            .then(|| format!("domain {host}")),
+        _ => {
+            let ip = dst.ip()?;
+            config
+                .blocked_cidrs
+                .iter()
+                .find(|c| c.contains(ip))
+                .map(|c| format!("address {ip} in {c}"))
+        }
+    }
+}
+
+// 実際に接続するアドレス（ドメイン名なら名前解決した結果）が遮断対象なら、その理由を返す
+// blocked_reason はリテラルの IP アドレスしか判定できないため、名前解決の後にこちらで判定し直す
+pub(crate) fn blocked_address(ip: IpAddr, config: &Config) -> Option<String> {
+    if config.block_private && is_private_address(ip) {
+        return Some("private address".to_string());
+    }
+    config
+        .blocked_cidrs
+        .iter()
+        .find(|c| c.contains(ip))
+        .map(|c| format!("address {ip} in {c}"))
+}
+
+// 内部向けのアドレスか（プロキシ経由で到達させたくない範囲）
+// IPv4: 0.0.0.0/8, 127.0.0.0/8, 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16
+// IPv6: ::, ::1, fc00::/7 (ULA), fe80::/10 (リンクローカル)。IPv4 射影アドレスは IPv4 として判定
+fn is_private_address(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            v4.octets()[0] == 0 || v4.is_loopback() || v4.is_private() || v4.is_link_local()
+        }
+        IpAddr::V6(v6) => {
+            v6.is_unspecified()
+                || v6.is_loopback()
+                || v6.is_unique_local()
+                || v6.is_unicast_link_local()
+        }
+    }
+}
+
+// 遮断対象か判定する。
+// 完全一致またはサフィックス一致（サブドメイン含む）で判定します。
+fn is_blocked(host: &str, blocked_suffixes: &[String]) -> bool {
+    let h = host.to_ascii_lowercase();
+    blocked_suffixes.iter().any(|suf| {
+        let s = suf.to_ascii_lowercase();
+        h == s || h.ends_with(&format!(".{s}"))
+    })
+}
+
+// DNS 名として妥当かを判定する（RFC 1123 のホスト名規則）
+// - 全体で 253 バイト以下（末尾のドット 1 つは許可）
+// - 各ラベルは 1〜63 バイトで、英数字とハイフンのみ
+// - ラベルの先頭・末尾はハイフン不可
+pub(crate) fn is_valid_dns_name(host: &str) -> bool {
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    })
+}
@@ -0,0 +1,1569 @@
+// SOCKS5 プロキシの共通実装
+// basic / intermediate / advanced の各バイナリは Config で機能を選び、Socks5Server を起動するだけ
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV6, TcpListener, TcpStream,
+    ToSocketAddrs,
+};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[macro_use]
+mod log;
+
+mod account;
+mod auth;
+mod authorize;
+mod authban;
+mod bcrypt;
+mod bind;
+mod cidr;
+mod cli;
+mod config;
+mod dns;
+mod error;
+mod eyeballs;
+mod geoip;
+#[cfg(feature = "gssapi")]
+mod gssapi;
+mod http;
+mod idna;
+mod inspect;
+mod metrics;
+mod pool;
+mod protocol;
+mod proxyproto;
+mod quota;
+mod ratelimit;
+mod relay;
+mod rewrite;
+mod session;
+mod signal;
+mod slowlog;
+mod sockopt;
+mod socks4;
+mod stream;
+mod udp;
+mod upstream;
+
+pub use account::{Account, Acl, Quota, QuotaPeriod, TimeWindow};
+pub use authorize::{AcceptFilter, Authorizer, ClientInfo, Decision, Destination};
+pub use cidr::Cidr;
+pub use cli::Parsed;
+pub use error::SocksError;
+pub use eyeballs::ConnectStrategy;
+pub use log::LogFormat;
+pub use rewrite::Rewrite;
+#[cfg(feature = "gssapi")]
+pub use gssapi::{GssapiAcceptor, GssapiContext, GssapiStep};
+pub use stream::ClientStream;
+
+use auth::{perform_userpass_auth_inline, user_account};
+use inspect::{blocked_address, blocked_reason, is_valid_dns_name};
+use pool::ThreadPool;
+use ratelimit::ConnRateLimiter;
+use session::{Session, next_conn_id};
+use stream::peer_label;
+use protocol::{
+    Dst, build_error_reply, build_success_reply, parse_greeting, parse_request_header, read_dst,
+    reply_code_for,
+};
+
+// サーバの設定
+#[derive(Debug, Clone)]
+pub struct Config {
+    // 待ち受けアドレス（複数指定可。IPv4 と IPv6 の両方で待ち受ける場合など）
+    // "unix:" で始まる指定は Unix ドメインソケットのパス
+    pub listen: Vec<String>,
+    // TCP の待ち受けソケットに SO_REUSEPORT を設定するか（Linux のみ）
+    // 同じアドレスで待ち受ける複数のインスタンスに、カーネルが新しい接続を振り分ける
+    pub reuse_port: bool,
+    // RFC1929（ユーザ/パスワード認証）を有効にするか
+    pub auth: bool,
+    // 認証を必須にするか（No Auth と SOCKS4 を受け付けない）
+    pub require_auth: bool,
+    // テナントタグモード: パスワードを検証せず、ユーザ名を集計・ログ用のテナントタグとして受け入れる
+    // 認証ではないため、認証情報ファイル・require_auth とは組み合わせられない
+    pub tenant_tags: bool,
+    // ユーザ/パスワード認証のサブネゴシエーションの読み込みタイムアウト（0 なら無制限）
+    pub auth_timeout: Duration,
+    // 接続してから転送を始めるまで（Greeting・Request）の読み書きのタイムアウト（0 なら無制限）
+    pub handshake_timeout: Duration,
+    // 認証失敗による締め出し: auth_fail_window の間に auth_fail_limit 回（0 なら無効）失敗した IP を
+    // auth_ban_duration の間拒否する
+    pub auth_fail_limit: u32,
+    pub auth_fail_window: Duration,
+    pub auth_ban_duration: Duration,
+    // 認証に使うユーザ名とパスワードの組（--auth-file で読み込む）。None なら環境変数の 1 組
+    // パスワードは bcrypt ハッシュ（$2b$...）で保存する。平文は移行用に allow_plaintext_passwords で許可
+    // SIGHUP で読み直して置き換えるため、全ての接続で RwLock 越しに共有する
+    pub credentials: Option<Arc<RwLock<HashMap<String, Account>>>>,
+    // 認証情報ファイルのパス（SIGHUP での読み直しに使う）
+    pub auth_file: Option<String>,
+    pub allow_plaintext_passwords: bool,
+    // ユーザごとの転送量の記録を保存する JSON ファイル（None なら保存せず、再起動で 0 に戻る）
+    // 起動時に読み込み、quota_save_interval ごと（0 なら終了時だけ）と終了時に書き出す
+    pub quota_file: Option<String>,
+    pub quota_save_interval: Duration,
+    // 認証情報ファイルの利用できる時間帯（hours=）を判定する現地時刻の、UTC からの時差（分）
+    pub utc_offset: i32,
+    // 遮断対象のドメイン（完全一致またはサフィックス一致）。空なら遮断しない
+    pub blocked_suffixes: Vec<String>,
+    // 遮断対象の宛先アドレス範囲と宛先ポート（内部サービスへの到達を防ぐ）
+    pub blocked_cidrs: Vec<Cidr>,
+    pub blocked_ports: Vec<u16>,
+    // 接続を許可する宛先ポート（空なら全て許可）。一覧に無いポートは REP=0x02 で拒否する
+    pub allowed_ports: Vec<u16>,
+    // 名前解決後の宛先がプライベート・ループバック・リンクローカル・ULA なら拒否する（SSRF 対策）
+    pub block_private: bool,
+    // 接続を許可・拒否するクライアントのアドレス範囲（拒否が優先。許可が空なら全て許可）
+    pub client_allow: Vec<Cidr>,
+    pub client_deny: Vec<Cidr>,
+    // 名前解決の結果をキャッシュする時間と最大件数（どちらかが 0 ならキャッシュしない）
+    pub dns_cache_ttl: Duration,
+    pub dns_cache_size: usize,
+    // 宛先（と上位プロキシ）への TCP 接続の進め方（タイムアウト・Happy Eyeballs・試行数・全体の期限）
+    pub connect: ConnectStrategy,
+    // BIND で相手からの接続を待つ上限時間（誰も接続してこない場合に待ち受けを解放する）
+    pub bind_timeout: Duration,
+    // BIND の DST.PORT が 0 でなければ、そのポートで待ち受けるか（既定は無効で、常に空きポート）
+    // 特権ポート（1024 未満）は拒否する。DST.ADDR は接続してくる相手の予定のまま
+    pub bind_port_hint: bool,
+    // 宛先（と上位プロキシ）への接続の送信元アドレス（None なら OS が選ぶ）
+    pub bind_source: Option<IpAddr>,
+    // ゾーンの無いリンクローカル IPv6 の宛先（fe80::/10）に使うゾーン（インターフェース名か番号）
+    // None ならそのような宛先は Host unreachable で拒否する
+    pub ipv6_zone: Option<String>,
+    // 転送中のソケットごとの無通信タイムアウト（None なら無制限。既定は無制限）
+    // 片方向だけが長く止まる正常な通信も打ち切るため、半死の接続対策には tunnel_idle_timeout を勧める
+    pub idle_timeout: Option<Duration>,
+    // トンネル全体の無通信タイムアウト（None なら無効）。どちらかの方向に流れていれば接続を保ち、
+    // 両方向とも止まったままこの時間が過ぎたら両側を閉じる
+    pub tunnel_idle_timeout: Option<Duration>,
+    // 1 本のトンネルの寿命の上限（None なら無制限）。転送していても、接続からこの時間で両側を閉じる
+    // lifetime_window を指定するとスライディング方式になり、期限はデータを転送するたびに
+    // その時間だけ先へ延びる（max_lifetime は延ばせる上限）。止まったトンネルだけが先に閉じる
+    pub max_lifetime: Option<Duration>,
+    pub lifetime_window: Option<Duration>,
+    // 転送するソケット（クライアント・宛先）の SO_LINGER（None なら OS の既定のまま）
+    // 0 なら閉じるときに待たずに RST を送り、TIME_WAIT を残さない
+    pub linger: Option<Duration>,
+    // 転送に使うバッファのサイズ（バイト）。大きいほど大量転送時の read/write 回数が減る
+    pub buffer_size: usize,
+    // 転送中の途中経過（バイト数・速度）をログに出す間隔（None なら終了時の合計のみ）
+    pub stats_interval: Option<Duration>,
+    // 接続ごと・方向ごとの転送速度の上限（バイト/秒。0 なら無制限）
+    pub rate_limit: u64,
+    // 全ての接続・両方向の合計の転送速度の上限（バイト/秒。0 なら無制限）。共有の回線を守るため
+    pub total_rate_limit: u64,
+    // 転送中のソケットに TCP_NODELAY を設定するか（既定で有効）
+    pub tcp_nodelay: bool,
+    // 転送中のソケットの TCP keepalive（無通信の相手が消えたことを検出する。None なら無効）
+    // 最初のプローブまでの無通信時間・プローブの間隔・応答なしで切断するまでの回数
+    pub keepalive_idle: Option<Duration>,
+    pub keepalive_interval: Duration,
+    pub keepalive_probes: u32,
+    // 転送中のソケットの TCP_USER_TIMEOUT（Linux のみ。None なら OS の既定のまま）
+    // 送ったデータが確認応答されないままこの時間が過ぎたら、カーネルが接続を切る
+    pub tcp_user_timeout: Option<Duration>,
+    // SIGINT / SIGTERM 受信後、処理中の接続の終了を待つ上限時間
+    pub shutdown_grace: Duration,
+    // 接続を処理するワーカースレッド数と、空きワーカーを待つ接続の上限
+    // 待ち行列が満杯のときは、shed_policy に従って新しい接続か最も古い接続をすぐに閉じる
+    pub workers: usize,
+    pub queue_capacity: usize,
+    pub shed_policy: ShedPolicy,
+    // 同時に処理する接続数の上限（0 なら無制限）。達している間の新しい接続はすぐに閉じる
+    pub max_connections: usize,
+    // Prometheus 形式のメトリクスを返す HTTP の待ち受けアドレス（None なら無効）
+    pub metrics_listen: Option<String>,
+    // メトリクスを StatsD の UDP パケットとして送る先（host:port。None なら無効）と、送る間隔
+    pub statsd: Option<String>,
+    pub statsd_interval: Duration,
+    // 宛先の国と AS 番号を引くデータベース（CSV）。指定するとアクセスログと国ごとのメトリクスに出す
+    pub geoip_db: Option<String>,
+    // 時間のかかった宛先への接続を、遅い順に何件まで覚えておくか（0 なら記録しない）
+    // メトリクスのエンドポイントの /slow で見られる
+    pub slow_connects: usize,
+    // クライアント IP ごとの新規接続数の上限（1 秒あたり。0 なら無制限）と、連続で許す接続数
+    pub conn_rate: f64,
+    pub conn_burst: u32,
+    // CONNECT を中継する上位の SOCKS5 プロキシ（host:port）と、その認証情報（ユーザ名, パスワード）
+    // 指定すると宛先へ直接接続せず、上位プロキシ経由で接続する
+    pub upstream: Option<String>,
+    pub upstream_auth: Option<(String, String)>,
+    // 上位プロキシ経由のとき、ドメイン名を名前解決せずに上位プロキシへ渡すか（既定で有効）
+    // 無効にすると手元で名前解決し、IP アドレスで依頼する（block_private も名前解決後のアドレスで判定できる）
+    pub remote_dns: bool,
+    // 宛先への接続の先頭に PROXY protocol v1 のヘッダ（元のクライアントのアドレス）を付けるか
+    pub send_proxy_protocol: bool,
+    // クライアントからの接続の先頭で PROXY protocol のヘッダ（v1 または v2）を受け取るか
+    // ロードバランサの背後で動かすときに、元のクライアントのアドレスをログと許可判定に使う
+    pub accept_proxy_protocol: bool,
+    // 起動せずに設定の確認（待ち受けアドレスの bind など）だけを行って終了するか（--check）
+    pub check_only: bool,
+    // 同じポートで HTTP CONNECT のトンネルも受け付けるか（先頭バイトで SOCKS と見分ける）
+    pub http_connect: bool,
+    // ログの書式（テキスト、または 1 行 1 つの JSON オブジェクト）
+    pub log_format: LogFormat,
+    // ログを書くファイル（None なら標準出力・標準エラー）
+    pub log_file: Option<String>,
+    // ログファイルをこの大きさ（バイト）で切り替える（0 なら切り替えない）
+    pub log_max_size: u64,
+    // 切り替えた古いログファイルを残す数
+    pub log_max_files: usize,
+    // ログをローカルの syslog へ送るときの facility（"daemon"・"local0" など。None なら送らない）
+    pub syslog: Option<String>,
+    // 認証の試行の監査記録を書くファイル（None なら通常のログの出力先へ書く）
+    pub audit_log: Option<String>,
+    // Greeting で受け付ける NMETHODS の上限（超えたら METHODS を読まずに 0xFF で閉じる）
+    pub max_methods: usize,
+    // Request のドメイン名の長さ（LEN）の上限（超えたら名前を読まずに REP=0x04 で拒否する）
+    pub max_domain_len: usize,
+    // DNS 名として不正なホスト名を、名前解決せずに拒否するか（既定は無効）
+    pub validate_hostnames: bool,
+    // 拒否した接続への応じ方（拒否の応答を返すか、応答せずに FIN か RST で切断するか）
+    pub deny_mode: DenyMode,
+    // 成功応答より前にデータを送ってきたクライアント（要求→応答の順序違反）を拒否するか
+    pub strict_ordering: bool,
+    // Greeting を読む前に挟む遅延（即時応答を前提とするフラッドツールを減速させる。0 なら無し）
+    pub welcome_delay: Duration,
+    // 認証方法の優先順（先頭ほど優先。空なら GSSAPI、ユーザ/パスワード、No Auth の順）
+    // クライアントが提示した中で最初に一致し、かつ使える方法を選ぶ。一覧に無い方法は選ばない
+    pub methods: Vec<u8>,
+    // 宛先の書き換え規則（接続の前に、最初に一致した規則で宛先を置き換える。ログには元の宛先を出す）
+    pub rewrites: Vec<Rewrite>,
+    // 組み込み側の許可判定（Request の宛先を決めた後に呼び、許可・拒否・宛先の書き換えを決める）
+    pub authorizer: Option<Authorizer>,
+    // 組み込み側の接続元の判定（accept の直後、ハンドシェイクの前に呼ぶ。None なら全て受け付ける）
+    pub accept_filter: Option<AcceptFilter>,
+    // GSSAPI 認証（方法 0x01）のセキュリティコンテキストを作るもの（None なら GSSAPI を選ばない）
+    #[cfg(feature = "gssapi")]
+    pub gssapi: Option<Arc<dyn gssapi::GssapiAcceptor>>,
+}
+
+// 拒否した接続（送信元の許可・締め出し・接続数の上限・遮断規則など）への応じ方
+// 既定は拒否の応答（REP=0x02 など）を返す
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DenyMode {
+    #[default]
+    Reply,
+    // 応答を返さずに切断する（スキャナに情報を与えない。正規のクライアントにも理由が伝わらない）
+    Silent,
+    // 応答を返さず、SO_LINGER=0 で閉じて RST を送る（ポートが開いていること自体を見せにくくする）
+    Reset,
+}
+
+impl DenyMode {
+    pub(crate) fn parse(s: &str) -> Option<DenyMode> {
+        match s {
+            "reply" => Some(DenyMode::Reply),
+            "silent" => Some(DenyMode::Silent),
+            "reset" => Some(DenyMode::Reset),
+            _ => None,
+        }
+    }
+}
+
+// ワーカーの待ち行列が満杯のときに押し出す接続
+// 既定は新しく受け付けた接続を閉じる。oldest では最も長く待っている接続を閉じて、新しい接続を入れる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShedPolicy {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl ShedPolicy {
+    pub(crate) fn parse(s: &str) -> Option<ShedPolicy> {
+        match s {
+            "newest" => Some(ShedPolicy::Newest),
+            "oldest" => Some(ShedPolicy::Oldest),
+            _ => None,
+        }
+    }
+}
+
+// 拒否した接続を deny_mode に従って閉じる（reply の応答は呼び出し側で先に書いておく）
+// reset では SO_LINGER=0 を設定するだけで、ソケットを閉じたときに RST が送られる
+// TCP 以外の接続と、設定に失敗した場合は FIN で閉じる
+pub(crate) fn close_denied<S: ClientStream>(client: &S, config: &Config) {
+    if config.deny_mode == DenyMode::Reset
+        && let Some(tcp) = client.as_tcp()
+    {
+        match sockopt::set_linger(tcp, Duration::ZERO) {
+            Ok(()) => return,
+            Err(e) => warn!("deny_mode=reset: cannot set SO_LINGER ({e}), closing with FIN"),
+        }
+    }
+    let _ = client.shutdown(Shutdown::Both);
+}
+
+// 拒否した Request に deny_mode に従って応じる
+// reply では REP=rep の応答を返し、silent・reset では応答を返さずに閉じる（スキャナに情報を与えない）
+fn deny_request<S: ClientStream>(
+    client: &mut S,
+    config: &Config,
+    rep: u8,
+    atyp: u8,
+) -> io::Result<()> {
+    if config.deny_mode == DenyMode::Reply {
+        client.write_all(&build_error_reply(rep, atyp))?;
+        client.flush()
+    } else {
+        close_denied(client, config);
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec!["127.0.0.1:8080".to_string()],
+            reuse_port: false,
+            auth: false,
+            require_auth: false,
+            tenant_tags: false,
+            auth_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(5),
+            auth_fail_limit: 0,
+            auth_fail_window: Duration::from_secs(60),
+            auth_ban_duration: Duration::from_secs(300),
+            credentials: None,
+            auth_file: None,
+            allow_plaintext_passwords: false,
+            quota_file: None,
+            quota_save_interval: Duration::from_secs(60),
+            utc_offset: 0,
+            blocked_suffixes: Vec::new(),
+            blocked_cidrs: Vec::new(),
+            blocked_ports: Vec::new(),
+            allowed_ports: Vec::new(),
+            block_private: false,
+            client_allow: Vec::new(),
+            client_deny: Vec::new(),
+            dns_cache_ttl: Duration::from_secs(60),
+            dns_cache_size: 1024,
+            connect: ConnectStrategy::default(),
+            bind_timeout: Duration::from_secs(60),
+            bind_port_hint: false,
+            bind_source: None,
+            ipv6_zone: None,
+            idle_timeout: None,
+            tunnel_idle_timeout: None,
+            max_lifetime: None,
+            lifetime_window: None,
+            linger: None,
+            buffer_size: 32 * 1024,
+            stats_interval: None,
+            rate_limit: 0,
+            total_rate_limit: 0,
+            tcp_nodelay: true,
+            keepalive_idle: Some(Duration::from_secs(60)),
+            keepalive_interval: Duration::from_secs(10),
+            keepalive_probes: 5,
+            tcp_user_timeout: None,
+            shutdown_grace: Duration::from_secs(10),
+            workers: thread::available_parallelism().map_or(1, |n| n.get()) * 4,
+            queue_capacity: 64,
+            shed_policy: ShedPolicy::Newest,
+            max_connections: 0,
+            metrics_listen: None,
+            statsd: None,
+            statsd_interval: Duration::from_secs(10),
+            geoip_db: None,
+            slow_connects: 10,
+            conn_rate: 0.0,
+            conn_burst: 10,
+            upstream: None,
+            upstream_auth: None,
+            remote_dns: true,
+            send_proxy_protocol: false,
+            accept_proxy_protocol: false,
+            check_only: false,
+            http_connect: false,
+            log_format: LogFormat::Text,
+            log_file: None,
+            log_max_size: 10 * 1024 * 1024,
+            log_max_files: 5,
+            syslog: None,
+            audit_log: None,
+            max_methods: 32,
+            max_domain_len: 253,
+            validate_hostnames: false,
+            deny_mode: DenyMode::Reply,
+            strict_ordering: false,
+            welcome_delay: Duration::ZERO,
+            methods: Vec::new(),
+            rewrites: Vec::new(),
+            authorizer: None,
+            accept_filter: None,
+            #[cfg(feature = "gssapi")]
+            gssapi: None,
+        }
+    }
+}
+
+// SOCKS5 サーバ本体
+pub struct Socks5Server {
+    config: Arc<Config>,
+    // ServerHandle::shutdown からの終了要求（シグナルによる終了要求とどちらでも止まる）
+    stop: Arc<AtomicBool>,
+}
+
+// spawn_server で起動したサーバ。shutdown で止める
+pub struct ServerHandle {
+    config: Arc<Config>,
+    stop: Arc<AtomicBool>,
+    local_addrs: Vec<SocketAddr>,
+    thread: thread::JoinHandle<io::Result<()>>,
+}
+
+// サーバを別スレッドで起動する（アプリケーションへの組み込み・テスト用）
+// 待ち受けアドレスの bind までは呼び出し元のスレッドで行い、失敗はここで返す
+// シグナルのハンドラは登録しない（止めるには ServerHandle::shutdown、読み直しには reload を呼ぶ）
+pub fn spawn_server(config: Config) -> io::Result<ServerHandle> {
+    let server = Socks5Server::new(config);
+    let listeners = server.start()?;
+    let local_addrs = listeners.iter().filter_map(Listener::local_addr).collect();
+    let config = Arc::clone(&server.config);
+    let stop = Arc::clone(&server.stop);
+    let thread = thread::spawn(move || server.serve(listeners));
+    Ok(ServerHandle { config, stop, local_addrs, thread })
+}
+
+impl ServerHandle {
+    // TCP の待ち受けアドレス（"127.0.0.1:0" で bind した場合の実際のポートを知るのに使う）
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    // SIGHUP と同じく認証情報ファイルを読み直す（読み込みに失敗したら、それまでの内容を使い続ける）
+    pub fn reload(&self) {
+        auth::reload_credentials(&self.config);
+    }
+
+    // 新規の受付を止め、処理中の接続の終了を（shutdown_grace まで）待ってから返る
+    pub fn shutdown(self) -> io::Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("server thread panicked")))
+    }
+}
+
+impl Socks5Server {
+    pub fn new(config: Config) -> Self {
+        log::set_format(config.log_format);
+        Socks5Server {
+            config: Arc::new(config),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // 1) 待ち受けアドレスごとにリスナーを立て、それぞれの accept ループをスレッドで回す
+    // 一部のアドレスで bind に失敗しても、残りのアドレスでは起動する
+    pub fn run(&self) -> io::Result<()> {
+        if self.config.check_only {
+            return self.check();
+        }
+        let listeners = self.start()?;
+        // 2) SIGINT / SIGTERM で新規受付を止め、処理中の接続を猶予時間まで待ってから終了する
+        signal::install_handlers();
+        self.serve(listeners)
+    }
+
+    // ログの出力先を開き、リスナーを立て、メトリクスと転送量の記録を準備する
+    fn start(&self) -> io::Result<Vec<Listener>> {
+        auth::check_tenant_tags(&self.config)?;
+        if let Some(path) = &self.config.log_file {
+            log::open_file(path, self.config.log_max_size, self.config.log_max_files)?;
+        }
+        if let Some(facility) = &self.config.syslog {
+            log::open_syslog(facility)?;
+        }
+        if let Some(path) = &self.config.audit_log {
+            log::open_audit_file(path)?;
+        }
+        let mut listeners = Vec::new();
+        for addr in &self.config.listen {
+            match Listener::bind(addr, self.config.reuse_port) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) => error!("failed to listen on {addr}: {e}"),
+            }
+        }
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                "no listen address could be bound",
+            ));
+        }
+
+        if let Some(addr) = &self.config.metrics_listen {
+            metrics::spawn_endpoint(addr)?;
+        }
+        if let Some(addr) = &self.config.statsd {
+            metrics::spawn_statsd(addr, self.config.statsd_interval)?;
+        }
+        if let Some(path) = &self.config.geoip_db {
+            let networks = geoip::load(path)?;
+            info!("loaded {networks} GeoIP network(s) from {path}");
+        }
+        if let Some(path) = &self.config.quota_file {
+            let users = quota::load(path)?;
+            info!("loaded transfer totals of {users} user(s) from {path}");
+        }
+        Ok(listeners)
+    }
+
+    // 終了要求（シグナルか ServerHandle::shutdown）まで接続を受け付け、処理中の接続を待ってから返る
+    fn serve(&self, listeners: Vec<Listener>) -> io::Result<()> {
+        let config = Arc::clone(&self.config);
+        let stop = Arc::clone(&self.stop);
+        thread::spawn(move || housekeeping(&config, &stop));
+        let active = Arc::new(AtomicUsize::new(0));
+        let shared = Shared {
+            config: Arc::clone(&self.config),
+            stop: Arc::clone(&self.stop),
+            active: Arc::clone(&active),
+            pool: ThreadPool::new(self.config.workers, self.config.queue_capacity),
+            conn_limiter: (self.config.conn_rate > 0.0).then(|| {
+                Arc::new(ConnRateLimiter::new(self.config.conn_rate, self.config.conn_burst))
+            }),
+        };
+
+        let socket_paths: Vec<PathBuf> =
+            listeners.iter().filter_map(|l| l.socket_path().map(PathBuf::from)).collect();
+        let loops: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| {
+                let shared = shared.clone();
+                thread::spawn(move || accept_loop(listener, shared))
+            })
+            .collect();
+        for handle in loops {
+            let _ = handle.join();
+        }
+        for path in &socket_paths {
+            let _ = fs::remove_file(path);
+        }
+
+        let remaining = active.load(Ordering::SeqCst);
+        if remaining > 0 {
+            info!("shutting down: waiting for {remaining} connection(s)");
+            let deadline = deadline_after(Instant::now(), self.config.shutdown_grace);
+            while active.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let remaining = active.load(Ordering::SeqCst);
+        if remaining > 0 {
+            warn!("shutdown grace period expired, dropping {remaining} connection(s)");
+        }
+        save_quota(&self.config);
+        info!("SOCKS5 proxy stopped");
+        Ok(())
+    }
+
+    // 設定の確認（--check）: 各待ち受けアドレスに bind できるか試してすぐに閉じ、
+    // 上位プロキシのアドレスが名前解決できるかも確かめる。問題は全て報告してからエラーを返す
+    // 引数・設定ファイル・認証情報ファイルの形式は、ここに来るまでの解析で確認済み
+    pub fn check(&self) -> io::Result<()> {
+        let mut errors = 0;
+        for addr in &self.config.listen {
+            match Listener::check(addr, self.config.reuse_port) {
+                Ok(()) => info!("listen address OK: {addr}"),
+                Err(e) => {
+                    error!("cannot listen on {addr}: {e}");
+                    errors += 1;
+                }
+            }
+        }
+        if let Some(addr) = &self.config.metrics_listen
+            && let Err(e) = TcpListener::bind(addr)
+        {
+            error!("cannot listen on {addr} for metrics: {e}");
+            errors += 1;
+        }
+        if let Some(addr) = &self.config.statsd
+            && let Err(e) = addr.to_socket_addrs()
+        {
+            error!("cannot resolve StatsD target {addr}: {e}");
+            errors += 1;
+        }
+        if let Some(path) = &self.config.geoip_db
+            && let Err(e) = geoip::load(path)
+        {
+            error!("cannot load the GeoIP database: {e}");
+            errors += 1;
+        }
+        if let Some(upstream) = &self.config.upstream
+            && let Err(e) = upstream.to_socket_addrs()
+        {
+            error!("cannot resolve upstream {upstream}: {e}");
+            errors += 1;
+        }
+        for path in [&self.config.log_file, &self.config.audit_log].into_iter().flatten() {
+            if let Err(e) = fs::OpenOptions::new().create(true).append(true).open(path) {
+                error!("cannot open log file {path}: {e}");
+                errors += 1;
+            }
+        }
+        if errors > 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("configuration check failed with {errors} error(s)"),
+            ));
+        }
+        info!("configuration OK");
+        Ok(())
+    }
+}
+
+// 接続の処理とは別のスレッドで、終了要求まで繰り返す定期的な処理
+// SIGHUP では認証情報ファイルを読み直し（処理中の接続はそのまま続ける）、
+// quota_save_interval ごとにユーザごとの転送量の記録を保存する
+fn housekeeping(config: &Config, stop: &AtomicBool) {
+    let mut last_save = Instant::now();
+    while !stopping(stop) {
+        if signal::take_reload_request() {
+            auth::reload_credentials(config);
+        }
+        if !config.quota_save_interval.is_zero()
+            && last_save.elapsed() >= config.quota_save_interval
+        {
+            save_quota(config);
+            last_save = Instant::now();
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// 転送量の記録を quota_file へ保存する（失敗しても、次の保存の機会に書き直す）
+fn save_quota(config: &Config) {
+    if let Some(path) = &config.quota_file
+        && let Err(e) = quota::save(path)
+    {
+        error!("failed to save transfer totals to {path}: {e}");
+    }
+}
+
+// 全ての accept ループで共有する状態
+#[derive(Clone)]
+struct Shared {
+    config: Arc<Config>,
+    stop: Arc<AtomicBool>,
+    // 処理中の接続数（終了時の待ち合わせに使う）
+    active: Arc<AtomicUsize>,
+    pool: ThreadPool,
+    // クライアント IP ごとの新規接続数の制限（無効なら None）
+    conn_limiter: Option<Arc<ConnRateLimiter>>,
+}
+
+// 待ち受けソケット（TCP、または "unix:" で始まるパスの Unix ドメインソケット）
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(addr: &str, reuse_port: bool) -> io::Result<Self> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            return Self::bind_unix(Path::new(path));
+        }
+        // Unix では std の TcpListener::bind が bind の前に SO_REUSEADDR を設定するため、
+        // 終了直後（TIME_WAIT が残っている間）でも同じアドレスで再起動できる
+        let listener = if reuse_port {
+            let listener = Self::bind_reuse_port(addr)?;
+            info!("SO_REUSEPORT enabled on {}", listener.local_addr()?);
+            listener
+        } else {
+            TcpListener::bind(addr)?
+        };
+        info!("SOCKS5 proxy running on {}", listener.local_addr()?);
+        Ok(Listener::Tcp(listener))
+    }
+
+    // SO_REUSEPORT 付きで待ち受ける（名前解決したアドレスを順に試す。TcpListener::bind と同じ）
+    fn bind_reuse_port(addr: &str) -> io::Result<TcpListener> {
+        let mut last_err = None;
+        for candidate in addr.to_socket_addrs()? {
+            match sockopt::listen_reuse_port(&candidate) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(ErrorKind::InvalidInput, "could not resolve to any addresses")
+        }))
+    }
+
+    // 前回の起動で残ったソケットファイルは消してから bind する（ソケット以外のファイルは消さない）
+    #[cfg(unix)]
+    fn bind_unix(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("SOCKS5 proxy running on unix:{}", path.display());
+        Ok(Listener::Unix(listener, path.to_path_buf()))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "Unix domain sockets are not supported on this platform",
+        ))
+    }
+
+    // bind できるかを確かめてすぐに閉じる（--check 用）
+    // reuse_port なら、同じ設定で動いているインスタンスがあっても bind できる
+    fn check(addr: &str, reuse_port: bool) -> io::Result<()> {
+        match addr.strip_prefix("unix:") {
+            Some(path) => Self::check_unix(Path::new(path)),
+            None if reuse_port => Self::bind_reuse_port(addr).map(drop),
+            None => TcpListener::bind(addr).map(drop),
+        }
+    }
+
+    // 動いているサーバのソケットを消さないよう、既存のソケットファイルには接続して使用中かを確かめる
+    #[cfg(unix)]
+    fn check_unix(path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixStream;
+        match fs::symlink_metadata(path) {
+            Ok(m) if !m.file_type().is_socket() => Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            )),
+            Ok(_) if UnixStream::connect(path).is_ok() => Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is in use", path.display()),
+            )),
+            // 前回の起動で残ったソケットは、起動時に置き換える
+            Ok(_) => Ok(()),
+            Err(_) => {
+                UnixListener::bind(path)?;
+                fs::remove_file(path)
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn check_unix(path: &Path) -> io::Result<()> {
+        Self::bind_unix(path).map(drop)
+    }
+
+    // Unix ドメインソケットのパス（終了時に消す）
+    fn socket_path(&self) -> Option<&Path> {
+        match self {
+            Listener::Tcp(_) => None,
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Some(path),
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(l) => l.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(..) => None,
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(l) => l.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+// シグナルによる終了要求か、このサーバへの終了要求（stop）が来ているか
+fn stopping(stop: &AtomicBool) -> bool {
+    signal::shutdown_requested() || stop.load(Ordering::SeqCst)
+}
+
+// 接続を受け付け、スレッドプールに処理を渡す
+// 終了要求を確認できるよう、ノンブロッキングの accept を短い間隔で繰り返す
+fn accept_loop(listener: Listener, shared: Shared) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        error!("accept error: {e}");
+        return;
+    }
+    while !stopping(&shared.stop) {
+        let accepted = match &listener {
+            Listener::Tcp(l) => l.accept().map(|(client, _)| dispatch(client, &shared)),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.accept().map(|(client, _)| dispatch(client, &shared)),
+        };
+        match accepted {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => error!("accept error: {e}"),
+        }
+    }
+}
+
+// 受け付けた接続を、制限を確認してからスレッドプールに渡す
+fn dispatch<S: ClientStream + 'static>(mut client: S, shared: &Shared) {
+    if let Err(e) = client.set_nonblocking(false) {
+        error!("accept error: {e}");
+        return;
+    }
+    let peer = match client.client_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            error!("accept error: {e}");
+            return;
+        }
+    };
+    let from = peer_label(peer);
+    // 組み込み側の判定（accept_filter）で拒否された接続元は、Greeting を読まずに閉じる
+    if let Some(peer) = peer
+        && !authorize::accept(&shared.config, peer)
+    {
+        warn!("connection from {from} rejected by accept filter");
+        metrics::connection_filtered();
+        close_denied(&client, &shared.config);
+        return;
+    }
+    // 同じ IP からの接続が多すぎる場合は、Greeting を読まずに閉じる
+    if let Some(limiter) = &shared.conn_limiter
+        && let Some(peer) = peer
+        && !limiter.allow(peer.ip())
+    {
+        warn!("connection from {from} rejected: connection rate limit exceeded");
+        close_denied(&client, &shared.config);
+        return;
+    }
+    // 同時接続数が上限に達していれば、メソッド選択の拒否（0xFF）を返して閉じる
+    let max = shared.config.max_connections;
+    let Some(guard) = ActiveGuard::try_new(&shared.active, max) else {
+        warn!(
+            "connection from {from} rejected: too many connections ({} active)",
+            shared.active.load(Ordering::SeqCst)
+        );
+        if shared.config.deny_mode == DenyMode::Reply {
+            let _ = client.write_all(&[0x05, 0xFF]);
+        }
+        close_denied(&client, &shared.config);
+        return;
+    };
+    // 待ち行列から押し出したときに閉じられるよう、複製を残しておく
+    let overflow = client.try_clone();
+    let id = next_conn_id();
+    if log::json_format() {
+        let fields = [
+            ("event", log::Field::Str("open")),
+            ("conn_id", log::Field::Num(id)),
+            ("client", log::Field::Str(&from)),
+            ("active", log::Field::Num(guard.count() as u64)),
+        ];
+        log::write_json(log::Level::Info, "accepted connection", &fields);
+    } else {
+        info!("[conn {id}] accepted connection from {from} ({} active)", guard.count());
+    }
+    let config = Arc::clone(&shared.config);
+    let job = move || {
+        let _guard = guard;
+        let _ = handle_connection_with_id(id, &mut client, &config);
+    };
+    // 押し出されたとき（仕事と guard・client は先に破棄される）
+    let config = Arc::clone(&shared.config);
+    let shed = move || {
+        warn!("[conn {id}] connection from {from} shed: worker queue is full");
+        metrics::connection_shed();
+        if let Ok(c) = overflow {
+            close_denied(&c, &config);
+        }
+    };
+    shared.pool.execute(job, shed, shared.config.shed_policy);
+}
+
+// 処理中の接続数を数える（スレッド終了時に Drop で減らす）
+struct ActiveGuard(Arc<AtomicUsize>);
+
+impl ActiveGuard {
+    // 処理中の接続数を 1 増やす。max（0 なら無制限）に達していれば増やさずに None を返す
+    fn try_new(active: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (max == 0 || n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(ActiveGuard(Arc::clone(active)))
+    }
+
+    // 現在の処理中の接続数（この接続を含む）
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// 1 本の接続を処理する（ハンドシェイクから転送の終了まで）
+// 終了時（エラーで終わった場合も）に、接続ごとのアクセスログを 1 行出す
+// 失敗した理由は SocksError で返す（プロトコル上の失敗と読み書きの失敗を見分けられる）
+pub fn handle_connection<S: ClientStream>(
+    client: &mut S,
+    config: &Arc<Config>,
+) -> Result<(), SocksError> {
+    handle_connection_with_id(next_conn_id(), client, config).map_err(SocksError::from)
+}
+
+// 接続 ID を指定して処理する（accept 時に払い出した ID をこのスレッドのログに付ける）
+fn handle_connection_with_id<S: ClientStream>(
+    id: u64,
+    client: &mut S,
+    config: &Arc<Config>,
+) -> io::Result<()> {
+    let peer = client.client_addr()?;
+    log::set_conn_id(Some(id));
+    let mut session = Session::new(id, peer);
+    metrics::connection_opened();
+    // 読み込みのタイムアウト（WouldBlock）はハンドシェイク中にしか起きない（転送中は正常終了として扱う）
+    let res = serve(client, config, &mut session).map_err(|e| {
+        if e.kind() == ErrorKind::WouldBlock {
+            io::Error::new(ErrorKind::TimedOut, "handshake timed out")
+        } else {
+            e
+        }
+    });
+    metrics::connection_closed();
+    session.log_access(&res);
+    if let Err(e) = &res {
+        warn!("client error: {e}");
+        // deny_mode=reset で拒否した接続は、FIN を送らずに閉じる（close_denied で RST になる）
+        if !(config.deny_mode == DenyMode::Reset && e.kind() == ErrorKind::PermissionDenied) {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+    log::set_conn_id(None);
+    res
+}
+
+fn serve<S: ClientStream>(
+    client: &mut S,
+    config: &Arc<Config>,
+    session: &mut Session,
+) -> io::Result<()> {
+    // 1.3) 転送を始めるまで（PROXY ヘッダ・Greeting・認証・Request）の読み書きに制限時間を設ける
+    // 途中で止まったクライアントがワーカーを占有しないように。転送開始時に idle_timeout に切り替わる
+    let handshake_timeout =
+        (!config.handshake_timeout.is_zero()).then_some(config.handshake_timeout);
+    client.set_read_timeout(handshake_timeout)?;
+    client.set_write_timeout(handshake_timeout)?;
+
+    // 1.35) PROXY protocol のヘッダを読み、元のクライアントのアドレスに置き換える
+    // アドレスを伝えないヘッダ（LOCAL・UNKNOWN）なら、TCP の接続元のまま扱う
+    if config.accept_proxy_protocol
+        && let Some(real) = proxyproto::read_header(client)?
+    {
+        info!("PROXY protocol: real client {real} (via {})", peer_label(session.peer));
+        session.peer = Some(real);
+    }
+
+    // 1.4) 送信元アドレスの許可・拒否（Greeting を読む前に判定し、許可されなければ即座に切断）
+    // アドレスの無い接続（Unix ドメインソケット）は判定しない
+    if let Some(peer) = session.peer
+        && !client_allowed(config, peer.ip())
+    {
+        close_denied(client, config);
+        return Err(SocksError::DestinationDenied(format!("client {peer} not allowed"))
+            .into_io(ErrorKind::PermissionDenied));
+    }
+
+    // 1.45) 認証の失敗が多すぎて締め出し中の IP は、Greeting を読まずに閉じる
+    if let Some(peer) = session.peer
+        && let Some(left) = authban::banned(peer.ip())
+    {
+        close_denied(client, config);
+        return Err(SocksError::DestinationDenied(format!(
+            "client {peer} banned for failed authentication ({}s left)",
+            left.as_secs()
+        ))
+        .into_io(ErrorKind::PermissionDenied));
+    }
+
+    // 1.5) 接続直後の遅延（接続フラッド対策、welcome_delay で指定。既定 0）
+    if !config.welcome_delay.is_zero() {
+        thread::sleep(config.welcome_delay);
+    }
+
+    // 1.6) 先頭 1 バイトを覗き、VER=0x04 なら SOCKS4/4a として処理する
+    // http_connect が有効なら、HTTP のメソッドに見えるもの（英大文字）は HTTP CONNECT として処理する
+    let mut first = [0u8; 1];
+    if client.peek(&mut first)? == 1 {
+        if first[0] == 0x04 {
+            return socks4::handle_socks4(client, config, session);
+        }
+        if config.http_connect && http::looks_like_http(first[0]) {
+            return http::handle_http_connect(client, config, session);
+        }
+    }
+
+    // 2) Greeting を読む: [VER, NMETHODS, METHODS]
+    let mut greeting = vec![0u8; 2];
+    client.read_exact(&mut greeting)?; // VER, NMETHODS
+    // METHODS は NMETHODS バイト
+    // NMETHODS=0 の場合は何も読まずに進み、選べるメソッドが無いので 0xFF を返して閉じる
+    // （Greeting に続けて Request を送ってくるクライアントでも、その Request は読まない）
+    // NMETHODS が上限（max_methods）を超えていれば、METHODS を読まずに 0xFF を返して閉じる
+    if greeting[0] == 0x05 && greeting[1] as usize > config.max_methods {
+        client.write_all(&[0x05, 0xFF])?;
+        client.flush()?;
+        return Err(SocksError::MalformedRequest(format!(
+            "malformed at greeting byte 1: NMETHODS={} exceeds the limit of {}",
+            greeting[1], config.max_methods
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    if greeting[0] == 0x05 && greeting[1] > 0 {
+        greeting.resize(2 + greeting[1] as usize, 0);
+        client.read_exact(&mut greeting[2..])?;
+    }
+    let methods = parse_greeting(&greeting)?;
+    debug!("methods offered: {:?}", methods);
+
+    // 3) METHOD 選択（select_method）
+    let chosen = select_method(config, &methods);
+    let selection = vec![0x05, chosen];
+    client.write_all(&selection)?;
+    client.flush()?;
+    if chosen == 0xFF {
+        if config.require_auth {
+            warn!("rejected: client did not offer username/password authentication (required)");
+            return Err(SocksError::NoAcceptableMethods.into_io(ErrorKind::PermissionDenied));
+        }
+        return Err(SocksError::NoAcceptableMethods.into_io(ErrorKind::Other));
+    }
+
+    // 3.5) ユーザ/パスワード認証の実行（選択が 0x02 の場合のみ実施）
+    // タグモードではユーザ名をテナントタグとして保持し、ログに付与する
+    // 認証したユーザのアカウント（認証情報ファイルの宛先の制限・転送量の上限）も取り出しておく
+    // タグもユーザ名と同じく扱い、転送量の集計などのユーザごとの処理はそのまま行う
+    let mut tenant: Option<String> = None;
+    let mut authenticated = None;
+    if chosen == 0x02 {
+        let username = perform_userpass_auth_inline(client, session.peer, config)?;
+        session.user = Some(username.clone());
+        if config.tenant_tags {
+            tenant = Some(username.clone());
+        }
+        authenticated = Some((user_account(config, &username), username));
+    }
+    // 3.6) GSSAPI のサブネゴシエーション（選択が 0x01 の場合のみ実施）
+    // 確立したコンテキストの利用者の名前を、ユーザ名と同じように扱う
+    #[cfg(feature = "gssapi")]
+    if chosen == 0x01
+        && let Some(acceptor) = &config.gssapi
+    {
+        let principal = gssapi::negotiate(client, acceptor.as_ref())?;
+        info!("Authenticated GSSAPI principal '{principal}'");
+        session.user = Some(principal.clone());
+        authenticated = Some((user_account(config, &principal), principal));
+    }
+    let account = authenticated.as_ref().and_then(|(account, _)| account.as_ref());
+
+    // 4) Request を読む: [VER, CMD, RSV, ATYP, DST.ADDR, DST.PORT]
+    let mut req_hdr = [0u8; 4];
+    client.read_exact(&mut req_hdr)?;
+    let (cmd, atyp) = match parse_request_header(&req_hdr) {
+        Ok(v) => v,
+        Err(e) => {
+            if req_hdr[0] != 0x05 {
+                // メソッド選択は済んでいるので、閉じる前に General failure (0x01) を返す
+                let rep = build_error_reply(0x01, req_hdr[3]);
+                let _ = client.write_all(&rep);
+                let _ = client.flush();
+            }
+            return Err(e);
+        }
+    };
+
+    if !matches!(cmd, 0x01..=0x03) {
+        // CONNECT (0x01)・BIND (0x02)・UDP ASSOCIATE (0x03) 以外は未対応
+        // 失敗応答（Command not supported = 0x07）。ATYP はリクエストに合わせる
+        let rep = build_error_reply(0x07, atyp);
+        client.write_all(&rep)?;
+        client.flush()?;
+        return Err(SocksError::UnsupportedCommand(cmd).into_io(ErrorKind::Other));
+    }
+
+    if !matches!(atyp, 0x01 | 0x03 | 0x04) {
+        // 未知の ATYP は DST.ADDR の長さが分からないため、Address type not supported (0x08) を返して閉じる
+        let rep = build_error_reply(0x08, atyp);
+        client.write_all(&rep)?;
+        client.flush()?;
+        return Err(SocksError::UnsupportedAtyp(atyp).into_io(ErrorKind::InvalidData));
+    }
+
+    // 5) DST.ADDR と DST.PORT の読み取り（ATYPに応じて可変長）
+    // ドメイン名の長さが 0 か上限（max_domain_len）を超えていれば、名前を読まずに REP=0x04 で拒否する
+    let mut dst = match read_dst(atyp, client, config.max_domain_len) {
+        Ok(dst) => dst,
+        Err(e) if e.kind() == ErrorKind::InvalidData => {
+            warn!("invalid hostname rejected: {e}");
+            let rep = build_error_reply(0x04, atyp);
+            client.write_all(&rep)?;
+            client.flush()?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    session.dst = Some(dst.to_string());
+
+    // 5.1) 国際化ドメイン名は ASCII 形式（xn--）にしてから判定・名前解決する
+    // 明らかに不正なホスト名（空・NUL を含む・長すぎる）は、接続を試みずに REP=0x04 で拒否する
+    if let Dst::Domain(host, _) = &mut dst {
+        match idna::to_ascii(host) {
+            Ok(ascii) => {
+                if ascii != *host {
+                    debug!("IDNA: {host} -> {ascii}");
+                }
+                *host = ascii;
+            }
+            Err(e) => {
+                warn!("invalid hostname rejected: {e}");
+                let rep = build_error_reply(0x04, atyp);
+                client.write_all(&rep)?;
+                client.flush()?;
+                return Err(e);
+            }
+        }
+    }
+
+    // 5.1.1) 宛先の書き換え規則（rewrites）を適用する
+    // 規則のホスト名は解析時に ASCII 形式にしてあり、書き換え後のホスト名も以下の判定を受ける
+    rewrite::rewrite(config, &mut dst);
+
+    // validate_hostnames のとき、DNS 名として不正なホスト名は
+    // 名前解決せずに REP=0x04 (Host unreachable) で拒否する
+    if let Dst::Domain(host, _) = &dst
+        && config.validate_hostnames
+        && !is_valid_dns_name(host)
+    {
+        warn!("invalid hostname rejected: {host:?}");
+        let rep = build_error_reply(0x04, atyp);
+        client.write_all(&rep)?;
+        client.flush()?;
+        return Err(SocksError::MalformedRequest(format!("invalid hostname: {host:?}"))
+            .into_io(ErrorKind::InvalidData));
+    }
+
+    // 5.1.2) 組み込み側の許可判定（authorizer）。拒否ならその REP を返し、書き換えなら宛先を置き換える
+    if let Some(rep) = authorize::authorize(config, session, cmd, &mut dst) {
+        deny_request(client, config, rep, atyp)?;
+        return Err(SocksError::DestinationDenied(format!(
+            "destination {dst} denied by authorizer"
+        ))
+        .into_io(ErrorKind::PermissionDenied));
+    }
+
+    // 簡単なインスペクション: 宛先（ポート・アドレス範囲・ドメイン名）で遮断判定し、REP=0x02 を返す
+    // BIND・UDP ASSOCIATE の DST は相手やクライアントの予定のアドレス（多くは 0.0.0.0:0）で宛先ではないため、
+    // 判定しない（UDP はデータグラムごとに宛先を判定する）
+    if cmd == 0x01
+        && let Some(reason) = blocked_reason(&dst, config)
+    {
+        info!("blocked by ruleset: {dst} ({reason})");
+        deny_request(client, config, 0x02, atyp)?; // REP=0x02 (Connection not allowed by ruleset)
+        return Err(SocksError::DestinationDenied(format!("blocked destination: {dst}"))
+            .into_io(ErrorKind::PermissionDenied));
+    }
+
+    // 5.2) ユーザごとの宛先の制限（ACL）で判定し、許されていなければ REP=0x02 を返す
+    // BIND・UDP ASSOCIATE は宛先ごとの判定ができないため、ACL のあるユーザには許さない
+    // ドメイン名の宛先は、6) で接続する直前に名前解決したアドレスで判定する
+    let acl = account.and_then(|a| a.acl.as_ref());
+    if let Some(acl) = acl {
+        let denied = if cmd == 0x01 {
+            acl.denied_reason(&dst, config)
+        } else {
+            Some(format!("command 0x{cmd:02X} not allowed for this user"))
+        };
+        if let Some(reason) = denied {
+            let user = session.user.as_deref().unwrap_or_default();
+            info!("blocked by user ACL: {dst} for '{user}' ({reason})");
+            deny_request(client, config, 0x02, atyp)?;
+            return Err(SocksError::DestinationDenied(format!(
+                "destination {dst} not allowed for user '{user}'"
+            ))
+            .into_io(ErrorKind::PermissionDenied));
+        }
+    }
+
+    // 5.3) 認証したユーザの転送量を数える。今の期間の上限に達していれば、新しい接続は REP=0x02 で拒否する
+    // （処理中の接続は上限を超えても切らない）
+    if let Some((_, user)) = &authenticated {
+        let quota = account.and_then(|a| a.quota.as_ref());
+        let usage = quota::counter(user, quota);
+        if let Some(quota) = quota {
+            let used = usage.load(Ordering::Relaxed);
+            if used >= quota.bytes {
+                info!("blocked by quota: {dst} for '{user}' ({used}/{} bytes)", quota.bytes);
+                deny_request(client, config, 0x02, atyp)?;
+                return Err(SocksError::DestinationDenied(format!(
+                    "user '{user}' exceeded the transfer quota"
+                ))
+                .into_io(ErrorKind::PermissionDenied));
+            }
+        }
+        session.usage = Some(usage);
+        // ユーザの転送速度の上限（rate=）。読み直しで変わった上限も、この接続の転送に効く
+        session.user_rate = Some(ratelimit::user_rate(user, account.and_then(|a| a.rate)));
+    }
+
+    // ログに付けるテナントタグ（タグモード以外は空）
+    let tag = tenant
+        .map(|t| format!(" [tenant {t}]"))
+        .unwrap_or_default();
+
+    if cmd != 0x01 {
+        // BIND・UDP ASSOCIATE は制御接続のアドレスを使うため、TCP の接続でしか受け付けない
+        let Some(tcp) = client.as_tcp() else {
+            info!("command 0x{cmd:02X} is not supported over a Unix domain socket");
+            let rep = build_error_reply(0x07, atyp);
+            client.write_all(&rep)?;
+            client.flush()?;
+            return Err(SocksError::UnsupportedCommand(cmd).into_io(ErrorKind::Unsupported));
+        };
+        // 制御接続を長く保つため、ハンドシェイクの制限時間を外す
+        tcp.set_read_timeout(None)?;
+        tcp.set_write_timeout(None)?;
+
+        // 5.4) BIND の場合は待ち受けへ（DST はこれから接続してくる相手の予定アドレス）
+        if cmd == 0x02 {
+            return bind::handle_bind(tcp, &dst, &tag, config, session);
+        }
+
+        // 5.5) UDP ASSOCIATE の場合は UDP 中継へ（DST はクライアントの送信元の予定で、通常 0）
+        return udp::handle_udp_associate(tcp, Arc::clone(config), session);
+    }
+
+    // strict_ordering のとき、応答より前にクライアントがデータを送ってきていたら
+    // （要求→応答の順序違反）、宛先へ接続せずに REP=0x02 で拒否する
+    if config.strict_ordering && has_pending_data(client)? {
+        info!("blocked by strict ordering: {dst} (client sent data before the reply)");
+        deny_request(client, config, 0x02, atyp)?;
+        return Err(SocksError::MalformedRequest(
+            "client sent data before the reply (strict ordering)".to_string(),
+        )
+        .into_io(ErrorKind::InvalidData));
+    }
+
+    // 6) 宛先へ TCP 接続
+
+    // ログ（要求された宛先）を表示
+    info!("Requested destination: {dst}");
+
+    let (remote, bound_addr) = match open_remote(&dst, config, acl) {
+        Ok(v) => v,
+        Err(e) => {
+            metrics::connect_failed();
+            // 失敗理由（ErrorKind）に応じた REP を返す（不明なものは General failure 0x01）
+            let rep = build_error_reply(reply_code_for(&e), atyp);
+            let _ = client.write_all(&rep);
+            let _ = client.flush();
+            return Err(SocksError::connect_failed(e));
+        }
+    };
+
+    // 7) 成功応答: [VER, REP, RSV, ATYP, BND.ADDR, BND.PORT]
+    log_connected(&remote, &dst, config, session);
+    // 6.5) 宛先が元のクライアントのアドレスを知れるよう、PROXY protocol のヘッダを先に送る
+    if config.send_proxy_protocol {
+        proxyproto::send_v1(&remote, client, session)?;
+    }
+    debug!("Bound local address: {bound_addr}");
+
+    let response = build_success_reply(bound_addr);
+
+    client.write_all(&response)?;
+    client.flush()?;
+
+    // 8) 転送
+    relay::splice(client, remote, &tag, config, session)
+}
+
+// CONNECT の宛先へ接続し、接続と成功応答で返す BND.ADDR を返す
+// --upstream の指定があれば上位プロキシ経由で接続し、BND.ADDR は上位プロキシの応答の値を使う
+// acl があれば、実際に接続するアドレスがユーザの ACL の networks に含まれるかを判定する
+pub(crate) fn open_remote(
+    dst: &Dst,
+    config: &Config,
+    acl: Option<&Acl>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    if let Some(upstream) = &config.upstream {
+        return open_via_upstream(upstream, dst, config, acl);
+    }
+
+    // 名前解決と接続にかかった時間を、遅かった接続の一覧（slow_connects）のために測る
+    let started = Instant::now();
+    let mut resolve = Duration::ZERO;
+    let remote = match dst {
+        Dst::V4(ip, port) => {
+            let addr =
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])), *port);
+            connect_dst(addr, config, acl)
+        }
+        Dst::V6(ip, port) => {
+            let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::from(*ip)), *port);
+            connect_dst(addr, config, acl)
+        }
+        // ゾーン付きの IPv6 アドレス（"fe80::1%eth0"）は、DOMAIN として送られてきたものだけを受け付ける
+        Dst::Domain(host, port) => match scoped_ipv6(host, *port)? {
+            Some(addr) => connect_dst(addr, config, acl),
+            None => {
+                let addrs = dns::resolve(host, *port, config);
+                resolve = started.elapsed();
+                addrs.and_then(|addrs| connect_dst(addrs.as_slice(), config, acl))
+            }
+        },
+    };
+    let connect = started.elapsed().saturating_sub(resolve);
+    slowlog::record(config.slow_connects, dst, resolve, connect, remote.is_ok());
+    let remote = remote?;
+    let bound_addr = remote
+        .local_addr()
+        .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+    Ok((remote, bound_addr))
+}
+
+// 接続できた宛先をログに出し、--geoip-db があれば宛先の国と AS 番号を session に記録する
+// 上位プロキシ経由では接続の相手は上位プロキシなので、要求された宛先と並べて出す
+// （宛先のアドレスは分からないため、国と AS 番号は引かない）
+pub(crate) fn log_connected(remote: &TcpStream, dst: &Dst, config: &Config, session: &mut Session) {
+    let Ok(peer) = remote.peer_addr() else {
+        return;
+    };
+    if config.upstream.is_some() {
+        info!("Connected to destination: {dst} via upstream {peer}");
+        return;
+    }
+    info!("Connected to destination: {peer}");
+    if config.geoip_db.is_some() {
+        session.geo = geoip::lookup(peer.ip());
+        metrics::connection_to_country(session.geo.as_ref().map(|g| g.country.as_str()));
+    }
+}
+
+// 上位プロキシ経由で接続する
+// remote_dns ならドメイン名をそのまま渡し（名前解決は上位プロキシが行う）、
+// そうでなければ手元で名前解決（キャッシュを使う）したアドレスを順に上位プロキシへ依頼する
+fn open_via_upstream(
+    upstream: &str,
+    dst: &Dst,
+    config: &Config,
+    acl: Option<&Acl>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let candidates = match dst {
+        Dst::Domain(host, port) if !config.remote_dns => {
+            let addrs = dns::resolve(host, *port, config)?;
+            check_user_acl(acl, &addrs)?;
+            addrs.into_iter().map(Dst::from).collect()
+        }
+        // IP アドレス指定の宛先はそのまま判定できる（ドメイン名は上位プロキシが名前解決するため判定できない）
+        _ => vec![dst.clone()],
+    };
+    let mut last_err = None;
+    for candidate in &candidates {
+        if let Some(reason) = candidate.ip().and_then(|ip| blocked_address(ip, config)) {
+            info!("blocked by ruleset: {candidate} ({reason})");
+            last_err = Some(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("blocked destination address: {candidate} ({reason})"),
+            ));
+            continue;
+        }
+        match upstream::connect_via(upstream, candidate, config) {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                info!("connect to {candidate} via upstream failed: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(ErrorKind::AddrNotAvailable, "no addresses resolved")
+    }))
+}
+
+// 宛先を名前解決し、得られたアドレスへタイムアウト付きで接続する
+// block_private が有効なら、名前解決の後のアドレスでプライベート宛てを除外する（DNS rebinding 対策）
+// acl があれば、名前解決の後のアドレスの全てが ACL の networks に含まれる場合だけ接続する（同上）
+// アドレスが複数あれば（デュアルスタックのドメイン名など）、config.connect の進め方で並行に試す
+// ゾーンの無いリンクローカル IPv6 アドレスには ipv6_zone のゾーンを付ける
+// すべて失敗した場合は最後のエラーを返す（タイムアウトなら ErrorKind::TimedOut、
+// プライベート宛てしか無ければ PermissionDenied、ゾーンが決まらなければ HostUnreachable）
+pub(crate) fn connect_dst<A: ToSocketAddrs>(
+    addr: A,
+    config: &Config,
+    acl: Option<&Acl>,
+) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    check_user_acl(acl, &addrs)?;
+    let mut candidates = Vec::new();
+    let mut rejected = None;
+    for candidate in addrs {
+        if let Some(reason) = blocked_address(candidate.ip(), config) {
+            info!("blocked by ruleset: {candidate} ({reason})");
+            rejected = Some(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("blocked destination address: {candidate} ({reason})"),
+            ));
+            continue;
+        }
+        match with_default_zone(candidate, config) {
+            Ok(candidate) => candidates.push(candidate),
+            Err(e) => {
+                info!("{e}");
+                rejected = Some(e);
+            }
+        }
+    }
+    if candidates.is_empty() {
+        return Err(rejected.unwrap_or_else(|| {
+            io::Error::new(ErrorKind::AddrNotAvailable, "no addresses resolved")
+        }));
+    }
+    config.connect.connect(candidates, config.bind_source)
+}
+
+// 接続するアドレスのうち、ユーザの ACL の networks に含まれないものがあれば PermissionDenied を返す
+fn check_user_acl(acl: Option<&Acl>, addrs: &[SocketAddr]) -> io::Result<()> {
+    let Some(acl) = acl else {
+        return Ok(());
+    };
+    for addr in addrs {
+        if let Some(reason) = acl.denied_address(addr.ip()) {
+            info!("blocked by user ACL: {addr} ({reason})");
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("destination address {addr} not allowed for this user"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// "addr%zone" の形の IPv6 アドレス（ゾーンはインターフェース名か番号）なら、ゾーン付きのアドレスにする
+// それ以外の文字列なら None（通常のホスト名として名前解決する）
+fn scoped_ipv6(host: &str, port: u16) -> io::Result<Option<SocketAddr>> {
+    let Some((ip, zone)) = host.split_once('%') else {
+        return Ok(None);
+    };
+    let Ok(ip) = ip.parse::<Ipv6Addr>() else {
+        return Ok(None);
+    };
+    let scope_id = sockopt::interface_index(zone)?;
+    Ok(Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id))))
+}
+
+// ゾーンの無いリンクローカル IPv6 アドレスに、ipv6_zone のゾーンを付ける
+// ゾーンが決まらない場合（ipv6_zone が未指定）は、どのインターフェースへ送るか分からないため HostUnreachable
+pub(crate) fn with_default_zone(addr: SocketAddr, config: &Config) -> io::Result<SocketAddr> {
+    let SocketAddr::V6(mut v6) = addr else {
+        return Ok(addr);
+    };
+    if !v6.ip().is_unicast_link_local() || v6.scope_id() != 0 {
+        return Ok(addr);
+    }
+    let Some(zone) = &config.ipv6_zone else {
+        return Err(io::Error::new(
+            ErrorKind::HostUnreachable,
+            format!("link-local destination {addr} needs a zone (set --ipv6-zone)"),
+        ));
+    };
+    v6.set_scope_id(sockopt::interface_index(zone)?);
+    Ok(SocketAddr::V6(v6))
+}
+
+// 既定の方法の優先順: 0x01=GSSAPI、0x02=ユーザ/パスワード、0x00=No Auth
+const DEFAULT_METHODS: [u8; 3] = [0x01, 0x02, 0x00];
+
+// 優先順（config.methods、空なら DEFAULT_METHODS）で最初の、クライアントが提示していて使える方法
+// 使えるのは、GSSAPI はアクセプタが設定されている場合、ユーザ/パスワードは認証ありの場合、
+// No Auth は認証必須（require_auth）でない場合だけ。どれも無ければ 0xFF
+fn select_method(config: &Config, offered: &[u8]) -> u8 {
+    let usable = |method: u8| match method {
+        0x00 => !config.require_auth,
+        0x01 => gssapi_configured(config),
+        0x02 => config.auth,
+        _ => false,
+    };
+    let order = match config.methods.as_slice() {
+        [] => &DEFAULT_METHODS[..],
+        methods => methods,
+    };
+    order
+        .iter()
+        .copied()
+        .find(|&m| offered.contains(&m) && usable(m))
+        .unwrap_or(0xFF)
+}
+
+// No Auth を選べるか（認証の無い SOCKS4・HTTP CONNECT は、選べるときだけ受け付ける）
+// 選べなければ、ユーザの ACL・転送量の上限を確かめずに宛先へ接続させることになる
+pub(crate) fn no_auth_allowed(config: &Config) -> bool {
+    select_method(config, &[0x00]) == 0x00
+}
+
+// GSSAPI の認証が設定されているか
+#[cfg(feature = "gssapi")]
+fn gssapi_configured(config: &Config) -> bool {
+    config.gssapi.is_some()
+}
+
+// feature "gssapi" が無ければ、GSSAPI は選ばない
+#[cfg(not(feature = "gssapi"))]
+fn gssapi_configured(_config: &Config) -> bool {
+    false
+}
+
+// 送信元アドレスが許可されているか（拒否リストが優先）
+fn client_allowed(config: &Config, ip: IpAddr) -> bool {
+    if config.client_deny.iter().any(|c| c.contains(ip)) {
+        return false;
+    }
+    config.client_allow.is_empty() || config.client_allow.iter().any(|c| c.contains(ip))
+}
+
+// now から after だけ後の時刻。Instant で表せない長さ（Duration::MAX など）は約 100 年後とする
+// 設定の秒数をそのまま足すと、大きすぎる値で panic するため
+pub(crate) fn deadline_after(now: Instant, after: Duration) -> Instant {
+    const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+    now.checked_add(after).or_else(|| now.checked_add(FAR_FUTURE)).unwrap_or(now)
+}
+
+// 読み取り待ちのデータがあるかを、ブロックせずに peek で確認する
+fn has_pending_data<S: ClientStream>(stream: &S) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut probe = [0u8; 1];
+    let res = stream.peek(&mut probe);
+    stream.set_nonblocking(false)?;
+    match res {
+        Ok(n) => Ok(n > 0),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,485 @@
+// レベル付きのログ出力（外部クレートを使わない簡易版）
+// 出力するレベルは環境変数 RUST_LOG で指定する（error / warn / info / debug。既定は info）
+// error と warn は標準エラー、それ以外は標準出力へ書く
+// 接続を処理中のスレッドでは、メッセージの先頭に接続 ID（[conn 42]）を付ける
+// --log-format json では、1 行に 1 つの JSON オブジェクト（timestamp・level・conn_id・message と、
+// 接続終了時の client・dest・bytes などの項目）として書く
+// --log-file を指定すると、全てのレベルをそのファイルへ書く（テキストの行には時刻を付ける）
+// ファイルが上限の大きさを超えそうになったら PATH.1, PATH.2, ... へ順に送り、新しいファイルに書く
+// --syslog では、レベルを severity に対応させてローカルの syslog（/dev/log）へも送る
+// 認証の試行などの監査記録は、レベルに関係なく [AUDIT] の行として書く（--audit-log で別のファイルへ）
+use std::cell::Cell;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write as _};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::process;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn parse(s: &str) -> Option<Level> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            // trace は debug と同じ扱い
+            "debug" | "trace" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+// ログの書式（既定はテキスト）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub(crate) fn parse(s: &str) -> Option<LogFormat> {
+        match s {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// 以後のログの書式を切り替える（引数・設定ファイルの解析中から効くよう、読んだ時点で呼ぶ）
+pub(crate) fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+pub(crate) fn json_format() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+// RUST_LOG から最大レベルを決める（最初に読んだ値を使い続ける）
+// env_logger と同じく "debug" や "seccamp2025mini_online=debug" の形式を受け付ける
+fn max_level() -> Level {
+    static MAX: OnceLock<Level> = OnceLock::new();
+    *MAX.get_or_init(|| {
+        env::var("RUST_LOG")
+            .ok()
+            .and_then(|v| {
+                v.split(',')
+                    .find_map(|item| Level::parse(item.rsplit('=').next().unwrap_or(item)))
+            })
+            .unwrap_or(Level::Info)
+    })
+}
+
+thread_local! {
+    static CONN_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+// このスレッドのログに付ける接続 ID を設定する（None で解除）
+// 接続の処理から起こしたスレッドでも、同じ ID を設定して使う
+pub(crate) fn set_conn_id(id: Option<u64>) {
+    CONN_ID.with(|c| c.set(id));
+}
+
+pub(crate) fn conn_id() -> Option<u64> {
+    CONN_ID.with(|c| c.get())
+}
+
+pub(crate) fn enabled(level: Level) -> bool {
+    level <= max_level()
+}
+
+// マクロから呼ばれる出力関数
+pub(crate) fn write(level: Level, args: fmt::Arguments<'_>) {
+    if !enabled(level) {
+        return;
+    }
+    if json_format() {
+        return write_json(level, &args.to_string(), &[]);
+    }
+    let prefix = match conn_id() {
+        Some(id) => format!("[{}] [conn {id}]", level.as_str()),
+        None => format!("[{}]", level.as_str()),
+    };
+    emit(level, &format!("{prefix} {args}"), true);
+}
+
+// JSON のログの項目の値
+pub(crate) enum Field<'a> {
+    Str(&'a str),
+    Num(u64),
+}
+
+// JSON の 1 行を書く（項目の並びは timestamp・level・conn_id・message・fields の順）
+pub(crate) fn write_json(level: Level, message: &str, fields: &[(&str, Field<'_>)]) {
+    if !enabled(level) {
+        return;
+    }
+    emit(level, &json_line(level.as_str(), message, fields), false);
+}
+
+// 監査記録（認証の試行など）を書く。RUST_LOG のレベルに関係なく必ず書き、
+// テキストでは [AUDIT]、JSON では "level":"AUDIT" の行にする
+// 監査ログのファイル（--audit-log）があればそこへ、無ければ通常のログと同じ出力先へ書く
+pub(crate) fn audit(message: &str, fields: &[(&str, Field<'_>)]) {
+    let json = json_format();
+    let line = if json {
+        json_line("AUDIT", message, fields)
+    } else {
+        let mut line = match conn_id() {
+            Some(id) => format!("[AUDIT] [conn {id}] {message}"),
+            None => format!("[AUDIT] {message}"),
+        };
+        for (key, value) in fields {
+            let _ = match value {
+                Field::Str(s) => write!(line, " {key}={s:?}"),
+                Field::Num(n) => write!(line, " {key}={n}"),
+            };
+        }
+        line
+    };
+    if let Some(file) = lock_audit_file().as_mut() {
+        if json {
+            file.write_line(&line);
+        } else {
+            file.write_line(&format!("{} {line}", timestamp(SystemTime::now())));
+        }
+        return;
+    }
+    emit(Level::Info, &line, !json);
+}
+
+// JSON の 1 行を作る（level は "INFO" などの表記）
+fn json_line(level: &str, message: &str, fields: &[(&str, Field<'_>)]) -> String {
+    let mut line = format!(
+        "{{\"timestamp\":\"{}\",\"level\":\"{level}\"",
+        timestamp(SystemTime::now())
+    );
+    if let Some(id) = conn_id() {
+        let _ = write!(line, ",\"conn_id\":{id}");
+    }
+    let _ = write!(line, ",\"message\":{}", quote(message));
+    for (key, value) in fields {
+        match value {
+            Field::Str(s) => {
+                let _ = write!(line, ",{}:{}", quote(key), quote(s));
+            }
+            Field::Num(n) => {
+                let _ = write!(line, ",{}:{n}", quote(key));
+            }
+        }
+    }
+    line.push('}');
+    line
+}
+
+// syslog・ログファイル・標準出力（標準エラー）へ書く
+// syslog を使うときは、ログファイルが無ければ標準出力・標準エラーへは書かない
+// stamp ならファイルの行の先頭に時刻を付ける（syslog では受け取った側が付ける）
+fn emit(level: Level, line: &str, stamp: bool) {
+    #[cfg(unix)]
+    let to_syslog = match lock_syslog().as_mut() {
+        Some(syslog) => {
+            syslog.send(level, line);
+            true
+        }
+        None => false,
+    };
+    #[cfg(not(unix))]
+    let to_syslog = false;
+    if let Some(file) = lock_file().as_mut() {
+        if stamp {
+            file.write_line(&format!("{} {line}", timestamp(SystemTime::now())));
+        } else {
+            file.write_line(line);
+        }
+        return;
+    }
+    if to_syslog {
+        return;
+    }
+    if level <= Level::Warn {
+        eprintln!("{line}");
+    } else {
+        println!("{line}");
+    }
+}
+
+// ローカルの syslog のソケット（/dev/log）への送信
+#[cfg(unix)]
+struct Syslog {
+    socket: UnixDatagram,
+    facility: u8,
+}
+
+#[cfg(unix)]
+const SYSLOG_PATH: &str = "/dev/log";
+
+#[cfg(unix)]
+static SYSLOG: Mutex<Option<Syslog>> = Mutex::new(None);
+
+#[cfg(unix)]
+fn lock_syslog() -> MutexGuard<'static, Option<Syslog>> {
+    match SYSLOG.lock() {
+        Ok(s) => s,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// syslog の facility の名前を番号にする
+pub(crate) fn syslog_facility(name: &str) -> Option<u8> {
+    let code = match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        _ => {
+            let n: u8 = name.strip_prefix("local")?.parse().ok()?;
+            return (n <= 7).then_some(16 + n);
+        }
+    };
+    Some(code)
+}
+
+// 以後のログを syslog へ送る（起動時に 1 度だけ呼ぶ。facility は syslog_facility で確かめた名前）
+#[cfg(unix)]
+pub(crate) fn open_syslog(facility: &str) -> io::Result<()> {
+    let facility = syslog_facility(facility).ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidInput, format!("unknown syslog facility {facility:?}"))
+    })?;
+    let socket = connect_syslog()?;
+    *lock_syslog() = Some(Syslog { socket, facility });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn open_syslog(_facility: &str) -> io::Result<()> {
+    Err(io::Error::new(ErrorKind::Unsupported, "syslog is not supported on this platform"))
+}
+
+#[cfg(unix)]
+fn connect_syslog() -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .connect(SYSLOG_PATH)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot connect to {SYSLOG_PATH}: {e}")))?;
+    Ok(socket)
+}
+
+#[cfg(unix)]
+impl Syslog {
+    // "<PRI>TAG[PID]: MESSAGE" の形で送る（syslogd の再起動でソケットが変わった場合は 1 度だけ繋ぎ直す）
+    fn send(&mut self, level: Level, line: &str) {
+        // レベルを severity にする（err=3, warning=4, info=6, debug=7）
+        let severity = match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        };
+        let message = format!(
+            "<{}>{}[{}]: {line}",
+            self.facility * 8 + severity,
+            env!("CARGO_PKG_NAME"),
+            process::id()
+        );
+        if self.socket.send(message.as_bytes()).is_ok() {
+            return;
+        }
+        let res = connect_syslog().and_then(|socket| {
+            self.socket = socket;
+            self.socket.send(message.as_bytes())
+        });
+        if let Err(e) = res {
+            eprintln!("[ERROR] failed to send to syslog: {e}\n{line}");
+        }
+    }
+}
+
+// ログファイル（全てのスレッドが 1 つを共有し、1 行ずつ排他して書く）
+struct LogFile {
+    path: String,
+    file: File,
+    // 今のファイルの大きさ（バイト）
+    size: u64,
+    // この大きさを超える前に新しいファイルへ切り替える（0 なら切り替えない）
+    max_size: u64,
+    // 残しておく古いファイルの数（PATH.1 が最も新しい）
+    max_files: usize,
+}
+
+static FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+// 監査ログのファイル（切り替えずに追記し続ける）
+static AUDIT_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+fn lock_file() -> MutexGuard<'static, Option<LogFile>> {
+    match FILE.lock() {
+        Ok(f) => f,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn lock_audit_file() -> MutexGuard<'static, Option<LogFile>> {
+    match AUDIT_FILE.lock() {
+        Ok(f) => f,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// 以後のログを path へ追記する（起動時に 1 度だけ呼ぶ）
+pub(crate) fn open_file(path: &str, max_size: u64, max_files: usize) -> io::Result<()> {
+    *lock_file() = Some(LogFile::open(path, max_size, max_files)?);
+    Ok(())
+}
+
+// 以後の監査記録を path へ追記する（起動時に 1 度だけ呼ぶ）
+pub(crate) fn open_audit_file(path: &str) -> io::Result<()> {
+    *lock_audit_file() = Some(LogFile::open(path, 0, 0)?);
+    Ok(())
+}
+
+impl LogFile {
+    fn open(path: &str, max_size: u64, max_files: usize) -> io::Result<LogFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot open log file {path}: {e}")))?;
+        let size = file.metadata()?.len();
+        Ok(LogFile { path: path.to_string(), file, size, max_size, max_files })
+    }
+
+    // 書けなかった場合は、その行を標準エラーへ出す（ログのためにサーバを止めない）
+    fn write_line(&mut self, line: &str) {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0
+            && self.size > 0
+            && self.size + len > self.max_size
+            && let Err(e) = self.rotate()
+        {
+            eprintln!("[ERROR] failed to rotate log file {}: {e}", self.path);
+        }
+        match writeln!(self.file, "{line}") {
+            Ok(()) => self.size += len,
+            Err(e) => eprintln!("[ERROR] failed to write log file {}: {e}\n{line}", self.path),
+        }
+    }
+
+    // PATH.(n-1) -> PATH.n, ..., PATH -> PATH.1 と送り、空の PATH を開き直す
+    // 古いファイルを残さない設定（max_files=0）では PATH を空にする
+    fn rotate(&mut self) -> io::Result<()> {
+        for i in (1..self.max_files).rev() {
+            match fs::rename(format!("{}.{i}", self.path), format!("{}.{}", self.path, i + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// JSON の文字列にする
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// RFC 3339 形式の UTC の時刻（ミリ秒まで。例: 2025-08-01T12:34:56.789Z）
+fn timestamp(now: SystemTime) -> String {
+    let since = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    let rem = secs % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
+
+// 1970-01-01 からの日数を (年, 月, 日) にする（グレゴリオ暦）
+pub(crate) fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // 3 月 1 日始まりの 400 年周期で数える
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
@@ -0,0 +1,229 @@
+// 監視用のカウンタと、それを Prometheus のテキスト形式で返す HTTP エンドポイント（--metrics-listen）
+// および StatsD へ定期的に送る UDP パケット（--statsd）
+// カウンタはプロセス全体で共有する
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{
+    Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::slowlog;
+
+static CONNECTIONS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_ACTIVE: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+static CONNECT_ERRORS: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_FILTERED: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_SHED: AtomicU64 = AtomicU64::new(0);
+// --geoip-db で引いた宛先の国ごとの接続数（国コードの数しか増えない）
+static CONNECTIONS_BY_COUNTRY: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+// 接続の処理を始めた
+pub(crate) fn connection_opened() {
+    CONNECTIONS_TOTAL.fetch_add(1, Ordering::Relaxed);
+    CONNECTIONS_ACTIVE.fetch_add(1, Ordering::Relaxed);
+}
+
+// 接続の処理が終わった
+pub(crate) fn connection_closed() {
+    CONNECTIONS_ACTIVE.fetch_sub(1, Ordering::Relaxed);
+}
+
+// 転送量のカウンタ（転送中に随時加算し、長い接続の転送量も閉じるのを待たずに見えるようにする）
+pub(crate) fn bytes_sent() -> &'static AtomicU64 {
+    &BYTES_SENT
+}
+
+pub(crate) fn bytes_received() -> &'static AtomicU64 {
+    &BYTES_RECEIVED
+}
+
+pub(crate) fn auth_failed() {
+    AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn connect_failed() {
+    CONNECT_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+// accept_filter で拒否した
+pub(crate) fn connection_filtered() {
+    CONNECTIONS_FILTERED.fetch_add(1, Ordering::Relaxed);
+}
+
+// 出力するメトリクス（名前, 種類, 説明, 値）。Prometheus と StatsD のどちらもこの表から出す
+static METRICS: [(&str, &str, &str, &AtomicU64); 8] = [
+    ("socks5_connections_total", "counter", "Connections accepted.", &CONNECTIONS_TOTAL),
+    ("socks5_connections_active", "gauge", "Connections being handled.", &CONNECTIONS_ACTIVE),
+    (
+        "socks5_bytes_sent_total",
+        "counter",
+        "Bytes forwarded from clients to destinations.",
+        &BYTES_SENT,
+    ),
+    (
+        "socks5_bytes_received_total",
+        "counter",
+        "Bytes forwarded from destinations to clients.",
+        &BYTES_RECEIVED,
+    ),
+    ("socks5_auth_failures_total", "counter", "Failed authentications.", &AUTH_FAILURES),
+    ("socks5_connect_errors_total", "counter", "Failed destination connects.", &CONNECT_ERRORS),
+    (
+        "socks5_connections_filtered_total",
+        "counter",
+        "Connections rejected by the accept filter.",
+        &CONNECTIONS_FILTERED,
+    ),
+    (
+        "socks5_connections_shed_total",
+        "counter",
+        "Connections closed because the worker queue was full.",
+        &CONNECTIONS_SHED,
+    ),
+];
+
+// 宛先へ接続した（country はデータベースに含まれなかったら None）
+pub(crate) fn connection_to_country(country: Option<&str>) {
+    let mut counts = CONNECTIONS_BY_COUNTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    *counts.entry(country.unwrap_or("unknown").to_string()).or_default() += 1;
+}
+
+// ワーカーの待ち行列が満杯で閉じた
+pub(crate) fn connection_shed() {
+    CONNECTIONS_SHED.fetch_add(1, Ordering::Relaxed);
+}
+
+// Prometheus のテキスト形式（version 0.0.4）
+fn render() -> String {
+    let mut out = String::new();
+    for (name, kind, help, value) in &METRICS {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+    }
+    let counts = CONNECTIONS_BY_COUNTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    if !counts.is_empty() {
+        let name = "socks5_connections_by_country_total";
+        let _ = writeln!(out, "# HELP {name} Destination connects by GeoIP country.");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (country, n) in counts.iter() {
+            let _ = writeln!(out, "{name}{{country=\"{country}\"}} {n}");
+        }
+    }
+    out
+}
+
+// StatsD へ送れなかったことのログは、この間隔に 1 回までにする
+const STATSD_WARN_EVERY: Duration = Duration::from_secs(60);
+
+// StatsD の形式の 1 パケット分と、今の値を返す
+// カウンタは前回送った値（last）から増えた分を |c で、ゲージは今の値を |g で出す
+fn render_statsd(last: &[u64; METRICS.len()]) -> (String, [u64; METRICS.len()]) {
+    let mut out = String::new();
+    let mut now = [0u64; METRICS.len()];
+    for (i, (name, kind, _, value)) in METRICS.iter().enumerate() {
+        now[i] = value.load(Ordering::Relaxed);
+        if *kind == "gauge" {
+            let _ = writeln!(out, "{name}:{}|g", now[i]);
+        } else {
+            let _ = writeln!(out, "{name}:{}|c", now[i].saturating_sub(last[i]));
+        }
+    }
+    (out, now)
+}
+
+// interval ごとにメトリクスを StatsD の UDP パケットとして addr へ送るスレッドを起動する
+// 送れなくても転送には影響しない（送れなかった分の増加は次のパケットにまとめて入る）
+pub(crate) fn spawn_statsd(addr: &str, interval: Duration) -> io::Result<()> {
+    let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(ErrorKind::AddrNotAvailable, format!("{addr} did not resolve"))
+    })?;
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(target)?;
+    info!("sending StatsD metrics to {target} every {interval:?}");
+    thread::spawn(move || {
+        let mut last = [0u64; METRICS.len()];
+        let mut warned: Option<Instant> = None;
+        let mut suppressed = 0u64;
+        loop {
+            thread::sleep(interval);
+            let (packet, now) = render_statsd(&last);
+            match socket.send(packet.as_bytes()) {
+                Ok(_) => last = now,
+                Err(e) if warned.is_none_or(|t| t.elapsed() >= STATSD_WARN_EVERY) => {
+                    warn!(
+                        "cannot send StatsD metrics to {target}: {e} \
+                         ({suppressed} earlier failure(s) not logged)"
+                    );
+                    warned = Some(Instant::now());
+                    suppressed = 0;
+                }
+                Err(_) => suppressed += 1,
+            }
+        }
+    });
+    Ok(())
+}
+
+// メトリクス用の HTTP サーバをバックグラウンドのスレッドで起動する
+// GET /metrics と、遅かった接続の一覧の GET /slow にだけ応答し、それ以外は 404 を返す
+// リクエストごとにスレッドで応答する（遅いクライアントがいても、他の収集を待たせない）
+pub(crate) fn spawn_endpoint(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("metrics endpoint on http://{}/metrics", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    thread::spawn(move || {
+                        if let Err(e) = serve_http(stream) {
+                            debug!("metrics request failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("metrics accept error: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+// 1 回のリクエストに応答して閉じる（リクエスト行とヘッダは 8 KiB まで読む）
+fn serve_http(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let line = String::from_utf8_lossy(&request);
+    let mut parts = line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), Some("/slow")) => ("200 OK", slowlog::render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
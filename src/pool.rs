@@ -0,0 +1,102 @@
+// 接続処理用の固定サイズのスレッドプール（待ち行列も上限付き）
+// 待ち行列が満杯のときは ShedPolicy に従って、新しい仕事か最も古い仕事を押し出す
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::ShedPolicy;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// 待ち行列に入れる仕事と、押し出されたときの後始末（接続を閉じて数える）
+struct Task {
+    run: Job,
+    shed: Job,
+}
+
+impl Task {
+    // 仕事（が持つ接続など）を先に破棄してから、後始末を行う
+    fn shed(self) {
+        drop(self.run);
+        (self.shed)();
+    }
+}
+
+pub(crate) struct ThreadPool {
+    sender: SyncSender<Task>,
+    receiver: Arc<Mutex<Receiver<Task>>>,
+}
+
+impl ThreadPool {
+    // workers 本のワーカースレッドと、queue_capacity 件までの待ち行列を用意する
+    pub(crate) fn new(workers: usize, queue_capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Task>(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || worker(receiver));
+        }
+        ThreadPool { sender, receiver }
+    }
+
+    // 仕事を待ち行列に入れる。満杯なら policy に従って、job か最も古い仕事の shed を呼ぶ
+    // Oldest で古い仕事を押し出しても（他の受け付けのスレッドに先を越されて）入らなければ、job も押し出す
+    pub(crate) fn execute<F, S>(&self, job: F, shed: S, policy: ShedPolicy)
+    where
+        F: FnOnce() + Send + 'static,
+        S: FnOnce() + Send + 'static,
+    {
+        let mut task = Task { run: Box::new(job), shed: Box::new(shed) };
+        let mut attempts = if policy == ShedPolicy::Oldest { 2 } else { 1 };
+        loop {
+            task = match self.sender.try_send(task) {
+                Ok(()) => return,
+                Err(TrySendError::Full(task)) | Err(TrySendError::Disconnected(task)) => task,
+            };
+            attempts -= 1;
+            if attempts == 0 {
+                break;
+            }
+            // 満杯の間はワーカーが受信を待っていないため、ロックはすぐに取れる
+            let oldest = match self.receiver.lock() {
+                Ok(rx) => rx.try_recv().ok(),
+                Err(_) => None,
+            };
+            match oldest {
+                Some(oldest) => oldest.shed(),
+                None => break,
+            }
+        }
+        task.shed();
+    }
+}
+
+impl Clone for ThreadPool {
+    fn clone(&self) -> Self {
+        ThreadPool {
+            sender: self.sender.clone(),
+            receiver: Arc::clone(&self.receiver),
+        }
+    }
+}
+
+// 待ち行列から仕事を取り出して実行する（送信側がすべて破棄されたら終了）
+fn worker(receiver: Arc<Mutex<Receiver<Task>>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(rx) => rx.recv(),
+            Err(_) => return,
+        };
+        match job {
+            // 仕事が panic してもワーカーは失わない
+            Ok(Task { run, shed }) => {
+                drop(shed);
+                if panic::catch_unwind(AssertUnwindSafe(run)).is_err() {
+                    error!("worker: connection handler panicked");
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
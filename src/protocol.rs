@@ -0,0 +1,426 @@
+// SOCKS5 のメッセージ解析と応答の組み立て（ソケットに依存しない純粋な関数）
+use std::fmt;
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::error::SocksError;
+use crate::upstream::UpstreamReply;
+
+// 宛先（DST.ADDR と DST.PORT）
+#[derive(Clone)]
+pub(crate) enum Dst {
+    V4([u8; 4], u16),
+    V6([u8; 16], u16),
+    Domain(String, u16),
+}
+
+impl Dst {
+    pub(crate) fn port(&self) -> u16 {
+        match self {
+            Dst::V4(_, port) | Dst::V6(_, port) | Dst::Domain(_, port) => *port,
+        }
+    }
+
+    // IP アドレス指定ならそのアドレス（ドメイン名なら None）
+    pub(crate) fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Dst::V4(ip, _) => Some(IpAddr::V4(Ipv4Addr::from(*ip))),
+            Dst::V6(ip, _) => Some(IpAddr::V6(Ipv6Addr::from(*ip))),
+            Dst::Domain(..) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for Dst {
+    fn from(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(a) => Dst::V4(a.ip().octets(), a.port()),
+            SocketAddr::V6(a) => Dst::V6(a.ip().octets(), a.port()),
+        }
+    }
+}
+
+// ログ用の表記（host:port、IPv6 は [addr]:port）
+impl fmt::Display for Dst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dst::V4(ip, port) => write!(f, "{}:{}", Ipv4Addr::from(*ip), port),
+            Dst::V6(ip, port) => write!(f, "[{}]:{}", Ipv6Addr::from(*ip), port),
+            Dst::Domain(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+// 認証方法の名前（none・gssapi・userpass）か、0x02 のような番号
+pub(crate) fn parse_method(name: &str) -> Option<u8> {
+    match name {
+        "none" | "no-auth" => Some(0x00),
+        "gssapi" => Some(0x01),
+        "userpass" | "password" => Some(0x02),
+        _ => u8::from_str_radix(name.strip_prefix("0x")?, 16).ok(),
+    }
+}
+
+// Greeting の解析: [VER, NMETHODS, METHODS] から METHODS を取り出す
+pub(crate) fn parse_greeting(buf: &[u8]) -> io::Result<Vec<u8>> {
+    if buf.len() < 2 {
+        return Err(SocksError::MalformedRequest(format!(
+            "truncated greeting: {} of 2 header bytes",
+            buf.len()
+        ))
+        .into_io(ErrorKind::UnexpectedEof));
+    }
+    let ver = buf[0];
+    if ver != 0x05 {
+        return Err(SocksError::UnsupportedVersion(format!(
+            "malformed at greeting byte 0: VER=0x{ver:02X}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    let nmethods = buf[1] as usize;
+    let methods = &buf[2..];
+    if methods.len() < nmethods {
+        return Err(SocksError::MalformedRequest(format!(
+            "truncated greeting: NMETHODS={nmethods} but {} method bytes",
+            methods.len()
+        ))
+        .into_io(ErrorKind::UnexpectedEof));
+    }
+    if methods.len() > nmethods {
+        return Err(SocksError::MalformedRequest(format!(
+            "malformed at greeting byte {}: unexpected trailing data",
+            2 + nmethods
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    Ok(methods.to_vec())
+}
+
+// Request ヘッダの解析: [VER, CMD, RSV, ATYP] から (CMD, ATYP) を返す
+// 不正なバイトの位置（オフセット）をエラーに含める
+pub(crate) fn parse_request_header(hdr: &[u8; 4]) -> io::Result<(u8, u8)> {
+    let [ver, cmd, rsv, atyp] = *hdr;
+    if ver != 0x05 {
+        return Err(SocksError::UnsupportedVersion(format!(
+            "malformed at request header byte 0: VER=0x{ver:02X}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    if rsv != 0x00 {
+        return Err(SocksError::MalformedRequest(format!(
+            "malformed at request header byte 2: RSV=0x{rsv:02X}"
+        ))
+        .into_io(ErrorKind::InvalidData));
+    }
+    Ok((cmd, atyp))
+}
+
+// DST.ADDR と DST.PORT を読む（ATYP に応じて可変長）
+// ドメイン名の LEN が 0、または max_domain_len を超える場合は、名前を読まずに InvalidData を返す
+pub(crate) fn read_dst<R: Read>(
+    atyp: u8,
+    reader: &mut R,
+    max_domain_len: usize,
+) -> io::Result<Dst> {
+    match atyp {
+        0x01 => {
+            // IPv4
+            let mut ip4 = [0u8; 4];
+            reader.read_exact(&mut ip4)?;
+            let port = read_port(reader)?;
+            Ok(Dst::V4(ip4, port))
+        }
+        0x03 => {
+            // DOMAIN: [LEN, NAME(LEN バイト)]
+            let mut len = [0u8; 1];
+            reader.read_exact(&mut len)?;
+            if len[0] == 0 {
+                return Err(SocksError::MalformedRequest(
+                    "malformed at request byte 4: domain name LEN=0".to_string(),
+                )
+                .into_io(ErrorKind::InvalidData));
+            }
+            if len[0] as usize > max_domain_len {
+                return Err(SocksError::MalformedRequest(format!(
+                    "malformed at request byte 4: domain name LEN={} exceeds the limit of {}",
+                    len[0], max_domain_len
+                ))
+                .into_io(ErrorKind::InvalidData));
+            }
+            let mut name = vec![0u8; len[0] as usize];
+            reader.read_exact(&mut name)?;
+            let port = read_port(reader)?;
+            let host = String::from_utf8_lossy(&name).into_owned();
+            Ok(Dst::Domain(host, port))
+        }
+        0x04 => {
+            // IPv6
+            let mut ip6 = [0u8; 16];
+            reader.read_exact(&mut ip6)?;
+            let port = read_port(reader)?;
+            Ok(Dst::V6(ip6, port))
+        }
+        other => Err(SocksError::UnsupportedAtyp(other).into_io(ErrorKind::InvalidData)),
+    }
+}
+
+// DST.PORT（ネットワークバイトオーダーの 2 バイト）を読む
+fn read_port<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut p = [0u8; 2];
+    reader.read_exact(&mut p)?;
+    Ok(u16::from_be_bytes(p))
+}
+
+// エラー応答を組み立てる: [VER, REP, RSV, ATYP, BND.ADDR, BND.PORT]
+// ATYP はリクエストの ATYP に合わせ、IPv6 なら 0x04 のゼロアドレス、
+// それ以外（IPv4・DOMAIN・不明）は 0x01 のゼロアドレスにフォールバックする
+pub(crate) fn build_error_reply(rep: u8, atyp: u8) -> Vec<u8> {
+    let mut reply = Vec::with_capacity(4 + 16 + 2);
+    reply.push(0x05); // VER
+    reply.push(rep); // REP
+    reply.push(0x00); // RSV
+    if atyp == 0x04 {
+        reply.push(0x04); // ATYP=IPv6
+        reply.extend_from_slice(&[0u8; 16]); // BND.ADDR
+    } else {
+        reply.push(0x01); // ATYP=IPv4
+        reply.extend_from_slice(&[0, 0, 0, 0]); // BND.ADDR
+    }
+    reply.extend_from_slice(&[0, 0]); // BND.PORT
+    reply
+}
+
+// 接続失敗の io::Error を SOCKS5 の REP コードに対応付ける
+// 上位プロキシ（--upstream）が返した REP はそのまま使う
+// 対応する種別が無いものは General failure (0x01)
+pub(crate) fn reply_code_for(err: &io::Error) -> u8 {
+    if let Some(UpstreamReply(rep)) = err.get_ref().and_then(|e| e.downcast_ref()) {
+        return *rep;
+    }
+    match err.kind() {
+        ErrorKind::PermissionDenied => 0x02,   // Connection not allowed by ruleset
+        ErrorKind::NetworkUnreachable => 0x03, // Network unreachable
+        ErrorKind::HostUnreachable | ErrorKind::AddrNotAvailable => 0x04, // Host unreachable
+        ErrorKind::ConnectionRefused => 0x05,  // Connection refused
+        ErrorKind::TimedOut => 0x06,           // TTL expired
+        _ => 0x01,                             // General failure
+    }
+}
+
+// 成功応答を組み立てる: [VER, REP, RSV, ATYP, BND.ADDR, BND.PORT]
+// 順に push し、ATYP は実アドレス種別で選択
+pub(crate) fn build_success_reply(bound_addr: SocketAddr) -> Vec<u8> {
+    let mut response = Vec::with_capacity(4 + 16 + 2);
+    response.push(0x05); // VER
+    response.push(0x00); // REP = succeeded
+    response.push(0x00); // RSV
+    push_socket_addr(&mut response, bound_addr);
+    response
+}
+
+// ATYP・アドレス・ポートを追記する（IPv4 なら 0x01、IPv6 なら 0x04）
+pub(crate) fn push_socket_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr {
+        SocketAddr::V4(a) => {
+            buf.push(0x01); // ATYP=IPv4
+            buf.extend_from_slice(&a.ip().octets());
+            buf.extend_from_slice(&a.port().to_be_bytes());
+        }
+        SocketAddr::V6(a) => {
+            buf.push(0x04); // ATYP=IPv6
+            buf.extend_from_slice(&a.ip().octets());
+            buf.extend_from_slice(&a.port().to_be_bytes());
+        }
+    }
+}
+
+// ATYP・DST.ADDR・DST.PORT を追記する（Request を上位プロキシへ中継する場合など）
+pub(crate) fn push_dst(buf: &mut Vec<u8>, dst: &Dst) -> io::Result<()> {
+    match dst {
+        Dst::V4(ip, port) => {
+            buf.push(0x01); // ATYP=IPv4
+            buf.extend_from_slice(ip);
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+        Dst::V6(ip, port) => {
+            buf.push(0x04); // ATYP=IPv6
+            buf.extend_from_slice(ip);
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+        Dst::Domain(host, port) => {
+            let len = u8::try_from(host.len()).map_err(|_| {
+                io::Error::new(ErrorKind::InvalidInput, format!("hostname too long: {host}"))
+            })?;
+            buf.push(0x03); // ATYP=DOMAIN
+            buf.push(len);
+            buf.extend_from_slice(host.as_bytes());
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(atyp: u8, input: &[u8]) -> io::Result<Dst> {
+        read_dst(atyp, &mut &input[..], 255)
+    }
+
+    // LEN と名前・ポートの DOMAIN 指定の入力
+    fn domain_input(name: &[u8], port: u16) -> Vec<u8> {
+        let mut input = vec![name.len() as u8];
+        input.extend_from_slice(name);
+        input.extend_from_slice(&port.to_be_bytes());
+        input
+    }
+
+    #[test]
+    fn parse_greeting_returns_the_methods() {
+        assert_eq!(parse_greeting(&[0x05, 0x02, 0x00, 0x02]).unwrap(), [0x00, 0x02]);
+        // NMETHODS=0 は空の一覧になり、選べるメソッドが無いとして 0xFF で断られる
+        assert_eq!(parse_greeting(&[0x05, 0x00]).unwrap(), []);
+    }
+
+    #[test]
+    fn parse_greeting_rejects_truncated_input() {
+        let cases: [(&[u8], &str); 4] = [
+            (&[], "truncated greeting: 0 of 2 header bytes"),
+            (&[0x05], "truncated greeting: 1 of 2 header bytes"),
+            (&[0x05, 0x03, 0x00, 0x02], "truncated greeting: NMETHODS=3 but 2 method bytes"),
+            (&[0x05, 0x01], "truncated greeting: NMETHODS=1 but 0 method bytes"),
+        ];
+        for (input, message) in cases {
+            let err = parse_greeting(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{input:?}");
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn parse_greeting_rejects_wrong_version_and_trailing_data() {
+        for ver in [0x04, 0x00, 0xFF] {
+            let err = parse_greeting(&[ver, 0x01, 0x00]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), format!("malformed at greeting byte 0: VER=0x{ver:02X}"));
+        }
+        let err = parse_greeting(&[0x05, 0x01, 0x00, 0x02]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "malformed at greeting byte 3: unexpected trailing data");
+    }
+
+    #[test]
+    fn parse_request_header_checks_version_and_reserved_byte() {
+        assert_eq!(parse_request_header(&[0x05, 0x01, 0x00, 0x03]).unwrap(), (0x01, 0x03));
+        let err = parse_request_header(&[0x04, 0x01, 0x00, 0x01]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "malformed at request header byte 0: VER=0x04");
+        let err = parse_request_header(&[0x05, 0x01, 0x01, 0x01]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "malformed at request header byte 2: RSV=0x01");
+    }
+
+    #[test]
+    fn parse_request_header_leaves_unknown_commands_to_the_caller() {
+        // 未知の CMD・ATYP はヘッダの形としては正しく、呼び出し側が REP=0x07・0x08 で断る
+        for cmd in [0x00, 0x04, 0xFF] {
+            assert_eq!(parse_request_header(&[0x05, cmd, 0x00, 0x01]).unwrap(), (cmd, 0x01));
+        }
+        assert_eq!(parse_request_header(&[0x05, 0x01, 0x00, 0x07]).unwrap(), (0x01, 0x07));
+    }
+
+    #[test]
+    fn read_dst_parses_each_address_type() {
+        let dst = read(0x01, &[127, 0, 0, 1, 0x1F, 0x90]).unwrap();
+        assert!(matches!(dst, Dst::V4([127, 0, 0, 1], 8080)));
+        let mut v6 = [0u8; 18];
+        v6[15] = 1;
+        v6[16..].copy_from_slice(&443u16.to_be_bytes());
+        assert!(matches!(read(0x04, &v6).unwrap(), Dst::V6(ip, 443) if ip == v6[..16]));
+        let dst = read(0x03, &domain_input(b"example.com", 80)).unwrap();
+        assert!(matches!(dst, Dst::Domain(ref host, 80) if host == "example.com"));
+    }
+
+    #[test]
+    fn read_dst_rejects_truncated_input() {
+        let cases: [(u8, &[u8]); 6] = [
+            (0x01, &[]),
+            (0x01, &[127, 0, 0, 1, 0x00]),
+            (0x04, &[0u8; 17]),
+            (0x03, &[]),
+            (0x03, &[5, b'a', b'b']),
+            (0x03, &[1, b'a', 0x00]),
+        ];
+        for (atyp, input) in cases {
+            let err = read(atyp, input).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{atyp:#04x} {input:?}");
+        }
+    }
+
+    #[test]
+    fn read_dst_rejects_unknown_address_types() {
+        for atyp in [0x00, 0x02, 0x05] {
+            let err = read(atyp, &[127, 0, 0, 1, 0, 80]).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(
+                err.to_string(),
+                format!("malformed at request header byte 3: ATYP=0x{atyp:02X}")
+            );
+        }
+    }
+
+    #[test]
+    fn read_dst_rejects_zero_length_domain() {
+        let err = read(0x03, &[0, 0, 80]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "malformed at request byte 4: domain name LEN=0");
+    }
+
+    #[test]
+    fn read_dst_limits_domain_length() {
+        let name = [b'a'; 255];
+        let input = domain_input(&name, 80);
+        let dst = read(0x03, &input).unwrap();
+        assert!(matches!(dst, Dst::Domain(ref host, 80) if host.len() == 255));
+
+        let err = read_dst(0x03, &mut &input[..], 253).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("LEN=255 exceeds the limit of 253"), "{err}");
+    }
+
+    #[test]
+    fn push_dst_round_trips_through_read_dst() {
+        let dsts = [
+            Dst::V4([10, 0, 0, 1], 1080),
+            Dst::V6(Ipv6Addr::LOCALHOST.octets(), 22),
+            Dst::Domain("a".repeat(255), 443),
+        ];
+        for dst in dsts {
+            let mut buf = Vec::new();
+            push_dst(&mut buf, &dst).unwrap();
+            let parsed = read(buf[0], &buf[1..]).unwrap();
+            assert_eq!(parsed.to_string(), dst.to_string());
+        }
+    }
+
+    #[test]
+    fn push_dst_rejects_domains_longer_than_255_bytes() {
+        let mut buf = Vec::new();
+        let err = push_dst(&mut buf, &Dst::Domain("a".repeat(256), 80)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn build_error_reply_follows_the_request_address_type() {
+        let v4 = [0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        for atyp in [0x00, 0x01, 0x03, 0x05] {
+            assert_eq!(build_error_reply(0x04, atyp), v4);
+        }
+        let v6 = build_error_reply(0x02, 0x04);
+        assert_eq!(v6[..4], [0x05, 0x02, 0x00, 0x04]);
+        assert_eq!(v6.len(), 4 + 16 + 2);
+        assert!(v6[4..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,280 @@
+// HAProxy の PROXY protocol（元のクライアントのアドレスを後段へ伝えるヘッダ）
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+
+use crate::session::Session;
+use crate::stream::ClientStream;
+
+// 宛先への接続に、元のクライアントの接続（クライアント -> このプロキシ）を表す v1 ヘッダを書く
+// 転送を始める前（成功応答の前）に呼ぶ
+pub(crate) fn send_v1<S: ClientStream>(
+    mut remote: &TcpStream,
+    client: &S,
+    session: &Session,
+) -> io::Result<()> {
+    let header = v1_header(session.peer, client.server_addr()?);
+    debug!("sending PROXY protocol header: {}", header.trim_end());
+    remote.write_all(header.as_bytes())?;
+    remote.flush()
+}
+
+// v1 のヘッダ行: "PROXY TCP4 src dst sport dport\r\n"（IPv6 は TCP6）
+// src と dst の種別が異なる場合（IPv4 射影アドレスの混在など）や、
+// アドレスが無い場合（Unix ドメインソケット）は UNKNOWN にする
+fn v1_header(src: Option<SocketAddr>, dst: Option<SocketAddr>) -> String {
+    let (proto, src, dst) = match (src, dst) {
+        (Some(src @ SocketAddr::V4(_)), Some(dst @ SocketAddr::V4(_))) => ("TCP4", src, dst),
+        (Some(src @ SocketAddr::V6(_)), Some(dst @ SocketAddr::V6(_))) => ("TCP6", src, dst),
+        _ => return "PROXY UNKNOWN\r\n".to_string(),
+    };
+    format!(
+        "PROXY {proto} {} {} {} {}\r\n",
+        src.ip(),
+        dst.ip(),
+        src.port(),
+        dst.port()
+    )
+}
+
+// v2 の先頭 12 バイト（シグネチャ）
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// v1 のヘッダ行の最大長（CRLF を含む）
+const V1_MAX_LEN: usize = 107;
+// v2 のアドレス部（TLV を含む）として受け付ける最大長
+const V2_MAX_LEN: usize = 4096;
+
+// 接続の先頭の PROXY protocol ヘッダ（v1 または v2）を読み、元のクライアントのアドレスを返す
+// ヘッダの分だけを読み、後続の SOCKS のデータには触れない
+// アドレスを伝えないヘッダ（v1 の UNKNOWN、v2 の LOCAL や TCP/UDP 以外）なら None
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 5];
+    reader.read_exact(&mut start)?;
+    if &start == b"PROXY" {
+        return read_v1(reader);
+    }
+    if start[..] != V2_SIGNATURE[..5] {
+        return Err(malformed("missing PROXY protocol header"));
+    }
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest)?;
+    if rest[..] != V2_SIGNATURE[5..] {
+        return Err(malformed("missing PROXY protocol header"));
+    }
+    read_v2(reader)
+}
+
+fn malformed(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// v1: "PROXY " に続けて "TCP4 src dst sport dport\r\n"（または "UNKNOWN ...\r\n"）
+fn read_v1<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut line = b"PROXY".to_vec();
+    let mut b = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(malformed("PROXY v1 header too long"));
+        }
+        reader.read_exact(&mut b)?;
+        line.push(b[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| malformed("PROXY v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, _dst, sport, _dport] => {
+            let ip: IpAddr = src.parse().map_err(|_| malformed("bad PROXY v1 source address"))?;
+            if ip.is_ipv4() != (*proto == "TCP4") {
+                return Err(malformed("PROXY v1 address does not match the protocol"));
+            }
+            let port = sport.parse().map_err(|_| malformed("bad PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(malformed("malformed PROXY v1 header")),
+    }
+}
+
+// v2: [VER_CMD, FAM, LEN(2)] とアドレス部（LEN バイト。TLV は読み捨てる）
+fn read_v2<R: Read>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut hdr = [0u8; 4];
+    reader.read_exact(&mut hdr)?;
+    let [ver_cmd, fam, l0, l1] = hdr;
+    if ver_cmd >> 4 != 0x2 {
+        return Err(malformed("unsupported PROXY protocol version"));
+    }
+    let len = u16::from_be_bytes([l0, l1]) as usize;
+    if len > V2_MAX_LEN {
+        return Err(malformed("PROXY v2 header too long"));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    // LOCAL（ロードバランサ自身のヘルスチェックなど）はアドレスを持たない
+    if ver_cmd & 0x0F == 0x0 {
+        return Ok(None);
+    }
+    if ver_cmd & 0x0F != 0x1 {
+        return Err(malformed("unsupported PROXY v2 command"));
+    }
+    match fam >> 4 {
+        // AF_INET: src(4) dst(4) sport(2) dport(2)
+        0x1 => {
+            let b = body.get(..12).ok_or_else(|| malformed("truncated PROXY v2 address"))?;
+            let ip = Ipv4Addr::new(b[0], b[1], b[2], b[3]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([b[8], b[9]]))))
+        }
+        // AF_INET6: src(16) dst(16) sport(2) dport(2)
+        0x2 => {
+            let b = body.get(..36).ok_or_else(|| malformed("truncated PROXY v2 address"))?;
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&b[..16]);
+            let port = u16::from_be_bytes([b[32], b[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)))
+        }
+        // AF_UNSPEC・AF_UNIX は元のアドレスとして使えない
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &[u8]) -> io::Result<Option<SocketAddr>> {
+        read_header(&mut &input[..])
+    }
+
+    // v2 のヘッダ: シグネチャ、VER_CMD、FAM、LEN とアドレス部
+    fn v2(ver_cmd: u8, fam: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[ver_cmd, fam]);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    #[test]
+    fn reads_v1_headers() {
+        let addr = read(b"PROXY TCP4 203.0.113.5 192.0.2.1 41234 1080\r\n").unwrap();
+        assert_eq!(addr, Some("203.0.113.5:41234".parse().unwrap()));
+        let addr = read(b"PROXY TCP6 2001:db8::5 2001:db8::1 41234 1080\r\n").unwrap();
+        assert_eq!(addr, Some("[2001:db8::5]:41234".parse().unwrap()));
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert_eq!(read(b"PROXY UNKNOWN ff::1 ff::2 1 2\r\n").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_only_the_header() {
+        let mut input = &b"PROXY UNKNOWN\r\n\x05\x01\x00"[..];
+        assert_eq!(read_header(&mut input).unwrap(), None);
+        assert_eq!(input, [0x05, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn rejects_malformed_v1_headers() {
+        let cases: [&[u8]; 5] = [
+            b"PROXY TCP4 203.0.113.5 192.0.2.1 41234\r\n",
+            b"PROXY TCP4 2001:db8::5 192.0.2.1 41234 1080\r\n",
+            b"PROXY TCP4 203.0.113.5 192.0.2.1 port 1080\r\n",
+            b"PROXY UDP4 203.0.113.5 192.0.2.1 41234 1080\r\n",
+            b"PROXY TCP4 \xFF 192.0.2.1 41234 1080\r\n",
+        ];
+        for input in cases {
+            let err = read(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", String::from_utf8_lossy(input));
+        }
+        // CRLF が来ないまま上限を超えたら、それ以上読まずに断る
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.resize(200, b'a');
+        assert_eq!(read(&long).unwrap_err().to_string(), "PROXY v1 header too long");
+        // ヘッダの途中で接続が切れた
+        let err = read(b"PROXY TCP4 203.0.113.5").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reads_v2_headers() {
+        let mut inet = vec![203, 0, 113, 5, 192, 0, 2, 1];
+        inet.extend_from_slice(&41234u16.to_be_bytes());
+        inet.extend_from_slice(&1080u16.to_be_bytes());
+        let addr = read(&v2(0x21, 0x11, &inet)).unwrap();
+        assert_eq!(addr, Some("203.0.113.5:41234".parse().unwrap()));
+        // TLV が続いていても読み捨てる
+        let mut with_tlv = inet.clone();
+        with_tlv.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert!(read(&v2(0x21, 0x11, &with_tlv)).unwrap().is_some());
+
+        let src: Ipv6Addr = "2001:db8::5".parse().unwrap();
+        let mut inet6 = src.octets().to_vec();
+        inet6.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        inet6.extend_from_slice(&41234u16.to_be_bytes());
+        inet6.extend_from_slice(&1080u16.to_be_bytes());
+        let addr = read(&v2(0x21, 0x21, &inet6)).unwrap();
+        assert_eq!(addr, Some(SocketAddr::new(IpAddr::V6(src), 41234)));
+
+        // LOCAL と、アドレスとして使えないファミリ（AF_UNSPEC・AF_UNIX）
+        assert_eq!(read(&v2(0x20, 0x11, &inet)).unwrap(), None);
+        assert_eq!(read(&v2(0x21, 0x00, &[])).unwrap(), None);
+        assert_eq!(read(&v2(0x21, 0x31, &[0u8; 216])).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_v2_headers() {
+        let cases = [
+            (v2(0x11, 0x11, &[0u8; 12]), "unsupported PROXY protocol version"),
+            (v2(0x22, 0x11, &[0u8; 12]), "unsupported PROXY v2 command"),
+            (v2(0x21, 0x11, &[0u8; 11]), "truncated PROXY v2 address"),
+            (v2(0x21, 0x21, &[0u8; 12]), "truncated PROXY v2 address"),
+            (v2(0x21, 0x11, &[0u8; V2_MAX_LEN + 1]), "PROXY v2 header too long"),
+        ];
+        for (input, message) in cases {
+            let err = read(&input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), message);
+        }
+        // LEN の分のアドレス部が届かない
+        let mut short = v2(0x21, 0x11, &[0u8; 12]);
+        short.truncate(short.len() - 1);
+        assert_eq!(read(&short).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_connections_without_a_signature() {
+        let cases: [&[u8]; 3] =
+            [b"\x05\x01\x00\x00\x00", b"GET / HTTP/1.1\r\n", b"\r\n\r\n\0\r\nQUIx\n"];
+        for input in cases {
+            let err = read(input).unwrap_err();
+            assert_eq!(err.to_string(), "missing PROXY protocol header");
+        }
+    }
+
+    #[test]
+    fn sent_v1_headers_are_read_back() {
+        use std::net::TcpListener;
+
+        // クライアント -> プロキシの接続と、プロキシ -> 宛先の接続
+        let pair = || {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let connected = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            (connected, listener.accept().unwrap().0)
+        };
+        let (_client, accepted) = pair();
+        let (remote, mut target) = pair();
+        let peer: SocketAddr = "203.0.113.5:41234".parse().unwrap();
+        send_v1(&remote, &accepted, &Session::new(1, Some(peer))).unwrap();
+
+        let mut line = vec![0u8; 64];
+        let n = target.read(&mut line).unwrap();
+        let port = accepted.local_addr().unwrap().port();
+        let expected = format!("PROXY TCP4 203.0.113.5 127.0.0.1 41234 {port}\r\n");
+        assert_eq!(String::from_utf8_lossy(&line[..n]), expected);
+        assert_eq!(read(&line[..n]).unwrap(), Some(peer));
+
+        let src: SocketAddr = "[2001:db8::5]:1".parse().unwrap();
+        let v6 = v1_header(Some(src), Some("[::1]:2".parse().unwrap()));
+        assert_eq!(read(v6.as_bytes()).unwrap(), Some(src));
+        // アドレスの無い接続や、種別の異なる組は UNKNOWN
+        assert_eq!(v1_header(Some(peer), None), "PROXY UNKNOWN\r\n");
+        assert_eq!(v1_header(Some(peer), Some("[::1]:2".parse().unwrap())), "PROXY UNKNOWN\r\n");
+    }
+}
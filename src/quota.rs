@@ -0,0 +1,285 @@
+// 認証したユーザごとの転送量（期間内の両方向の合計バイト数）の記録
+// 記録は全ての接続で共有し、quota_file があれば定期的に JSON で保存して再起動後も引き継ぐ
+// 期間は UTC の暦で区切り、保存した期間が終わっていれば 0 から数え直す
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account::{Quota, QuotaPeriod};
+use crate::log::{self, quote};
+
+struct Usage {
+    // 数えている期間の始まり（UNIX 時刻の秒）
+    period_start: u64,
+    // 転送スレッドから直接加算する
+    bytes: Arc<AtomicU64>,
+}
+
+static USAGE: LazyLock<Mutex<HashMap<String, Usage>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock() -> MutexGuard<'static, HashMap<String, Usage>> {
+    match USAGE.lock() {
+        Ok(u) => u,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// user の今の期間の転送量のカウンタ（期間が変わっていれば 0 から数え直す）
+// 上限の無いユーザも、暦月ごとに数えて記録する
+pub(crate) fn counter(user: &str, quota: Option<&Quota>) -> Arc<AtomicU64> {
+    let period = quota.map_or(QuotaPeriod::Month, |q| q.period);
+    let start = period_start(now_secs(), period);
+    let mut usage = lock();
+    let entry = usage.entry(user.to_string()).or_insert_with(|| Usage {
+        period_start: start,
+        bytes: Arc::default(),
+    });
+    // 数え直した後も、前の期間から続いている接続は古いカウンタに加算する
+    if entry.period_start != start {
+        *entry = Usage { period_start: start, bytes: Arc::default() };
+    }
+    Arc::clone(&entry.bytes)
+}
+
+// 記録を path へ保存する（書きかけのファイルを読まれないよう、一時ファイルに書いてから置き換える）
+// 形式: {"user": {"period_start": UNIX 時刻の秒, "bytes": バイト数}, ...}
+pub(crate) fn save(path: &str) -> io::Result<()> {
+    let mut json = String::from("{\n");
+    {
+        let usage = lock();
+        let mut users: Vec<_> = usage.iter().collect();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        for (i, (user, u)) in users.into_iter().enumerate() {
+            let sep = if i == 0 { "" } else { ",\n" };
+            let _ = write!(
+                json,
+                "{sep}  {}: {{\"period_start\": {}, \"bytes\": {}}}",
+                quote(user),
+                u.period_start,
+                u.bytes.load(Ordering::Relaxed)
+            );
+        }
+    }
+    json.push_str("\n}\n");
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, path)
+}
+
+// path に保存した記録を読み込む（ファイルが無ければ、何も記録の無い状態から始める）
+pub(crate) fn load(path: &str) -> io::Result<usize> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(io::Error::new(e.kind(), format!("cannot read {path}: {e}"))),
+    };
+    let records = Parser { s: text.as_bytes(), pos: 0 }
+        .records()
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{path}: {e}")))?;
+    let count = records.len();
+    let mut usage = lock();
+    for (user, period_start, bytes) in records {
+        let bytes = Arc::new(AtomicU64::new(bytes));
+        usage.insert(user, Usage { period_start, bytes });
+    }
+    Ok(count)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// now を含む期間の始まり（UTC の 0 時。週は月曜日、月は 1 日）
+fn period_start(now: u64, period: QuotaPeriod) -> u64 {
+    let days = now / 86_400;
+    let start = match period {
+        QuotaPeriod::Day => days,
+        // 1970-01-01 は木曜日
+        QuotaPeriod::Week => days - (days + 3) % 7,
+        QuotaPeriod::Month => days - (log::civil_from_days(days).2 - 1),
+    };
+    start * 86_400
+}
+
+// save で書いた形の JSON だけを読む簡易な解析器（外部クレートを使わない）
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    // {"user": {"period_start": N, "bytes": N}, ...} を (user, period_start, bytes) の一覧にする
+    fn records(mut self) -> Result<Vec<(String, u64, u64)>, String> {
+        let mut records = Vec::new();
+        self.expect(b'{')?;
+        if !self.eat(b'}') {
+            loop {
+                let user = self.string()?;
+                self.expect(b':')?;
+                self.expect(b'{')?;
+                let (mut period_start, mut bytes) = (None, None);
+                loop {
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    let value = self.number()?;
+                    match key.as_str() {
+                        "period_start" => period_start = Some(value),
+                        "bytes" => bytes = Some(value),
+                        _ => return Err(format!("unknown key {key:?} for {user:?}")),
+                    }
+                    if !self.eat(b',') {
+                        break;
+                    }
+                }
+                self.expect(b'}')?;
+                match (period_start, bytes) {
+                    (Some(p), Some(b)) => records.push((user, p, b)),
+                    _ => return Err(format!("missing period_start or bytes for {user:?}")),
+                }
+                if !self.eat(b',') {
+                    break;
+                }
+            }
+            self.expect(b'}')?;
+        }
+        self.skip_ws();
+        if self.pos != self.s.len() {
+            return Err(self.error("trailing data"));
+        }
+        Ok(records)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.s.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    // 空白の次が c なら読み進めて true
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_ws();
+        if self.s.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.s.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| self.error("expected an unsigned integer"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let Some(&c) = self.s.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.s.get(self.pos) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => {
+                            let hex = self.s.get(self.pos + 1..self.pos + 5);
+                            self.pos += 4;
+                            hex.and_then(|h| std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                c => out.push(c),
+            }
+        }
+        String::from_utf8(out).map_err(|_| self.error("string is not valid UTF-8"))
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("{msg} at byte {}", self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(text: &str) -> Result<Vec<(String, u64, u64)>, String> {
+        Parser { s: text.as_bytes(), pos: 0 }.records()
+    }
+
+    #[test]
+    fn parses_saved_records() {
+        let text = "{\n  \"alice\": {\"period_start\": 1700000000, \"bytes\": 42},\n  \
+                    \"b\\\"o\\\\b\\n\\u00e9\": {\"bytes\": 0, \"period_start\": 0}\n}\n";
+        assert_eq!(
+            records(text).unwrap(),
+            [("alice".to_string(), 1_700_000_000, 42), ("b\"o\\b\né".to_string(), 0, 0)]
+        );
+        assert_eq!(records(" { } ").unwrap(), []);
+        // save が書く形（quote で引用したユーザ名）をそのまま読める
+        let user = "ctl\u{1}\"x";
+        let text = format!("{{{}: {{\"period_start\": 1, \"bytes\": 2}}}}", quote(user));
+        assert_eq!(records(&text).unwrap(), [(user.to_string(), 1, 2)]);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let cases = [
+            ("", "expected '{' at byte 0"),
+            ("[]", "expected '{' at byte 0"),
+            ("{", "expected '\"' at byte 1"),
+            ("{\"a\" {}}", "expected ':' at byte 5"),
+            ("{\"a\": 1}", "expected '{' at byte 6"),
+            ("{\"a", "unterminated string at byte 3"),
+            ("{\"a\\q\": {}}", "invalid escape at byte 4"),
+            ("{\"\\u12\": {}}", "invalid \\u escape at byte 7"),
+            ("{\"a\": {\"bytes\": -1}}", "expected an unsigned integer at byte 16"),
+            ("{\"a\": {\"bytes\": 99999999999999999999}}", "expected an unsigned integer"),
+            ("{\"a\": {\"bytes\": 1}}", "missing period_start or bytes for \"a\""),
+            ("{\"a\": {\"size\": 1}}", "unknown key \"size\" for \"a\""),
+            ("{\"a\": {\"bytes\": 1, \"period_start\": 2},}", "expected '\"' at byte 38"),
+            ("{} {}", "trailing data at byte 3"),
+        ];
+        for (text, expected) in cases {
+            let err = records(text).unwrap_err();
+            assert!(err.starts_with(expected), "{text:?}: {err}");
+        }
+    }
+}
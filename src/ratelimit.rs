@@ -0,0 +1,154 @@
+// クライアント IP ごとの新規接続数の制限と、プロセス全体・ユーザごとの転送速度の制限（トークンバケット）
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// これを超えたら、満タンに戻ったバケット（しばらく接続の無い IP）を捨てる
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // 転送速度の制限のバケットから bytes バイト分を差し引き、送ってよくなるまで待つべき時間を返す
+    // 1 秒あたり rate バイトが貯まり、最大 1 秒分まで連続で送れる
+    // 足りなければ先に借りて（トークンは負になる）、不足分が貯まるまで呼び出し側が sleep する
+    fn reserve(&mut self, bytes: usize, rate: f64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+// 1 秒あたり rate 個のトークンが貯まり、最大 burst 個まで連続で接続できる
+// 全ての accept ループで共有するため、状態は Mutex で保護する
+pub(crate) struct ConnRateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl ConnRateLimiter {
+    pub(crate) fn new(rate: f64, burst: u32) -> Self {
+        ConnRateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // ip からの新しい接続を受け付けてよいか（受け付けるならトークンを 1 つ消費する）
+    pub(crate) fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets = match self.buckets.lock() {
+            Ok(b) => b,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// プロセス全体の転送速度の制限（total_rate_limit）で、全ての接続の転送ループが共有するバケット
+static TOTAL: Mutex<Option<Bucket>> = Mutex::new(None);
+
+// bytes バイトを転送する分のトークンを差し引き、送ってよくなるまで待つべき時間を返す（Bucket::reserve）
+// 借りた順に後の接続の待ち時間が延びるため、混み合っても各接続に順に配られ、忙しく待ち続けることも無い
+pub(crate) fn reserve_total(bytes: usize, rate: u64) -> Duration {
+    let rate = rate as f64;
+    let mut total = match TOTAL.lock() {
+        Ok(t) => t,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    total
+        .get_or_insert(Bucket { tokens: rate, updated: Instant::now() })
+        .reserve(bytes, rate)
+}
+
+// ユーザごとの転送速度の上限（認証情報ファイルの rate=。バイト/秒で、0 なら無制限）
+// 転送中の接続はこの Arc を持ち続け、認証情報の読み直しで値が書き換わると次の補充から新しい上限で待つ
+static USER_RATES: LazyLock<Mutex<HashMap<String, Arc<AtomicU64>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn lock_user_rates() -> MutexGuard<'static, HashMap<String, Arc<AtomicU64>>> {
+    match USER_RATES.lock() {
+        Ok(r) => r,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// user の転送速度の上限を rate にして、転送中の接続と共有する値を返す
+pub(crate) fn user_rate(user: &str, rate: Option<u64>) -> Arc<AtomicU64> {
+    let mut rates = lock_user_rates();
+    let shared = rates.entry(user.to_string()).or_default();
+    shared.store(rate.unwrap_or(0), Ordering::Relaxed);
+    Arc::clone(shared)
+}
+
+// 読み直した認証情報の rate= を、転送中の接続の上限にも反映する（消えたユーザは無制限に戻す）
+pub(crate) fn update_user_rates(rate_of: impl Fn(&str) -> Option<u64>) {
+    for (user, shared) in lock_user_rates().iter() {
+        shared.store(rate_of(user).unwrap_or(0), Ordering::Relaxed);
+    }
+}
+
+// 接続の片方向ごとの、ユーザの転送速度の上限のバケット
+// 上限は共有の値から補充のたびに読み直すため、読み直しで下がった上限もすぐに効く
+pub(crate) struct UserBucket<'a> {
+    rate: &'a AtomicU64,
+    bucket: Option<Bucket>,
+}
+
+impl<'a> UserBucket<'a> {
+    pub(crate) fn new(rate: &'a AtomicU64) -> Self {
+        UserBucket { rate, bucket: None }
+    }
+
+    // 今の上限（バイト/秒。0 なら無制限）
+    pub(crate) fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    // bytes バイトを転送する分のトークンを差し引き、送ってよくなるまで待つべき時間を返す
+    // 無制限の間はバケットを持たず、上限が付いたら満タンのバケットから始める
+    pub(crate) fn reserve(&mut self, bytes: usize) -> Duration {
+        let rate = self.rate() as f64;
+        if rate == 0.0 {
+            self.bucket = None;
+            return Duration::ZERO;
+        }
+        self.bucket
+            .get_or_insert(Bucket { tokens: rate, updated: Instant::now() })
+            .reserve(bytes, rate)
+    }
+}
@@ -0,0 +1,352 @@
+// クライアントと宛先の間のデータ転送
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Config, log, metrics, ratelimit, sockopt};
+use crate::ratelimit::UserBucket;
+use crate::session::Session;
+use crate::stream::ClientStream;
+
+// クライアントと宛先の双方向転送
+// tag はログに付けるテナントタグ（無ければ空文字列）
+// config.idle_timeout を指定すると、その間データが流れない方向は正常終了として閉じる
+pub(crate) fn splice<S: ClientStream>(
+    client: &S,
+    remote: TcpStream,
+    tag: &str,
+    config: &Config,
+    session: &Session,
+) -> io::Result<()> {
+    // タイムアウトはソケット単位なので、両方向の転送に効く
+    client.set_read_timeout(config.idle_timeout)?;
+    client.set_write_timeout(config.idle_timeout)?;
+    remote.set_read_timeout(config.idle_timeout)?;
+    remote.set_write_timeout(config.idle_timeout)?;
+    // 対話的な通信（SSH など）の遅延を減らすため、既定で Nagle アルゴリズムを無効にする
+    // Unix ドメインソケットのクライアントには TCP のオプションが無いため、宛先側だけに設定する
+    for s in [client.as_tcp(), Some(&remote)].into_iter().flatten() {
+        s.set_nodelay(config.tcp_nodelay)?;
+        if let Some(idle) = config.keepalive_idle {
+            sockopt::set_keepalive(s, idle, config.keepalive_interval, config.keepalive_probes)?;
+        }
+        // SO_LINGER の無い OS では、OS の既定の閉じ方のままにする
+        if let Some(linger) = config.linger
+            && let Err(e) = sockopt::set_linger(s, linger)
+            && e.kind() != ErrorKind::Unsupported
+        {
+            return Err(e);
+        }
+        if let Some(timeout) = config.tcp_user_timeout {
+            sockopt::set_user_timeout(s, timeout)?;
+            debug!("TCP_USER_TIMEOUT set to {}ms", timeout.as_millis());
+        }
+    }
+
+    let opts = RelayOptions {
+        user_rate: session.user_rate.as_deref(),
+        ..RelayOptions::from(config)
+    };
+    copy_bidirectional(client, &remote, tag, opts, Deadlines::from(config), session).map(|_| ())
+}
+
+// 両方向を同時に転送し、両方が終わるまで待って (client -> remote, remote -> client) のバイト数を返す
+// 片方向が EOF になったら、その転送先の書き込み側だけを閉じ（half-close）、もう片方向は続ける
+// 片方向がエラーで終わったら両方のソケットを閉じ、もう片方向も終わらせる
+// deadlines のどれかを過ぎたら（見張りのスレッドが期限を計算し直して）両方を閉じる
+fn copy_bidirectional<S: ClientStream>(
+    client: &S,
+    remote: &TcpStream,
+    tag: &str,
+    opts: RelayOptions<'_>,
+    deadlines: Deadlines,
+    session: &Session,
+) -> io::Result<(u64, u64)> {
+    let upstream_label = format!("client -> remote{tag}");
+    let downstream_label = format!("remote -> client{tag}");
+    let traffic = &session.traffic;
+    // 方向ごとの転送量に加えて、ユーザの転送量（両方向の合計）とメトリクスにも加算する
+    let usage = session.usage.as_deref();
+    let sent: Vec<&AtomicU64> = [Some(&traffic.sent), usage, Some(metrics::bytes_sent())]
+        .into_iter()
+        .flatten()
+        .collect();
+    let received: Vec<&AtomicU64> =
+        [Some(&traffic.received), usage, Some(metrics::bytes_received())]
+            .into_iter()
+            .flatten()
+            .collect();
+    let conn_id = log::conn_id();
+    // 方向ごとに読み書きのハンドルを複製して渡す（閉じる操作は元の接続全体に効く）
+    let (client_rx, client_tx) = (client.try_clone()?, client.try_clone()?);
+    let (remote_rx, remote_tx) = (remote.try_clone()?, remote.try_clone()?);
+    let activity = Activity::new();
+
+    thread::scope(|s| {
+        // 転送が終わったら（done が破棄されたら）見張りも終わる
+        let (done, finished) = mpsc::channel::<()>();
+        if deadlines.any() {
+            let client = client.try_clone()?;
+            let remote = remote.try_clone()?;
+            let activity = &activity;
+            s.spawn(move || {
+                log::set_conn_id(conn_id);
+                loop {
+                    let wait = match deadlines.next(activity) {
+                        Ok(wait) => wait,
+                        Err(reason) => {
+                            info!("{reason}, closing");
+                            let _ = client.shutdown(Shutdown::Both);
+                            let _ = remote.shutdown(Shutdown::Both);
+                            break;
+                        }
+                    };
+                    if finished.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+                        break;
+                    }
+                }
+            });
+        }
+        let upstream = s.spawn(|| {
+            log::set_conn_id(conn_id);
+            pipe(client_rx, remote_tx, &upstream_label, opts, &sent, &activity)
+        });
+        let (down, down_res) =
+            pipe(remote_rx, client_tx, &downstream_label, opts, &received, &activity);
+        let (up, up_res) = upstream
+            .join()
+            .unwrap_or_else(|_| (0, Err(io::Error::other("forward thread panicked"))));
+        drop(done);
+        // 失敗した接続でも、どこまで転送できたかがエラー（とアクセスログ）から分かるようにする
+        up_res.and(down_res).map(|()| (up, down)).map_err(|e| {
+            io::Error::new(e.kind(), format!("{e} (after {up} bytes sent, {down} bytes received)"))
+        })
+    })
+}
+
+// 片方向の転送と、終了時の後始末
+fn pipe<R: ClientStream, W: ClientStream>(
+    mut src: R,
+    mut dst: W,
+    direction: &str,
+    opts: RelayOptions<'_>,
+    counters: &[&AtomicU64],
+    activity: &Activity,
+) -> (u64, io::Result<()>) {
+    let res = relay(&mut src, &mut dst, direction, opts, counters, activity);
+    let (n, res) = idle_as_eof(res, direction);
+    log_relay_result(direction, n, &res);
+    match &res {
+        // linger=0 でも half-close はそのまま行う（RST になるのは最後にソケットを閉じるときだけ）
+        Ok(()) => {
+            // 書き込み側を閉じる前に、ストリームに残っているデータを送り切る
+            let _ = dst.flush();
+            let _ = dst.shutdown(Shutdown::Write);
+        }
+        Err(_) => {
+            let _ = src.shutdown(Shutdown::Both);
+            let _ = dst.shutdown(Shutdown::Both);
+        }
+    }
+    (n, res)
+}
+
+// 転送ループの設定（Config から必要な項目だけを取り出し、スレッドへコピーで渡す）
+// user_rate は認証したユーザの転送速度の上限（Session と共有し、読み直しで書き換わる）
+#[derive(Clone, Copy)]
+struct RelayOptions<'a> {
+    buffer_size: usize,
+    stats_interval: Option<Duration>,
+    rate_limit: u64,
+    total_rate_limit: u64,
+    user_rate: Option<&'a AtomicU64>,
+}
+
+impl From<&Config> for RelayOptions<'_> {
+    fn from(config: &Config) -> Self {
+        RelayOptions {
+            buffer_size: config.buffer_size.max(1),
+            stats_interval: config.stats_interval,
+            rate_limit: config.rate_limit,
+            total_rate_limit: config.total_rate_limit,
+            user_rate: None,
+        }
+    }
+}
+
+// トンネル全体を閉じる期限（Config から取り出し、見張りのスレッドへコピーで渡す）
+#[derive(Clone, Copy)]
+struct Deadlines {
+    tunnel_idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+    lifetime_window: Option<Duration>,
+}
+
+impl From<&Config> for Deadlines {
+    fn from(config: &Config) -> Self {
+        Deadlines {
+            tunnel_idle_timeout: config.tunnel_idle_timeout,
+            max_lifetime: config.max_lifetime,
+            lifetime_window: config.lifetime_window,
+        }
+    }
+}
+
+impl Deadlines {
+    fn any(&self) -> bool {
+        [self.tunnel_idle_timeout, self.max_lifetime, self.lifetime_window]
+            .iter()
+            .any(Option::is_some)
+    }
+
+    // 最も近い期限までの時間（データが流れるたびに延びる期限は、呼ぶたびに計算し直す）
+    // どれかを過ぎていれば、閉じる理由を返す
+    fn next(&self, activity: &Activity) -> Result<Duration, String> {
+        let idle = activity.idle();
+        let age = activity.started.elapsed();
+        let mut wait = Duration::MAX;
+        if let Some(timeout) = self.tunnel_idle_timeout {
+            if idle >= timeout {
+                return Err(format!("tunnel idle for {}s in both directions", idle.as_secs()));
+            }
+            wait = wait.min(timeout - idle);
+        }
+        // スライディング方式: 最後に転送してから lifetime_window まで（max_lifetime は下で見る）
+        if let Some(window) = self.lifetime_window {
+            if idle >= window {
+                return Err(format!("sliding lifetime expired after {idle:?} without data"));
+            }
+            wait = wait.min(window - idle);
+        }
+        if let Some(limit) = self.max_lifetime {
+            if age >= limit {
+                return Err(format!("maximum lifetime of {limit:?} reached"));
+            }
+            wait = wait.min(limit - age);
+        }
+        Ok(wait)
+    }
+}
+
+// 両方向で最後にデータ（ペイロード）を読んだ時刻（転送を始めてからのミリ秒）
+struct Activity {
+    started: Instant,
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity { started: Instant::now(), last_ms: AtomicU64::new(0) }
+    }
+
+    fn touch(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_ms.fetch_max(now, Ordering::Relaxed);
+    }
+
+    // どちらの方向にもデータが流れていない時間
+    fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+}
+
+// 片方向の転送ループ（io::copy 相当）
+// stats_interval ごとに途中経過（累計バイト数とその区間の転送速度）をログに出す
+// rate_limit (バイト/秒) が 0 でなければ、平均がその速度を超えないよう書き込みの後に待つ
+// total_rate_limit が 0 でなければ、全ての接続で共有するバケットから読んだ分を差し引き、書き込みの前に待つ
+// user_rate の上限も同じく書き込みの前に待つ（上限は読み込みのたびに読み直す）
+// エラーで中断した場合も、それまでに転送したバイト数を返す（counters の各カウンタにも随時加算する）
+fn relay<R: Read, W: Write>(
+    src: &mut R,
+    dst: &mut W,
+    direction: &str,
+    opts: RelayOptions<'_>,
+    counters: &[&AtomicU64],
+    activity: &Activity,
+) -> (u64, io::Result<()>) {
+    // バッファは方向ごとに 1 回だけ確保し、転送の間再利用する
+    let mut buf = vec![0u8; opts.buffer_size];
+    // 速度制限時は 1 回の読み込みを 1 秒分以下に抑え、送信が一度に偏らないようにする
+    let chunk = [opts.rate_limit, opts.total_rate_limit]
+        .into_iter()
+        .filter(|&limit| limit > 0)
+        .fold(buf.len(), |chunk, limit| chunk.min(limit as usize));
+    let mut user_bucket = opts.user_rate.map(UserBucket::new);
+    let mut total = 0u64;
+    let started = Instant::now();
+    let mut last_report = (started, 0u64);
+    loop {
+        let chunk = match user_bucket.as_ref().map(UserBucket::rate) {
+            Some(limit @ 1..) => chunk.min(limit as usize),
+            _ => chunk,
+        };
+        let n = match src.read(&mut buf[..chunk]) {
+            Ok(0) => return (total, Ok(())), // EOF
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return (total, Err(e)),
+        };
+        // 無通信の時間は、1 バイト以上読めたときだけリセットする
+        // （keepalive のプローブはカーネルが扱い、read には現れないため数えない）
+        activity.touch();
+        if opts.total_rate_limit > 0 {
+            thread::sleep(ratelimit::reserve_total(n, opts.total_rate_limit));
+        }
+        if let Some(bucket) = &mut user_bucket {
+            thread::sleep(bucket.reserve(n));
+        }
+        if let Err(e) = dst.write_all(&buf[..n]) {
+            return (total, Err(e));
+        }
+        total += n as u64;
+        for counter in counters {
+            counter.fetch_add(n as u64, Ordering::Relaxed);
+        }
+
+        if opts.rate_limit > 0 {
+            let due = Duration::from_secs_f64(total as f64 / opts.rate_limit as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+
+        if let Some(interval) = opts.stats_interval {
+            let elapsed = last_report.0.elapsed();
+            if elapsed >= interval {
+                let rate = (total - last_report.1) as f64 / elapsed.as_secs_f64();
+                info!(
+                    "{direction}: {total} bytes so far ({:.1} KiB/s)",
+                    rate / 1024.0
+                );
+                last_report = (Instant::now(), total);
+            }
+        }
+    }
+}
+
+// アイドルタイムアウト（WouldBlock / TimedOut）は致命的なエラーではなく、
+// EOF と同じく正常な終了として扱う
+fn idle_as_eof((n, res): (u64, io::Result<()>), direction: &str) -> (u64, io::Result<()>) {
+    match res {
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            info!("{direction}: idle timeout after {n} bytes");
+            (n, Ok(()))
+        }
+        res => (n, res),
+    }
+}
+
+// 転送結果のログ。EOF と転送途中の RST（ConnectionReset）を区別して表示する
+fn log_relay_result(direction: &str, n: u64, res: &io::Result<()>) {
+    match res {
+        Ok(()) => info!("{direction}: {n} bytes"),
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+            warn!("{direction}: connection reset mid-transfer after {n} bytes")
+        }
+        Err(e) => warn!("{direction}: transfer failed after {n} bytes: {e}"),
+    }
+}
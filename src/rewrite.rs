@@ -0,0 +1,172 @@
+// 宛先の書き換え規則（例: example.com:443=staging.example.net:8443）
+// 左辺はホスト名か IP アドレス（ポートを付けるとそのポートだけ）、右辺は置き換え先（ポート省略時は元のポート）
+// 最初に一致した規則だけを適用する。ホスト名の比較は大文字・小文字を区別しない
+// ホスト名は要求の宛先と同じく ASCII 形式（xn--）にして持つ
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::{Config, idna};
+use crate::protocol::Dst;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    host: String,
+    port: Option<u16>,
+    to_host: String,
+    to_port: Option<u16>,
+}
+
+impl Rewrite {
+    // dst がこの規則に一致すれば、置き換え先を返す
+    fn apply(&self, dst: &Dst) -> Option<Dst> {
+        let port = dst.port();
+        if self.port.is_some_and(|p| p != port) {
+            return None;
+        }
+        let matched = match dst {
+            Dst::Domain(host, _) => host.eq_ignore_ascii_case(&self.host),
+            _ => dst.ip() == self.host.parse().ok(),
+        };
+        if !matched {
+            return None;
+        }
+        let port = self.to_port.unwrap_or(port);
+        Some(match self.to_host.parse::<IpAddr>() {
+            Ok(ip) => Dst::from(SocketAddr::new(ip, port)),
+            Err(_) => Dst::Domain(self.to_host.clone(), port),
+        })
+    }
+}
+
+// "host"・"host:port"・"[IPv6]"・"[IPv6]:port" をホストとポートに分ける
+fn split_host_port(s: &str) -> Result<(String, Option<u16>), String> {
+    let bad = || format!("invalid destination {s:?}");
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']').ok_or_else(bad)?;
+        host.parse::<IpAddr>().map_err(|_| bad())?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':').ok_or_else(bad)?)),
+        }
+    } else {
+        match s.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (s, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(bad());
+    }
+    let port = port
+        .map(|p| p.parse::<u16>().ok().filter(|&p| p > 0).ok_or_else(bad))
+        .transpose()?;
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok((host.to_string(), port));
+    }
+    let host = idna::to_ascii(host).map_err(|e| format!("invalid destination {s:?}: {e}"))?;
+    Ok((host, port))
+}
+
+impl FromStr for Rewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid rewrite {s:?}: expected FROM=TO"))?;
+        let (host, port) = split_host_port(from.trim())?;
+        let (to_host, to_port) = split_host_port(to.trim())?;
+        Ok(Rewrite { host, port, to_host, to_port })
+    }
+}
+
+// 設定ファイル・ログ用の表記（FROM=TO）
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn side(f: &mut fmt::Formatter<'_>, host: &str, port: Option<u16>) -> fmt::Result {
+            match (host.contains(':'), port) {
+                (true, Some(p)) => write!(f, "[{host}]:{p}"),
+                (true, None) => write!(f, "[{host}]"),
+                (false, Some(p)) => write!(f, "{host}:{p}"),
+                (false, None) => f.write_str(host),
+            }
+        }
+        side(f, &self.host, self.port)?;
+        f.write_str("=")?;
+        side(f, &self.to_host, self.to_port)
+    }
+}
+
+// config.rewrites のうち最初に一致した規則で dst を置き換える
+// 元の宛先は session.dst に残り、アクセスログにはそちらが出る
+pub(crate) fn rewrite(config: &Config, dst: &mut Dst) {
+    if let Some((rule, new)) = config.rewrites.iter().find_map(|r| Some((r, r.apply(dst)?))) {
+        info!("destination rewritten: {dst} -> {new} (rule {rule})");
+        *dst = new;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(s: &str) -> Rewrite {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_hosts_ports_and_ipv6_literals() {
+        let r = rule("example.com:443=staging.example.net:8443");
+        assert_eq!((r.host.as_str(), r.port), ("example.com", Some(443)));
+        assert_eq!((r.to_host.as_str(), r.to_port), ("staging.example.net", Some(8443)));
+        let r = rule(" 10.0.0.1 = [2001:db8::1] ");
+        assert_eq!((r.host.as_str(), r.port), ("10.0.0.1", None));
+        assert_eq!((r.to_host.as_str(), r.to_port), ("2001:db8::1", None));
+        assert_eq!(rule("[::1]:22=host").to_string(), "[::1]:22=host");
+        assert_eq!(rule("a=[::1]").to_string(), "a=[::1]");
+    }
+
+    #[test]
+    fn converts_hostnames_to_ascii() {
+        let r = rule("bücher.example=Bücher.test:80");
+        assert_eq!(r.host, "xn--bcher-kva.example");
+        assert_eq!(r.to_host, "xn--bcher-kva.test");
+        assert_eq!(r.to_string(), "xn--bcher-kva.example=xn--bcher-kva.test:80");
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        let cases = [
+            ("example.com", "expected FROM=TO"),
+            ("=example.net", "invalid destination \"\""),
+            ("example.com:0=a", "invalid destination"),
+            ("example.com:65536=a", "invalid destination"),
+            ("example.com:http=a", "invalid destination"),
+            ("a=[example.net]", "invalid destination"),
+            ("a=[::1]22", "invalid destination"),
+            ("a=b\0c", "hostname contains NUL"),
+            ("a=", "invalid destination"),
+        ];
+        for (input, message) in cases {
+            let err = input.parse::<Rewrite>().unwrap_err();
+            assert!(err.contains(message), "{input:?}: {err}");
+        }
+        let long = format!("a={}", "b".repeat(254));
+        assert!(long.parse::<Rewrite>().unwrap_err().contains("invalid destination"));
+    }
+
+    #[test]
+    fn applies_to_matching_destinations() {
+        let r = rule("Example.COM:443=[2001:db8::1]");
+        let to = r.apply(&Dst::Domain("example.com".to_string(), 443)).unwrap();
+        assert_eq!(to.to_string(), "[2001:db8::1]:443");
+        assert!(r.apply(&Dst::Domain("example.com".to_string(), 80)).is_none());
+        assert!(r.apply(&Dst::Domain("www.example.com".to_string(), 443)).is_none());
+
+        let r = rule("192.0.2.1=target.example:8080");
+        let to = r.apply(&Dst::V4([192, 0, 2, 1], 22)).unwrap();
+        assert!(matches!(to, Dst::Domain(ref host, 8080) if host == "target.example"));
+        assert!(r.apply(&Dst::V4([192, 0, 2, 2], 22)).is_none());
+    }
+}
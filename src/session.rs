@@ -0,0 +1,116 @@
+// 接続 1 本分の記録（接続 ID・利用者・宛先・転送量）と、終了時のアクセスログ
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::geoip::Geo;
+use crate::log::{self, Field, Level};
+use crate::stream::peer_label;
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+// 接続 ID を払い出す（accept した順に 1 から増える）
+pub(crate) fn next_conn_id() -> u64 {
+    NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// 転送量のカウンタ（転送スレッドから更新するため Arc で共有する）
+#[derive(Default)]
+pub(crate) struct Traffic {
+    // クライアント -> 宛先
+    pub(crate) sent: AtomicU64,
+    // 宛先 -> クライアント
+    pub(crate) received: AtomicU64,
+}
+
+pub(crate) struct Session {
+    pub(crate) id: u64,
+    // 接続元のアドレス（Unix ドメインソケットで、PROXY protocol でも伝えられなければ None）
+    pub(crate) peer: Option<SocketAddr>,
+    // 認証したユーザ名（認証なしなら None）
+    pub(crate) user: Option<String>,
+    // 要求された宛先（Request を読む前に終わった場合は None）
+    pub(crate) dst: Option<String>,
+    pub(crate) traffic: Arc<Traffic>,
+    // 認証したユーザの今の期間の転送量（転送したバイト数をここにも加算する。ユーザが無ければ None）
+    pub(crate) usage: Option<Arc<AtomicU64>>,
+    // 認証したユーザの転送速度の上限（バイト/秒。0 なら無制限）。読み直しで変わると転送中の接続にも効く
+    pub(crate) user_rate: Option<Arc<AtomicU64>>,
+    // 接続した宛先の国と AS 番号（--geoip-db で読み込んだものに含まれていれば）
+    pub(crate) geo: Option<Geo>,
+    started: Instant,
+}
+
+impl Session {
+    pub(crate) fn new(id: u64, peer: Option<SocketAddr>) -> Self {
+        Session {
+            id,
+            peer,
+            user: None,
+            dst: None,
+            traffic: Arc::new(Traffic::default()),
+            usage: None,
+            user_rate: None,
+            geo: None,
+            started: Instant::now(),
+        }
+    }
+
+    // 接続終了時のアクセスログ（key=value 形式の 1 行。エラーで終わった場合も出す）
+    // --log-format json では、同じ項目を JSON の項目として出す（bytes は両方向の合計）
+    pub(crate) fn log_access(&self, result: &io::Result<()>) {
+        if log::json_format() {
+            return self.log_access_json(result);
+        }
+        let outcome = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error error={:?}", e.to_string()),
+        };
+        // 国と AS 番号は、宛先がデータベースに含まれていたときだけ dst の後に付ける
+        let geo = match &self.geo {
+            Some(Geo { country, asn: Some(asn) }) => format!(" country={country} asn={asn}"),
+            Some(Geo { country, asn: None }) => format!(" country={country}"),
+            None => String::new(),
+        };
+        info!(
+            "access conn={} client={} user={:?} dst={:?}{geo} sent={} received={} duration_ms={} result={outcome}",
+            self.id,
+            peer_label(self.peer),
+            self.user.as_deref().unwrap_or("-"),
+            self.dst.as_deref().unwrap_or("-"),
+            self.traffic.sent.load(Ordering::Relaxed),
+            self.traffic.received.load(Ordering::Relaxed),
+            self.started.elapsed().as_millis(),
+        );
+    }
+
+    fn log_access_json(&self, result: &io::Result<()>) {
+        let sent = self.traffic.sent.load(Ordering::Relaxed);
+        let received = self.traffic.received.load(Ordering::Relaxed);
+        let client = peer_label(self.peer);
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let mut fields = vec![
+            ("event", Field::Str("access")),
+            ("client", Field::Str(&client)),
+            ("user", Field::Str(self.user.as_deref().unwrap_or("-"))),
+            ("dest", Field::Str(self.dst.as_deref().unwrap_or("-"))),
+            ("sent", Field::Num(sent)),
+            ("received", Field::Num(received)),
+            ("bytes", Field::Num(sent + received)),
+            ("duration_ms", Field::Num(self.started.elapsed().as_millis() as u64)),
+            ("result", Field::Str(if error.is_some() { "error" } else { "ok" })),
+        ];
+        if let Some(geo) = &self.geo {
+            fields.push(("country", Field::Str(&geo.country)));
+            if let Some(asn) = geo.asn {
+                fields.push(("asn", Field::Num(u64::from(asn))));
+            }
+        }
+        if let Some(error) = &error {
+            fields.push(("error", Field::Str(error)));
+        }
+        log::write_json(Level::Info, "access", &fields);
+    }
+}
@@ -0,0 +1,48 @@
+// SIGINT / SIGTERM / SIGHUP の捕捉（外部クレートを使わず、libc の signal() を直接呼ぶ）
+// ハンドラではフラグを立てるだけで、accept ループ（SIGHUP は読み直し用のスレッド）がそれを見て処理する
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+// 終了要求が来ているか
+pub(crate) fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+// 前回の確認以降に読み直しの要求（SIGHUP）が来ていたか。確認するとフラグは下ろす
+pub(crate) fn take_reload_request() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+pub(crate) fn install_handlers() {
+    use std::os::raw::c_int;
+
+    const SIGHUP: c_int = 1;
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    // シグナルハンドラ内ではアトミック変数への書き込みのみ行う（async-signal-safe）
+    extern "C" fn on_signal(_signum: c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    extern "C" fn on_hangup(_signum: c_int) {
+        RELOAD.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        signal(SIGINT, on_signal);
+        signal(SIGTERM, on_signal);
+        signal(SIGHUP, on_hangup);
+    }
+}
+
+// Unix 以外ではハンドラを登録しない（従来どおり即座に終了する）
+#[cfg(not(unix))]
+pub(crate) fn install_handlers() {}
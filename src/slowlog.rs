@@ -0,0 +1,94 @@
+// 時間のかかった宛先への接続（名前解決と接続にかかった時間）の上位 N 件（--slow-connects）
+// メトリクスのエンドポイントの GET /slow で、遅い順に返す
+// 接続のたびに呼ぶため、一覧の最も速いものより速かった接続は、アトミック変数を 1 回読むだけで捨てる
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+struct SlowConnect {
+    dst: String,
+    resolve: Duration,
+    connect: Duration,
+    ok: bool,
+}
+
+impl SlowConnect {
+    fn total(&self) -> Duration {
+        self.resolve + self.connect
+    }
+}
+
+// 遅い順に並べた一覧
+static SLOWEST: Mutex<Vec<SlowConnect>> = Mutex::new(Vec::new());
+// 一覧が埋まっているときは、その最も速いものの時間（マイクロ秒）。これ以下の接続は記録しない
+static THRESHOLD_US: AtomicU64 = AtomicU64::new(0);
+
+fn lock() -> MutexGuard<'static, Vec<SlowConnect>> {
+    SLOWEST.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// dst への接続が終わった（失敗も含む）。keep は残す件数（0 なら記録しない）
+pub(crate) fn record(
+    keep: usize,
+    dst: &dyn fmt::Display,
+    resolve: Duration,
+    connect: Duration,
+    ok: bool,
+) {
+    let total = resolve + connect;
+    if keep == 0 || total.as_micros() as u64 <= THRESHOLD_US.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut slowest = lock();
+    let i = slowest.partition_point(|s| s.total() >= total);
+    if i >= keep {
+        return;
+    }
+    slowest.insert(i, SlowConnect { dst: dst.to_string(), resolve, connect, ok });
+    slowest.truncate(keep);
+    let threshold = match slowest.get(keep - 1) {
+        Some(fastest) => fastest.total().as_micros() as u64,
+        None => 0,
+    };
+    THRESHOLD_US.store(threshold, Ordering::Relaxed);
+}
+
+// 1 行 1 件、遅い順
+pub(crate) fn render() -> String {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let mut out = String::new();
+    for s in lock().iter() {
+        let _ = writeln!(
+            out,
+            "{} total_ms={:.1} resolve_ms={:.1} connect_ms={:.1} result={}",
+            s.dst,
+            ms(s.total()),
+            ms(s.resolve),
+            ms(s.connect),
+            if s.ok { "ok" } else { "error" },
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_slowest_in_order() {
+        let ms = Duration::from_millis;
+        for (dst, total) in [("a:1", 30), ("b:1", 10), ("c:1", 50), ("d:1", 20), ("e:1", 40)] {
+            record(3, &dst, ms(1), ms(total - 1), dst != "e:1");
+        }
+        // 3 件埋まった後は、最も速いもの（30ms）以下は記録しない
+        record(3, &"f:1", ms(0), ms(30), true);
+        let text = render();
+        let order: Vec<&str> = text.lines().map(|l| l.split(' ').next().unwrap()).collect();
+        assert_eq!(order, ["c:1", "e:1", "a:1"]);
+        assert!(text.starts_with("c:1 total_ms=50.0 resolve_ms=1.0 connect_ms=49.0 result=ok\n"));
+        assert!(text.contains("e:1 total_ms=40.0 resolve_ms=1.0 connect_ms=39.0 result=error\n"));
+        assert_eq!(THRESHOLD_US.load(Ordering::Relaxed), 30_000);
+    }
+}
@@ -0,0 +1,461 @@
+// std から設定できないソケットオプションと、送信元アドレスを指定した接続（libc を直接呼ぶ）
+// 定数と構造体は Linux の値（アーキテクチャで異なるものは sys::abi で切り替える）
+// Linux 以外ではオプションの設定は何もせず、送信元の指定は未対応とする
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::net::SocketAddr;
+    use std::os::fd::AsRawFd;
+    use std::os::raw::{c_int, c_ulong, c_void};
+
+    pub(super) use abi::*;
+
+    // ソケットのレベルのオプション・ソケットの種類・errno は、アーキテクチャによって値が異なる
+    // （MIPS と SPARC は独自の値を持ち、それ以外は asm-generic の値）
+    #[cfg(not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6",
+        target_arch = "sparc",
+        target_arch = "sparc64"
+    )))]
+    mod abi {
+        use std::os::raw::c_int;
+        pub(crate) const SOL_SOCKET: c_int = 1;
+        pub(crate) const SO_REUSEADDR: c_int = 2;
+        pub(crate) const SO_ERROR: c_int = 4;
+        pub(crate) const SO_KEEPALIVE: c_int = 9;
+        pub(crate) const SO_LINGER: c_int = 13;
+        pub(crate) const SO_REUSEPORT: c_int = 15;
+        pub(crate) const SOCK_STREAM: c_int = 1;
+        pub(crate) const SOCK_NONBLOCK: c_int = 0o4000;
+        pub(crate) const SOCK_CLOEXEC: c_int = 0o2000000;
+        pub(crate) const EINPROGRESS: i32 = 115;
+    }
+
+    #[cfg(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6"
+    ))]
+    mod abi {
+        use std::os::raw::c_int;
+        pub(crate) const SOL_SOCKET: c_int = 0xffff;
+        pub(crate) const SO_REUSEADDR: c_int = 0x0004;
+        pub(crate) const SO_ERROR: c_int = 0x1007;
+        pub(crate) const SO_KEEPALIVE: c_int = 0x0008;
+        pub(crate) const SO_LINGER: c_int = 0x0080;
+        pub(crate) const SO_REUSEPORT: c_int = 0x0200;
+        pub(crate) const SOCK_STREAM: c_int = 2;
+        pub(crate) const SOCK_NONBLOCK: c_int = 0o200;
+        pub(crate) const SOCK_CLOEXEC: c_int = 0o2000000;
+        pub(crate) const EINPROGRESS: i32 = 150;
+    }
+
+    #[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+    mod abi {
+        use std::os::raw::c_int;
+        pub(crate) const SOL_SOCKET: c_int = 0xffff;
+        pub(crate) const SO_REUSEADDR: c_int = 0x0004;
+        pub(crate) const SO_ERROR: c_int = 0x1007;
+        pub(crate) const SO_KEEPALIVE: c_int = 0x0008;
+        pub(crate) const SO_LINGER: c_int = 0x0080;
+        pub(crate) const SO_REUSEPORT: c_int = 0x0200;
+        pub(crate) const SOCK_STREAM: c_int = 1;
+        pub(crate) const SOCK_NONBLOCK: c_int = 0x4000;
+        pub(crate) const SOCK_CLOEXEC: c_int = 0x400000;
+        pub(crate) const EINPROGRESS: i32 = 36;
+    }
+
+    pub(super) const IPPROTO_TCP: c_int = 6;
+    pub(super) const TCP_KEEPIDLE: c_int = 4;
+    pub(super) const TCP_KEEPINTVL: c_int = 5;
+    pub(super) const TCP_KEEPCNT: c_int = 6;
+    pub(super) const TCP_USER_TIMEOUT: c_int = 18;
+
+    // struct linger
+    #[repr(C)]
+    pub(super) struct Linger {
+        pub(super) onoff: c_int,
+        pub(super) linger: c_int,
+    }
+
+    pub(super) const AF_INET: c_int = 2;
+    pub(super) const AF_INET6: c_int = 10;
+    pub(super) const POLLOUT: i16 = 4;
+
+    // struct sockaddr_in / sockaddr_in6（ポートとアドレスはネットワークバイトオーダー）
+    #[repr(C)]
+    pub(super) struct SockaddrIn {
+        pub(super) family: u16,
+        pub(super) port: [u8; 2],
+        pub(super) addr: [u8; 4],
+        pub(super) zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    pub(super) struct SockaddrIn6 {
+        pub(super) family: u16,
+        pub(super) port: [u8; 2],
+        pub(super) flowinfo: u32,
+        pub(super) addr: [u8; 16],
+        pub(super) scope_id: u32,
+    }
+
+    #[repr(C)]
+    pub(super) struct PollFd {
+        pub(super) fd: c_int,
+        pub(super) events: i16,
+        pub(super) revents: i16,
+    }
+
+    unsafe extern "C" {
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        fn getsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *mut c_void,
+            len: *mut u32,
+        ) -> c_int;
+        pub(super) fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+        pub(super) fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        pub(super) fn connect(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+        pub(super) fn listen(fd: c_int, backlog: c_int) -> c_int;
+        pub(super) fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    }
+
+    // SocketAddr を sockaddr_in / sockaddr_in6 にして f に渡す
+    pub(super) fn with_sockaddr<R>(
+        addr: &SocketAddr,
+        f: impl FnOnce(*const c_void, u32) -> io::Result<R>,
+    ) -> io::Result<R> {
+        match addr {
+            SocketAddr::V4(a) => {
+                let sa = SockaddrIn {
+                    family: AF_INET as u16,
+                    port: a.port().to_be_bytes(),
+                    addr: a.ip().octets(),
+                    zero: [0; 8],
+                };
+                f(&sa as *const SockaddrIn as *const c_void, size_of::<SockaddrIn>() as u32)
+            }
+            SocketAddr::V6(a) => {
+                let sa = SockaddrIn6 {
+                    family: AF_INET6 as u16,
+                    port: a.port().to_be_bytes(),
+                    flowinfo: a.flowinfo(),
+                    addr: a.ip().octets(),
+                    scope_id: a.scope_id(),
+                };
+                f(&sa as *const SockaddrIn6 as *const c_void, size_of::<SockaddrIn6>() as u32)
+            }
+        }
+    }
+
+    // libc の戻り値が負ならエラー（errno）にする
+    pub(super) fn cvt(ret: c_int) -> io::Result<c_int> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    // 保留中のエラー（SO_ERROR）を取り出す
+    pub(super) fn take_error<S: AsRawFd>(sock: &S) -> io::Result<c_int> {
+        get(sock, SOL_SOCKET, SO_ERROR)
+    }
+
+    // int の値を 1 つ読む
+    pub(super) fn get<S: AsRawFd>(sock: &S, level: c_int, name: c_int) -> io::Result<c_int> {
+        let mut value: c_int = 0;
+        let mut len = std::mem::size_of::<c_int>() as u32;
+        let ret = unsafe {
+            getsockopt(
+                sock.as_raw_fd(),
+                level,
+                name,
+                &mut value as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            Ok(value)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    // 値を 1 つ設定する（値は int でも構造体でもよい）
+    pub(super) fn set<S: AsRawFd, T>(
+        sock: &S,
+        level: c_int,
+        name: c_int,
+        value: &T,
+    ) -> io::Result<()> {
+        let ret = unsafe {
+            setsockopt(
+                sock.as_raw_fd(),
+                level,
+                name,
+                value as *const T as *const c_void,
+                std::mem::size_of::<T>() as u32,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+// TCP keepalive を有効にする
+// idle: 無通信になってから最初のプローブまで、interval: プローブの間隔、probes: 応答なしで切断する回数
+#[cfg(target_os = "linux")]
+pub(crate) fn set_keepalive(
+    stream: &TcpStream,
+    idle: Duration,
+    interval: Duration,
+    probes: u32,
+) -> io::Result<()> {
+    use sys::*;
+    let secs = |d: Duration| d.as_secs().clamp(1, i32::MAX as u64) as i32;
+    set(stream, SOL_SOCKET, SO_KEEPALIVE, &1i32)?;
+    set(stream, IPPROTO_TCP, TCP_KEEPIDLE, &secs(idle))?;
+    set(stream, IPPROTO_TCP, TCP_KEEPINTVL, &secs(interval))?;
+    set(stream, IPPROTO_TCP, TCP_KEEPCNT, &(probes.clamp(1, i32::MAX as u32) as i32))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_keepalive(
+    _stream: &TcpStream,
+    _idle: Duration,
+    _interval: Duration,
+    _probes: u32,
+) -> io::Result<()> {
+    Ok(())
+}
+
+// 送信したデータが確認応答（ACK）されないまま timeout が過ぎたら、カーネルに接続を切らせる（TCP_USER_TIMEOUT）
+// 再送を諦めるまでの時間を短くし、keepalive より早く消えた相手を検出する。値はミリ秒で設定する
+#[cfg(target_os = "linux")]
+pub(crate) fn set_user_timeout(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    use sys::*;
+    let ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+    set(stream, IPPROTO_TCP, TCP_USER_TIMEOUT, &ms)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_user_timeout(_stream: &TcpStream, _timeout: Duration) -> io::Result<()> {
+    Ok(())
+}
+
+// close 時の動作（SO_LINGER）を設定する
+// linger の秒数だけ未送信のデータの送信を待つ。0 なら待たずに RST で閉じる（TIME_WAIT を残さない）
+#[cfg(target_os = "linux")]
+pub(crate) fn set_linger(stream: &TcpStream, linger: Duration) -> io::Result<()> {
+    use sys::*;
+    set(stream, SOL_SOCKET, SO_LINGER, &Linger { onoff: 1, linger: linger_secs(linger) })
+}
+
+// SO_LINGER の秒数。1 秒未満の端数は切り上げる（0.5 秒を 0 にすると、待つ代わりに RST で閉じてしまう）
+#[cfg(target_os = "linux")]
+fn linger_secs(linger: Duration) -> i32 {
+    linger.as_nanos().div_ceil(1_000_000_000).min(i32::MAX as u128) as i32
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_linger(_stream: &TcpStream, _linger: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_LINGER is only supported on Linux",
+    ))
+}
+
+// SO_REUSEPORT を付けて待ち受ける（同じアドレスで待ち受ける複数のプロセスに、カーネルが接続を振り分ける）
+// bind の前に設定する必要があるため、socket() → setsockopt() → bind() → listen() を直接呼ぶ
+// std の TcpListener::bind と同じく SO_REUSEADDR も設定する
+#[cfg(target_os = "linux")]
+pub(crate) fn listen_reuse_port(addr: &SocketAddr) -> io::Result<TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use sys::*;
+
+    let family = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+    let fd = cvt(unsafe { socket(family, SOCK_STREAM | SOCK_CLOEXEC, 0) })?;
+    // 以降は失敗時に OwnedFd の Drop で閉じられる
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    set(&sock, SOL_SOCKET, SO_REUSEADDR, &1i32)?;
+    set(&sock, SOL_SOCKET, SO_REUSEPORT, &1i32)?;
+    with_sockaddr(addr, |p, len| cvt(unsafe { bind(fd, p, len) }))?;
+    // バックログは std の TcpListener::bind と同じ値
+    cvt(unsafe { listen(fd, 128) })?;
+    Ok(TcpListener::from(sock))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn listen_reuse_port(_addr: &SocketAddr) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is only supported on Linux",
+    ))
+}
+
+// 宛先へタイムアウト付きで接続する。source を指定すると、その送信元アドレスに bind してから接続する
+// （複数のアドレスを持つホストで、経路が送信元アドレスで決まる場合など）
+pub(crate) fn connect_timeout(
+    addr: &SocketAddr,
+    source: Option<IpAddr>,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    match source {
+        None => TcpStream::connect_timeout(addr, timeout),
+        Some(ip) if ip.is_ipv4() != addr.is_ipv4() => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("source address {ip} cannot reach {addr}"),
+        )),
+        Some(ip) => connect_from(SocketAddr::new(ip, 0), addr, timeout),
+    }
+}
+
+// socket() → bind() → ノンブロッキングの connect() → poll() で完了を待つ
+#[cfg(target_os = "linux")]
+fn connect_from(local: SocketAddr, addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use sys::*;
+
+    let family = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+    let fd = cvt(unsafe { socket(family, SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, 0) })?;
+    // 以降は失敗時に OwnedFd の Drop で閉じられる
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    with_sockaddr(&local, |p, len| cvt(unsafe { bind(fd, p, len) }))?;
+    if let Err(e) = with_sockaddr(addr, |p, len| cvt(unsafe { connect(fd, p, len) })) {
+        if e.raw_os_error() != Some(EINPROGRESS) {
+            return Err(e);
+        }
+        let mut pfd = PollFd {
+            fd,
+            events: POLLOUT,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+        let ready = loop {
+            match cvt(unsafe { poll(&mut pfd, 1, ms) }) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        };
+        if ready == 0 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out"));
+        }
+        match take_error(&sock)? {
+            0 => {}
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+    let stream = TcpStream::from(sock);
+    stream.set_nonblocking(false)?;
+    Ok(stream)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_from(
+    _local: SocketAddr,
+    _addr: &SocketAddr,
+    _timeout: Duration,
+) -> io::Result<TcpStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding the source address is only supported on Linux",
+    ))
+}
+
+// 読み取り待ちのデータを、読み進めずに buf へ写す（recv の MSG_PEEK。std に無いソケット向け）
+// MSG_PEEK の値は Linux・macOS・BSD で共通
+#[cfg(unix)]
+pub(crate) fn peek<S: std::os::fd::AsRawFd>(sock: &S, buf: &mut [u8]) -> io::Result<usize> {
+    use std::os::raw::{c_int, c_void};
+
+    const MSG_PEEK: c_int = 2;
+    unsafe extern "C" {
+        fn recv(fd: c_int, buf: *mut c_void, len: usize, flags: c_int) -> isize;
+    }
+
+    loop {
+        let ret = unsafe {
+            recv(sock.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, buf.len(), MSG_PEEK)
+        };
+        if ret >= 0 {
+            return Ok(ret as usize);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+// IPv6 のゾーン（"eth0" のようなインターフェース名、または "2" のような番号）をインターフェース番号にする
+pub(crate) fn interface_index(zone: &str) -> io::Result<u32> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+    let not_found = || {
+        io::Error::new(io::ErrorKind::NotFound, format!("unknown network interface: {zone}"))
+    };
+    let name = std::ffi::CString::new(zone).map_err(|_| not_found())?;
+    match if_nametoindex(&name) {
+        0 => Err(not_found()),
+        index => Ok(index),
+    }
+}
+
+#[cfg(unix)]
+fn if_nametoindex(name: &std::ffi::CStr) -> u32 {
+    unsafe extern "C" {
+        fn if_nametoindex(name: *const std::os::raw::c_char) -> u32;
+    }
+    unsafe { if_nametoindex(name.as_ptr()) }
+}
+
+#[cfg(not(unix))]
+fn if_nametoindex(_name: &std::ffi::CStr) -> u32 {
+    0
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn user_timeout_is_set_in_milliseconds() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        set_user_timeout(&stream, Duration::from_secs(3)).unwrap();
+        assert_eq!(sys::get(&stream, sys::IPPROTO_TCP, sys::TCP_USER_TIMEOUT).unwrap(), 3000);
+    }
+
+    #[test]
+    fn linger_rounds_fractions_up() {
+        assert_eq!(linger_secs(Duration::ZERO), 0);
+        assert_eq!(linger_secs(Duration::from_millis(1)), 1);
+        assert_eq!(linger_secs(Duration::from_millis(500)), 1);
+        assert_eq!(linger_secs(Duration::from_secs(2)), 2);
+        assert_eq!(linger_secs(Duration::from_millis(2001)), 3);
+        assert_eq!(linger_secs(Duration::MAX), i32::MAX);
+    }
+}
@@ -0,0 +1,159 @@
+// SOCKS4/4a のフォールバック（先頭バイトが VER=0x04 の接続）
+use std::io::{self, ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr};
+
+use crate::authorize::authorize;
+use crate::error::SocksError;
+use crate::inspect::{blocked_reason, is_valid_dns_name};
+use crate::{idna, metrics, proxyproto};
+use crate::protocol::Dst;
+use crate::session::Session;
+use crate::relay::splice;
+use crate::rewrite::rewrite;
+use crate::stream::ClientStream;
+use crate::{Config, DenyMode, close_denied, log_connected, no_auth_allowed, open_remote};
+
+// SOCKS4/4a のリクエストを処理する（CONNECT のみ対応）
+// [VN=0x04, CD, DSTPORT(2), DSTIP(4), USERID, NUL]
+// SOCKS4a では DSTIP が 0.0.0.x (x != 0) で、USERID の後に [HOSTNAME, NUL] が続く
+pub(crate) fn handle_socks4<S: ClientStream>(
+    client: &mut S,
+    config: &Config,
+    session: &mut Session,
+) -> io::Result<()> {
+    let mut hdr = [0u8; 8];
+    client.read_exact(&mut hdr)?;
+    let cd = hdr[1];
+    let port = u16::from_be_bytes([hdr[2], hdr[3]]);
+    let ip = Ipv4Addr::new(hdr[4], hdr[5], hdr[6], hdr[7]);
+    let userid = read_null_terminated(client, "USERID")?;
+
+    // SOCKS4a: 0.0.0.x はホスト名が後続する印
+    let o = ip.octets();
+    let hostname = if o[0] == 0 && o[1] == 0 && o[2] == 0 && o[3] != 0 {
+        Some(read_null_terminated(client, "HOSTNAME")?)
+    } else {
+        None
+    };
+
+    // SOCKS4 には認証が無いため、SOCKS5 でも No Auth を選べないときは受け付けない
+    if !no_auth_allowed(config) {
+        warn!("rejected: SOCKS4 request while No Auth is not selectable");
+        client.write_all(&socks4_reply(0x5B, None))?;
+        client.flush()?;
+        return Err(SocksError::AuthFailed(
+            "authentication required (SOCKS4 has no authentication)".to_string(),
+        )
+        .into_io(ErrorKind::PermissionDenied));
+    }
+
+    if cd != 0x01 {
+        // CONNECT 以外は拒否（CD=91: request rejected or failed）
+        client.write_all(&socks4_reply(0x5B, None))?;
+        client.flush()?;
+        return Err(SocksError::UnsupportedCommand(cd).into_io(ErrorKind::Other));
+    }
+
+    // 国際化ドメイン名は ASCII 形式（xn--）にする。明らかに不正なホスト名は拒否する
+    let hostname = match hostname.as_deref().map(idna::to_ascii).transpose() {
+        Ok(hostname) => hostname,
+        Err(e) => {
+            warn!("invalid hostname rejected: {e}");
+            let _ = client.write_all(&socks4_reply(0x5B, None));
+            let _ = client.flush();
+            return Err(e);
+        }
+    };
+
+    // SOCKS5 と同じ遮断ルール（ポート・アドレス範囲・SOCKS4a のホスト名）を適用する
+    let mut dst = match hostname {
+        Some(host) => Dst::Domain(host, port),
+        None => Dst::V4(ip.octets(), port),
+    };
+    session.dst = Some(dst.to_string());
+    // 書き換え後のホスト名も validate_hostnames で判定する
+    rewrite(config, &mut dst);
+    if let Dst::Domain(host, _) = &dst
+        && config.validate_hostnames
+        && !is_valid_dns_name(host)
+    {
+        warn!("invalid hostname rejected: {host:?}");
+        let _ = client.write_all(&socks4_reply(0x5B, None));
+        let _ = client.flush();
+        return Err(SocksError::MalformedRequest(format!("invalid hostname: {host:?}"))
+            .into_io(ErrorKind::InvalidData));
+    }
+    // 組み込み側の許可判定で拒否されたら CD=91 を返す
+    if authorize(config, session, 0x01, &mut dst).is_some() {
+        let _ = client.write_all(&socks4_reply(0x5B, None));
+        let _ = client.flush();
+        return Err(SocksError::DestinationDenied(format!(
+            "destination {dst} denied by authorizer"
+        ))
+        .into_io(ErrorKind::PermissionDenied));
+    }
+    if let Some(reason) = blocked_reason(&dst, config) {
+        info!("blocked by ruleset: {dst} ({reason})");
+        if config.deny_mode == DenyMode::Reply {
+            let _ = client.write_all(&socks4_reply(0x5B, None));
+            let _ = client.flush();
+        } else {
+            close_denied(client, config);
+        }
+        return Err(SocksError::DestinationDenied(format!("blocked destination: {dst}"))
+            .into_io(ErrorKind::PermissionDenied));
+    }
+
+    let requested = dst.to_string();
+    info!("SOCKS4 request from user {userid:?}, destination: {requested}");
+
+    let (remote, bound) = match open_remote(&dst, config, None) {
+        Ok(v) => v,
+        Err(e) => {
+            metrics::connect_failed();
+            let _ = client.write_all(&socks4_reply(0x5B, None));
+            let _ = client.flush();
+            return Err(SocksError::connect_failed(e));
+        }
+    };
+
+    log_connected(&remote, &dst, config, session);
+    if config.send_proxy_protocol {
+        proxyproto::send_v1(&remote, client, session)?;
+    }
+    // 成功応答（CD=90: request granted）
+    client.write_all(&socks4_reply(0x5A, Some(bound)))?;
+    client.flush()?;
+
+    splice(client, remote, "", config, session)
+}
+
+// SOCKS4 の応答: [VN=0x00, CD, DSTPORT(2), DSTIP(4)]（IPv4 以外はゼロ）
+fn socks4_reply(cd: u8, bound: Option<SocketAddr>) -> [u8; 8] {
+    let mut reply = [0x00, cd, 0, 0, 0, 0, 0, 0];
+    if let Some(SocketAddr::V4(a)) = bound {
+        reply[2..4].copy_from_slice(&a.port().to_be_bytes());
+        reply[4..8].copy_from_slice(&a.ip().octets());
+    }
+    reply
+}
+
+// NUL 終端の文字列を読む（USERID / HOSTNAME 用。255 バイトを超えたら不正とする）
+fn read_null_terminated<R: Read>(client: &mut R, field: &str) -> io::Result<String> {
+    let mut out = Vec::new();
+    let mut b = [0u8; 1];
+    loop {
+        client.read_exact(&mut b)?;
+        if b[0] == 0x00 {
+            break;
+        }
+        if out.len() == 255 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("SOCKS4 {field} too long"),
+            ));
+        }
+        out.push(b[0]);
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
@@ -0,0 +1,121 @@
+// クライアントとの接続（TCP・Unix ドメインソケットなど）を同じ処理で扱うための共通の操作
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+// ハンドシェイクから転送までの処理（handle_connection）は、この trait だけを通して接続を扱う
+// TCP と Unix ドメインソケットのほか、テスト用のメモリ上のパイプなども実装できるよう、
+// ソケットにしか無い操作（アドレス・タイムアウト・ノンブロッキング）には何もしない既定の実装を持たせる
+pub trait ClientStream: Read + Write + Send + Sized {
+    // 複製（同じ接続の読み書きのハンドル。転送で方向ごとに使い、閉じる操作は接続全体に効く）
+    fn try_clone(&self) -> io::Result<Self>;
+    // 読み取り待ちのデータを、読み進めずに buf へ写す（データが届くまで待つ）
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    // 接続元のアドレス（Unix ドメインソケットなどには無いため None）
+    fn client_addr(&self) -> io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+    // このプロキシ側のアドレス（Unix ドメインソケットなどには無いため None）
+    fn server_addr(&self) -> io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+    // TCP の接続なら、その TcpStream（TCP のソケットオプションや BIND・UDP ASSOCIATE 用）
+    fn as_tcp(&self) -> Option<&TcpStream> {
+        None
+    }
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(None)
+    }
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClientStream for TcpStream {
+    fn client_addr(&self) -> io::Result<Option<SocketAddr>> {
+        self.peer_addr().map(Some)
+    }
+
+    fn server_addr(&self) -> io::Result<Option<SocketAddr>> {
+        self.local_addr().map(Some)
+    }
+
+    fn as_tcp(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        TcpStream::peek(self, buf)
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+#[cfg(unix)]
+impl ClientStream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    // std の UnixStream::peek は未安定のため、MSG_PEEK 付きの recv で読む
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        crate::sockopt::peek(self, buf)
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        UnixStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+}
+
+// ログに出す接続元（Unix ドメインソケットでは "unix"）
+pub(crate) fn peer_label(peer: Option<SocketAddr>) -> String {
+    peer.map_or_else(|| "unix".to_string(), |p| p.to_string())
+}
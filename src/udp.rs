@@ -0,0 +1,244 @@
+// UDP ASSOCIATE (CMD=0x03) の中継
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::{Config, dns, idna, log, metrics, with_default_zone};
+use crate::inspect::{blocked_address, blocked_reason};
+use crate::protocol::Dst;
+use crate::session::Session;
+use crate::protocol::{build_success_reply, push_socket_addr};
+
+// UDP ASSOCIATE (CMD=0x03) の処理
+// UDP ソケットを bind して BND.ADDR/BND.PORT を返し、TCP の制御接続が
+// 閉じられるまでデータグラムを中継する
+pub(crate) fn handle_udp_associate(
+    mut client: &TcpStream,
+    config: Arc<Config>,
+    session: &Session,
+) -> io::Result<()> {
+    // 制御接続と同じローカル IP に、空きポートで UDP ソケットを用意する
+    let local_ip = client.local_addr()?.ip();
+    let socket = UdpSocket::bind(SocketAddr::new(local_ip, 0))?;
+    let bound_addr = socket.local_addr()?;
+    info!("UDP relay bound: {bound_addr}");
+
+    client.write_all(&build_success_reply(bound_addr))?;
+    client.flush()?;
+
+    // 中継スレッドが停止フラグを確認できるよう、受信にタイムアウトを設定する
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let client_ip = client.peer_addr()?.ip();
+    let closed = Arc::new(AtomicBool::new(false));
+    let closed_flag = Arc::clone(&closed);
+    let traffic = Arc::clone(&session.traffic);
+    let usage = session.usage.clone();
+    let conn_id = log::conn_id();
+    let relay_thread = thread::spawn(move || {
+        log::set_conn_id(conn_id);
+        // TCP の転送と同じく、ユーザの転送量（両方向の合計）とメトリクスにも加算する
+        let sent: Vec<&AtomicU64> =
+            [Some(&traffic.sent), usage.as_deref(), Some(metrics::bytes_sent())]
+                .into_iter()
+                .flatten()
+                .collect();
+        let received: Vec<&AtomicU64> =
+            [Some(&traffic.received), usage.as_deref(), Some(metrics::bytes_received())]
+                .into_iter()
+                .flatten()
+                .collect();
+        relay_udp(&socket, client_ip, &closed_flag, &config, (&sent, &received))
+    });
+
+    // 制御接続は EOF（またはエラー）になるまで読み捨て、関連付けを維持する
+    let mut sink = [0u8; 512];
+    loop {
+        match client.read(&mut sink) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => continue,
+        }
+    }
+    closed.store(true, Ordering::Relaxed);
+    info!("UDP association closed: {bound_addr}");
+
+    match relay_thread.join() {
+        Ok(()) => Ok(()),
+        Err(_) => Err(io::Error::other("udp relay thread panicked")),
+    }
+}
+
+// UDP データグラムの中継ループ
+// クライアントから: [RSV(2), FRAG, ATYP, DST.ADDR, DST.PORT, DATA] を解いて宛先へ送る
+// 宛先から: 同じヘッダを付けてクライアントへ返す（クライアントが送った先からのものだけ）
+// 転送量（ヘッダを除くデータ部分）は (送信, 受信) の各カウンタに加算する
+fn relay_udp(
+    socket: &UdpSocket,
+    client_ip: IpAddr,
+    closed: &AtomicBool,
+    config: &Config,
+    (sent_counters, received_counters): (&[&AtomicU64], &[&AtomicU64]),
+) {
+    let mut buf = vec![0u8; 65535];
+    // クライアントの UDP 送信元は、最初のデータグラムで確定する
+    let mut client_udp: Option<SocketAddr> = None;
+    // クライアントが送った先。それ以外の送信元からのデータグラムは、クライアントへ返さずに捨てる
+    let mut peers: HashSet<SocketAddr> = HashSet::new();
+
+    while !closed.load(Ordering::Relaxed) {
+        let (n, src) = match socket.recv_from(&mut buf) {
+            Ok(v) => v,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                warn!("udp relay error: {e}");
+                return;
+            }
+        };
+
+        let from_client = match client_udp {
+            Some(addr) => src == addr,
+            None => src.ip() == client_ip,
+        };
+
+        if from_client {
+            client_udp = Some(src);
+            // FRAG != 0（断片化）は未対応のため破棄する
+            let Some((target, header_len)) = parse_udp_header(&buf[..n], config) else {
+                continue;
+            };
+            match socket.send_to(&buf[header_len..n], target) {
+                Ok(sent) => {
+                    peers.insert(target);
+                    for counter in sent_counters {
+                        counter.fetch_add(sent as u64, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => warn!("udp send to {target} failed: {e}"),
+            }
+        } else if !peers.contains(&src) {
+            debug!("udp: dropped datagram from {src} (not a destination of this association)");
+        } else if let Some(addr) = client_udp {
+            let mut packet = Vec::with_capacity(4 + 16 + 2 + n);
+            packet.extend_from_slice(&[0x00, 0x00, 0x00]); // RSV, FRAG
+            push_socket_addr(&mut packet, src);
+            packet.extend_from_slice(&buf[..n]);
+            match socket.send_to(&packet, addr) {
+                Ok(_) => {
+                    for counter in received_counters {
+                        counter.fetch_add(n as u64, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => warn!("udp send to client {addr} failed: {e}"),
+            }
+        }
+    }
+}
+
+// SOCKS5 UDP ヘッダを解析し、宛先とヘッダ長を返す（不正・断片化されたものは None）
+fn parse_udp_header(packet: &[u8], config: &Config) -> Option<(SocketAddr, usize)> {
+    if packet.len() < 4 || packet[0] != 0x00 || packet[1] != 0x00 || packet[2] != 0x00 {
+        return None;
+    }
+    let (dst, header_len) = match packet[3] {
+        0x01 => {
+            // IPv4
+            let b = packet.get(4..10)?;
+            let port = u16::from_be_bytes([b[4], b[5]]);
+            (Dst::V4([b[0], b[1], b[2], b[3]], port), 10)
+        }
+        0x03 => {
+            // DOMAIN
+            let len = *packet.get(4)? as usize;
+            let name = packet.get(5..5 + len)?;
+            let p = packet.get(5 + len..7 + len)?;
+            let port = u16::from_be_bytes([p[0], p[1]]);
+            let host = idna::to_ascii(&String::from_utf8_lossy(name)).ok()?;
+            (Dst::Domain(host, port), 7 + len)
+        }
+        0x04 => {
+            // IPv6
+            let b = packet.get(4..22)?;
+            let mut ip6 = [0u8; 16];
+            ip6.copy_from_slice(&b[..16]);
+            let port = u16::from_be_bytes([b[16], b[17]]);
+            (Dst::V6(ip6, port), 22)
+        }
+        _ => return None,
+    };
+
+    // UDP でも遮断対象の宛先は破棄する
+    if let Some(reason) = blocked_reason(&dst, config) {
+        info!("udp: blocked by ruleset: {dst} ({reason})");
+        return None;
+    }
+    let target = match &dst {
+        Dst::Domain(host, port) => *dns::resolve(host, *port, config).ok()?.first()?,
+        _ => SocketAddr::new(dst.ip()?, dst.port()),
+    };
+    if let Some(reason) = blocked_address(target.ip(), config) {
+        info!("udp: blocked by ruleset: {target} ({reason})");
+        return None;
+    }
+    let target = with_default_zone(target, config)
+        .inspect_err(|e| info!("udp: {e}"))
+        .ok()?;
+    Some((target, header_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 127.0.0.1:port 宛てのヘッダ
+    fn ipv4_header(port: u16) -> Vec<u8> {
+        let mut packet = vec![0x00, 0x00, 0x00, 0x01, 127, 0, 0, 1];
+        packet.extend_from_slice(&port.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn parses_an_ipv4_header() {
+        let mut packet = ipv4_header(5353);
+        packet.extend_from_slice(b"data");
+        let (target, len) = parse_udp_header(&packet, &Config::default()).unwrap();
+        assert_eq!(target, "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(&packet[len..], b"data");
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let config = Config::default();
+        let packet = ipv4_header(5353);
+        for n in 0..packet.len() {
+            assert_eq!(parse_udp_header(&packet[..n], &config), None, "{n} bytes");
+        }
+        // ドメイン名が LEN より短い・ポートが欠けている
+        let mut domain = vec![0x00, 0x00, 0x00, 0x03, 9];
+        domain.extend_from_slice(b"localhost");
+        assert_eq!(parse_udp_header(&domain, &config), None);
+        assert_eq!(parse_udp_header(&domain[..8], &config), None);
+        // IPv6 のアドレスが途中まで
+        let ipv6 = [0x00, 0x00, 0x00, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0];
+        assert_eq!(parse_udp_header(&ipv6, &config), None);
+    }
+
+    #[test]
+    fn rejects_fragments_reserved_bytes_and_unknown_atyp() {
+        let config = Config::default();
+        for (i, value) in [(2, 0x01), (0, 0x01), (1, 0x01), (3, 0x05)] {
+            let mut packet = ipv4_header(5353);
+            packet[i] = value;
+            assert_eq!(parse_udp_header(&packet, &config), None, "byte {i} = {value:#04x}");
+        }
+    }
+
+    #[test]
+    fn applies_the_destination_rules() {
+        let config = Config { allowed_ports: vec![53], ..Config::default() };
+        assert_eq!(parse_udp_header(&ipv4_header(5353), &config), None);
+        assert!(parse_udp_header(&ipv4_header(53), &config).is_some());
+    }
+}
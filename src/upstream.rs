@@ -0,0 +1,139 @@
+// 上位の SOCKS5 プロキシを経由した接続（--upstream）
+// 宛先へ直接接続する代わりに、上位プロキシへクライアントとしてハンドシェイクし CONNECT を依頼する
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+
+use crate::Config;
+use crate::protocol::{Dst, push_dst, read_dst};
+
+// 上位プロキシが返した失敗の REP（クライアントにはこの値をそのまま返す）
+#[derive(Debug)]
+pub(crate) struct UpstreamReply(pub(crate) u8);
+
+impl fmt::Display for UpstreamReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "upstream proxy replied REP=0x{:02X}", self.0)
+    }
+}
+
+impl Error for UpstreamReply {}
+
+// 上位プロキシ経由で dst へ接続し、接続と上位プロキシの BND.ADDR を返す
+// ドメイン名は名前解決せずにそのまま上位プロキシへ渡す
+// ハンドシェイクの各読み書きには config.connect.timeout を適用する
+pub(crate) fn connect_via(
+    upstream: &str,
+    dst: &Dst,
+    config: &Config,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut stream = connect_upstream(upstream, config)?;
+    stream.set_read_timeout(Some(config.connect.timeout))?;
+    stream.set_write_timeout(Some(config.connect.timeout))?;
+
+    // 1) Greeting: 認証情報があればユーザ/パスワード認証 (0x02) も提示する
+    let greeting: &[u8] = match config.upstream_auth {
+        Some(_) => &[0x05, 0x02, 0x00, 0x02],
+        None => &[0x05, 0x01, 0x00],
+    };
+    stream.write_all(greeting)?;
+    let mut selection = [0u8; 2];
+    stream.read_exact(&mut selection)?;
+    if selection[0] != 0x05 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("upstream proxy sent VER=0x{:02X}", selection[0]),
+        ));
+    }
+    match selection[1] {
+        0x00 => {}
+        0x02 => authenticate(&mut stream, config)?,
+        method => {
+            return Err(io::Error::other(format!(
+                "upstream proxy selected unsupported method 0x{method:02X}"
+            )));
+        }
+    }
+
+    // 2) CONNECT の Request をそのまま中継する
+    let mut request = vec![0x05, 0x01, 0x00];
+    push_dst(&mut request, dst)?;
+    stream.write_all(&request)?;
+    stream.flush()?;
+
+    // 3) 応答: [VER, REP, RSV, ATYP, BND.ADDR, BND.PORT]
+    let mut hdr = [0u8; 4];
+    stream.read_exact(&mut hdr)?;
+    let rep = hdr[1];
+    if rep != 0x00 {
+        return Err(io::Error::new(kind_for_reply(rep), UpstreamReply(rep)));
+    }
+    let bound = read_dst(hdr[3], &mut stream, u8::MAX.into())?;
+    let bound = match bound.ip() {
+        Some(ip) => SocketAddr::new(ip, bound.port()),
+        None => stream.local_addr()?,
+    };
+
+    stream.set_read_timeout(None)?;
+    stream.set_write_timeout(None)?;
+    info!("connected to {dst} via upstream {upstream}");
+    Ok((stream, bound))
+}
+
+// 上位プロキシへ TCP 接続する（名前解決した各アドレスを、宛先と同じ config.connect の進め方で試す）
+// 上位プロキシは社内ネットワークにあることが多いため、block_private は適用しない
+fn connect_upstream(upstream: &str, config: &Config) -> io::Result<TcpStream> {
+    let candidates: Vec<SocketAddr> = upstream.to_socket_addrs()?.collect();
+    if candidates.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::AddrNotAvailable,
+            "upstream: no addresses resolved",
+        ));
+    }
+    config
+        .connect
+        .connect(candidates, config.bind_source)
+        .inspect_err(|e| info!("connect to upstream {upstream} failed: {e}"))
+}
+
+// RFC1929: [VER=0x01, ULEN, UNAME, PLEN, PASSWD] を送り、[VER, STATUS] を受け取る
+fn authenticate(stream: &mut TcpStream, config: &Config) -> io::Result<()> {
+    let (user, pass) = config.upstream_auth.as_ref().ok_or_else(|| {
+        io::Error::other("upstream proxy requires authentication but no credentials are set")
+    })?;
+    if user.len() > 255 || pass.len() > 255 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "upstream username and password must be at most 255 bytes",
+        ));
+    }
+    let mut msg = vec![0x01, user.len() as u8];
+    msg.extend_from_slice(user.as_bytes());
+    msg.push(pass.len() as u8);
+    msg.extend_from_slice(pass.as_bytes());
+    stream.write_all(&msg)?;
+    stream.flush()?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status)?;
+    if status[1] != 0x00 {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "upstream proxy rejected the credentials",
+        ));
+    }
+    Ok(())
+}
+
+// 上位プロキシの REP を ErrorKind に対応付ける（reply_code_for の逆。ログと SOCKS4 用）
+fn kind_for_reply(rep: u8) -> ErrorKind {
+    match rep {
+        0x02 => ErrorKind::PermissionDenied,
+        0x03 => ErrorKind::NetworkUnreachable,
+        0x04 => ErrorKind::HostUnreachable,
+        0x05 => ErrorKind::ConnectionRefused,
+        0x06 => ErrorKind::TimedOut,
+        _ => ErrorKind::Other,
+    }
+}
@@ -0,0 +1,58 @@
+// 接続元の判定（Config::accept_filter）の結合テスト
+// 関数が拒否した接続元はハンドシェイクの前に閉じられ、それ以外はそのまま処理されることを確かめる
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use seccamp2025mini_online::{AcceptFilter, Config, spawn_server};
+
+mod common;
+
+#[test]
+fn filter_rejects_peers_by_port() {
+    // 送信元ポートが 4 の倍数の接続元だけを拒否し、判定した接続元を記録する
+    // （Linux は connect の送信元に偶数のポートを選ぶため、偶奇では分けられない）
+    let seen: Arc<Mutex<Vec<SocketAddr>>> = Arc::default();
+    let record = Arc::clone(&seen);
+    let filter = AcceptFilter::new(move |peer| {
+        record.lock().unwrap().push(peer);
+        !peer.port().is_multiple_of(4)
+    });
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        accept_filter: Some(filter),
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+
+    let (mut accepted, mut rejected) = (0, 0);
+    while accepted == 0 || rejected == 0 {
+        assert!(accepted + rejected < 50, "no port of both kinds was chosen");
+        let mut client = common::client(proxy);
+        let port = client.local_addr().unwrap().port();
+        let _ = client.write_all(&[0x05, 0x01, 0x00]);
+        let mut selection = [0u8; 2];
+        let answered = client.read_exact(&mut selection).is_ok();
+        if !port.is_multiple_of(4) {
+            assert!(answered, "port {port} should be accepted");
+            assert_eq!(selection, [0x05, 0x00]);
+            accepted += 1;
+        } else {
+            assert!(!answered, "port {port} should be rejected");
+            rejected += 1;
+        }
+    }
+    server.shutdown().unwrap();
+    assert_eq!(seen.lock().unwrap().len(), accepted + rejected);
+}
+
+#[test]
+fn default_filter_accepts_everyone() {
+    let proxy = common::spawn_proxy(Config {
+        accept_filter: Some(AcceptFilter::default()),
+        ..Config::default()
+    });
+    let client = common::socks5_connect(proxy, common::spawn_echo());
+    drop(client);
+}
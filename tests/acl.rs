@@ -0,0 +1,137 @@
+// ユーザごとの宛先の制限（ACL）の結合テスト
+// 認証したユーザの ACL に合う宛先だけに接続でき、合わない宛先は REP=0x02 で拒否されることを確かめる
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Acl, Config, handle_connection};
+
+// 接続を受け付けるだけの宛先を立て、そのアドレスを返す
+fn spawn_target() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            drop(stream);
+        }
+    });
+    addr
+}
+
+// alice（ACL あり）と bob（ACL なし）のアカウントで認証するプロキシを立て、そのアドレスを返す
+fn spawn_proxy(alice: Acl) -> SocketAddr {
+    let accounts = HashMap::from([
+        (
+            "alice".to_string(),
+            Account { password: "pw".to_string(), acl: Some(alice), ..Account::default() },
+        ),
+        ("bob".to_string(), Account { password: "pw".to_string(), ..Account::default() }),
+    ]);
+    let config = Arc::new(Config {
+        auth: true,
+        credentials: Some(Arc::new(RwLock::new(accounts))),
+        ..Config::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let config = Arc::clone(&config);
+            thread::spawn(move || {
+                let _ = handle_connection(&mut stream, &config);
+            });
+        }
+    });
+    addr
+}
+
+// user で認証してから 127.0.0.1:port への CONNECT を送り、応答の REP を返す
+fn connect_as(proxy: SocketAddr, user: &str, port: u16) -> u8 {
+    let mut dst = vec![0x01, 127, 0, 0, 1];
+    dst.extend_from_slice(&port.to_be_bytes());
+    request_as(proxy, user, &dst)
+}
+
+// user で認証してから ATYP 以降が dst の CONNECT を送り、応答の REP を返す
+fn request_as(proxy: SocketAddr, user: &str, dst: &[u8]) -> u8 {
+    let mut client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(&[0x05, 0x01, 0x02]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x02]);
+
+    let mut auth = vec![0x01, user.len() as u8];
+    auth.extend_from_slice(user.as_bytes());
+    auth.extend_from_slice(&[2, b'p', b'w']);
+    client.write_all(&auth).unwrap();
+    let mut status = [0u8; 2];
+    client.read_exact(&mut status).unwrap();
+    assert_eq!(status, [0x01, 0x00]);
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    request.extend_from_slice(dst);
+    client.write_all(&request).unwrap();
+    let mut reply = [0u8; 2];
+    client.read_exact(&mut reply).unwrap();
+    reply[1]
+}
+
+#[test]
+fn acl_allows_listed_port_and_denies_others() {
+    let allowed = spawn_target();
+    let other = spawn_target();
+    let proxy = spawn_proxy(Acl { networks: Vec::new(), ports: vec![allowed.port()] });
+
+    assert_eq!(connect_as(proxy, "alice", allowed.port()), 0x00);
+    assert_eq!(connect_as(proxy, "alice", other.port()), 0x02);
+}
+
+#[test]
+fn acl_denies_address_outside_networks() {
+    let target = spawn_target();
+    let proxy = spawn_proxy(Acl {
+        networks: vec!["10.0.0.0/8".parse().unwrap()],
+        ports: Vec::new(),
+    });
+
+    assert_eq!(connect_as(proxy, "alice", target.port()), 0x02);
+}
+
+#[test]
+fn user_without_acl_uses_global_rules() {
+    let target = spawn_target();
+    let proxy = spawn_proxy(Acl { networks: Vec::new(), ports: vec![1] });
+
+    assert_eq!(connect_as(proxy, "bob", target.port()), 0x00);
+}
+
+// localhost:port の DOMAIN 指定の宛先（ATYP 以降）
+fn localhost(port: u16) -> Vec<u8> {
+    let mut dst = vec![0x03, 9];
+    dst.extend_from_slice(b"localhost");
+    dst.extend_from_slice(&port.to_be_bytes());
+    dst
+}
+
+#[test]
+fn acl_checks_the_resolved_addresses_of_domains() {
+    let target = spawn_target();
+    let denied = spawn_proxy(Acl {
+        networks: vec!["10.0.0.0/8".parse().unwrap()],
+        ports: Vec::new(),
+    });
+    assert_eq!(request_as(denied, "alice", &localhost(target.port())), 0x02);
+
+    // localhost の名前解決の結果（127.0.0.1 と ::1）が全て含まれるなら接続できる
+    let allowed = spawn_proxy(Acl {
+        networks: vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+        ports: Vec::new(),
+    });
+    assert_eq!(request_as(allowed, "alice", &localhost(target.port())), 0x00);
+}
@@ -0,0 +1,22 @@
+// 宛先ポートの許可の一覧（Config::allowed_ports）の結合テスト
+// 一覧にあるポートにだけ接続でき、それ以外は REP=0x02 で拒否されることを確かめる
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{assert_echo, connect_request, domain_request, socks5_connect, socks5_request};
+
+#[test]
+fn only_listed_ports_are_allowed() {
+    let (allowed, other) = (common::spawn_echo(), common::spawn_echo());
+    let proxy = common::spawn_proxy(Config {
+        allowed_ports: vec![443, allowed.port()],
+        ..Config::default()
+    });
+
+    assert_echo(&mut socks5_connect(proxy, allowed));
+    let (_, reply) = socks5_request(proxy, &connect_request(other));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    // ドメイン名の宛先も、名前を解決する前にポートで判定する
+    let (_, reply) = socks5_request(proxy, &domain_request(b"localhost", other.port()));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
@@ -0,0 +1,63 @@
+// 認証の試行の監査記録（Config::audit_log）の結合テスト
+// 成功・失敗のどちらも、接続 ID 付きの [AUDIT] の行として監査ログのファイルに残ることを確かめる
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{account, client, credentials, userpass};
+
+// ユーザ/パスワード認証を行い、認証の応答の STATUS を返す
+fn login(proxy: SocketAddr, user: &str, password: &str) -> u8 {
+    let mut client = client(proxy);
+    client.write_all(&[0x05, 0x01, 0x02]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x02]);
+    client.write_all(&userpass(user, password)).unwrap();
+    let mut status = [0u8; 2];
+    client.read_exact(&mut status).unwrap();
+    status[1]
+}
+
+#[test]
+fn successful_and_failed_logins_are_audited() {
+    let path = env::temp_dir().join(format!("audit-{}.log", process::id()));
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        auth: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        audit_log: Some(path.to_string_lossy().into_owned()),
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+
+    assert_eq!(login(proxy, "alice", "secret"), 0x00);
+    assert_ne!(login(proxy, "alice", "wrong"), 0x00);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        if text.lines().count() >= 2 || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    server.shutdown().unwrap();
+    let _ = fs::remove_file(&path);
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2, "{text}");
+    for (line, result) in lines.iter().zip(["success", "failure"]) {
+        assert!(line.contains("[AUDIT] [conn "), "{line}");
+        assert!(line.contains(" user=\"alice\""), "{line}");
+        assert!(line.ends_with(&format!(" result=\"{result}\"")), "{line}");
+    }
+}
@@ -0,0 +1,36 @@
+// 認証失敗による締め出し（Config::auth_fail_limit / auth_ban_duration）の結合テスト
+// 上限まで失敗した IP は Greeting を読まずに閉じられることを確かめる
+// 締め出しの記録はプロセス全体で共有され、接続元は全て 127.0.0.1 のため、1 つのテストで確かめる
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{account, client, credentials, userpass};
+
+#[test]
+fn clients_are_banned_after_repeated_failures() {
+    let proxy = common::spawn_proxy(Config {
+        auth: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        auth_fail_limit: 2,
+        // Instant に足すと溢れる長さでも、締め出しは続けられる
+        auth_ban_duration: Duration::MAX,
+        ..Config::default()
+    });
+    let login = |password: &str| {
+        let mut client = client(proxy);
+        let mut input = vec![0x05, 0x01, 0x02];
+        input.extend_from_slice(&userpass("alice", password));
+        client.write_all(&input).unwrap();
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response);
+        response
+    };
+
+    assert_eq!(login("wrong"), [0x05, 0x02, 0x01, 0x01]);
+    assert_eq!(login("wrong"), [0x05, 0x02, 0x01, 0x01]);
+    // 正しいパスワードでも、締め出し中はメソッド選択も返さずに閉じる
+    assert_eq!(login("secret"), []);
+}
@@ -0,0 +1,44 @@
+// 組み込み側の許可判定（Config::authorizer）の結合テスト
+// 判定関数の結果どおりに、拒否の REP を返すこと・宛先を置き換えて接続することを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use seccamp2025mini_online::{Authorizer, Config, Decision, Destination};
+
+mod common;
+use common::{domain_request, socks5_request, spawn_echo};
+
+fn spawn_proxy(authorizer: Authorizer) -> SocketAddr {
+    common::spawn_proxy(Config { authorizer: Some(authorizer), ..Config::default() })
+}
+
+// example.com:80 への CONNECT を送り、Greeting の選択の後の応答を返す
+fn connect_example(proxy: SocketAddr) -> (TcpStream, Vec<u8>) {
+    socks5_request(proxy, &domain_request(b"example.com", 80))
+}
+
+#[test]
+fn denied_requests_get_the_chosen_reply_code() {
+    let proxy = spawn_proxy(Authorizer::new(|info, dst| {
+        assert_eq!(info.command, 0x01);
+        assert_eq!(info.user, None);
+        assert_eq!(*dst, Destination::Domain("example.com".to_string(), 80));
+        Decision::Deny(0x05)
+    }));
+    let (_, reply) = connect_example(proxy);
+    assert_eq!(reply[..2], [0x05, 0x05]);
+}
+
+#[test]
+fn rewritten_destination_is_connected_instead() {
+    let echo = spawn_echo();
+    let proxy = spawn_proxy(Authorizer::new(move |_, _| {
+        Decision::Rewrite(Destination::Addr(echo))
+    }));
+    let (mut client, reply) = connect_example(proxy);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    client.write_all(b"ping").unwrap();
+    let mut echoed = [0u8; 4];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"ping");
+}
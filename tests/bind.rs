@@ -0,0 +1,93 @@
+// BIND (CMD=0x02) の結合テスト
+// DST.ADDR の相手からの接続だけを受け付け、それ以外の相手は閉じて待ち続けることを確かめる
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{client, read_reply};
+
+// DST.ADDR を expected、DST.PORT を port として BIND を送り、1 回目の応答と接続を返す
+fn bind_request(config: Config, expected: Ipv4Addr, port: u16) -> (TcpStream, Vec<u8>) {
+    let mut client = client(common::spawn_proxy(config));
+    let mut input = vec![0x05, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01];
+    input.extend_from_slice(&expected.octets());
+    input.extend_from_slice(&port.to_be_bytes());
+    client.write_all(&input).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    let reply = read_reply(&mut client);
+    (client, reply)
+}
+
+// DST.ADDR を expected として BIND を送り、1 回目の応答の待ち受けアドレスと接続を返す
+fn bind(config: Config, expected: Ipv4Addr) -> (TcpStream, SocketAddr) {
+    let (client, reply) = bind_request(config, expected, 0);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    let ip = Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+    (client, SocketAddr::from((ip, port)))
+}
+
+#[test]
+fn expected_peer_is_accepted_and_relayed() {
+    let (mut client, bound) = bind(Config::default(), Ipv4Addr::LOCALHOST);
+    let mut peer = TcpStream::connect(bound).unwrap();
+    let reply = read_reply(&mut client);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_eq!(u16::from_be_bytes([reply[8], reply[9]]), peer.local_addr().unwrap().port());
+
+    peer.write_all(b"from peer").unwrap();
+    let mut buf = [0u8; 9];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"from peer");
+}
+
+#[test]
+fn unexpected_peer_is_closed_until_the_timeout() {
+    let config = Config { bind_timeout: Duration::from_millis(500), ..Config::default() };
+    // 127.0.0.1 から接続するが、予定の相手は 127.0.0.2
+    let (mut client, bound) = bind(config, Ipv4Addr::new(127, 0, 0, 2));
+    let mut peer = TcpStream::connect(bound).unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(peer.read(&mut [0u8; 1]).unwrap(), 0);
+
+    // 予定の相手は現れないため、2 回目の応答は TTL expired になる
+    let reply = read_reply(&mut client);
+    assert_eq!(reply[..2], [0x05, 0x06]);
+}
+
+fn port_hint() -> Config {
+    Config { bind_port_hint: true, ..Config::default() }
+}
+
+#[test]
+fn port_hint_is_used_as_the_listening_port() {
+    // 空いているポートを調べてから閉じる
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let (_client, reply) = bind_request(port_hint(), Ipv4Addr::LOCALHOST, port);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_eq!(u16::from_be_bytes([reply[8], reply[9]]), port);
+}
+
+#[test]
+fn port_hint_in_use_is_answered_with_general_failure() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let (_client, reply) = bind_request(port_hint(), Ipv4Addr::LOCALHOST, port);
+    assert_eq!(reply[..2], [0x05, 0x01]);
+
+    // 無効なら DST.PORT は使わず、空きポートで待ち受ける
+    let (_client, reply) = bind_request(Config::default(), Ipv4Addr::LOCALHOST, port);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_ne!(u16::from_be_bytes([reply[8], reply[9]]), port);
+}
+
+#[test]
+fn privileged_port_hint_is_refused() {
+    let (_client, reply) = bind_request(port_hint(), Ipv4Addr::LOCALHOST, 80);
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
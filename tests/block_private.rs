@@ -0,0 +1,34 @@
+// プライベートな宛先の遮断（Config::block_private）の結合テスト
+// ループバックの宛先は IP アドレスでもドメイン名でも REP=0x02 で拒否されることを確かめる
+use std::net::SocketAddr;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{connect_request, domain_request, socks5_connect, socks5_request, spawn_echo};
+
+fn spawn_proxy(block_private: bool) -> SocketAddr {
+    common::spawn_proxy(Config { block_private, ..Config::default() })
+}
+
+#[test]
+fn loopback_addresses_are_refused() {
+    let proxy = spawn_proxy(true);
+    let (_, reply) = socks5_request(proxy, &connect_request(spawn_echo()));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    let (_, reply) = socks5_request(proxy, &connect_request("[::1]:80".parse().unwrap()));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+#[test]
+fn names_resolving_to_loopback_are_refused() {
+    let echo = spawn_echo();
+    let (_, reply) = socks5_request(spawn_proxy(true), &domain_request(b"localhost", echo.port()));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+#[test]
+fn loopback_is_allowed_without_the_option() {
+    let mut client = socks5_connect(spawn_proxy(false), spawn_echo());
+    common::assert_echo(&mut client);
+}
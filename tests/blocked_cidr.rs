@@ -0,0 +1,36 @@
+// 遮断するアドレス範囲（Config::blocked_cidrs）の結合テスト
+// IP アドレスの宛先だけでなく、名前解決した結果が範囲に入るドメイン名の宛先も REP=0x02 で拒否することを確かめる
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{connect_request, socks5_request, spawn_echo};
+
+fn blocking_loopback() -> Config {
+    // localhost は ::1 にも解決されうるため、IPv6 のループバックも遮断する
+    Config {
+        blocked_cidrs: vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+        ..Config::default()
+    }
+}
+
+#[test]
+fn literal_address_in_a_blocked_range_is_refused() {
+    let proxy = common::spawn_proxy(blocking_loopback());
+    let (_client, reply) = socks5_request(proxy, &connect_request(spawn_echo()));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+#[test]
+fn domain_resolving_into_a_blocked_range_is_refused() {
+    let port = spawn_echo().port();
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, 9];
+    request.extend_from_slice(b"localhost");
+    request.extend_from_slice(&port.to_be_bytes());
+
+    let (_client, reply) = socks5_request(common::spawn_proxy(blocking_loopback()), &request);
+    assert_eq!(reply[..2], [0x05, 0x02]);
+
+    // 遮断しなければ、同じ宛先に接続できる
+    let (_client, reply) = socks5_request(common::spawn_proxy(Config::default()), &request);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+}
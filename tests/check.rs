@@ -0,0 +1,29 @@
+// 設定の確認（--check）の結合テスト
+use std::net::TcpListener;
+
+use seccamp2025mini_online::{Config, Socks5Server};
+
+fn check(listen: &[String]) -> std::io::Result<()> {
+    let config = Config {
+        listen: listen.to_vec(),
+        check_only: true,
+        ..Config::default()
+    };
+    Socks5Server::new(config).run()
+}
+
+#[test]
+fn free_address_passes_and_is_released() {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    check(std::slice::from_ref(&addr)).unwrap();
+    // 確認の後はアドレスを握ったままにしない
+    TcpListener::bind(&addr).unwrap();
+}
+
+#[test]
+fn address_in_use_fails() {
+    let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = busy.local_addr().unwrap().to_string();
+    let err = check(&[addr]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,45 @@
+// 送信元アドレスの許可・拒否（Config::client_allow / client_deny）の結合テスト
+// 許可の一覧に入らないクライアントと拒否されたクライアントは、Greeting を読まずに閉じられることを確かめる
+use std::io::Read;
+use std::net::SocketAddr;
+
+use seccamp2025mini_online::{Cidr, Config};
+
+mod common;
+use common::{assert_echo, client, socks5_connect, spawn_echo};
+
+fn spawn_proxy(allow: &[&str], deny: &[&str]) -> SocketAddr {
+    let cidrs = |list: &[&str]| list.iter().map(|c| c.parse().unwrap()).collect::<Vec<Cidr>>();
+    common::spawn_proxy(Config {
+        client_allow: cidrs(allow),
+        client_deny: cidrs(deny),
+        ..Config::default()
+    })
+}
+
+// 応答を返さずに閉じられることを確かめる
+fn assert_closed(proxy: SocketAddr) {
+    let mut client = client(proxy);
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).unwrap();
+    assert!(output.is_empty(), "unexpected reply {output:?}");
+}
+
+#[test]
+fn clients_inside_the_allow_list_are_served() {
+    let proxy = spawn_proxy(&["10.0.0.0/8", "127.0.0.0/8"], &[]);
+    assert_echo(&mut socks5_connect(proxy, spawn_echo()));
+}
+
+#[test]
+fn clients_outside_the_allow_list_are_closed() {
+    assert_closed(spawn_proxy(&["10.0.0.0/8", "::1/128"], &[]));
+}
+
+#[test]
+fn deny_rules_take_precedence() {
+    assert_closed(spawn_proxy(&["127.0.0.0/8"], &["127.0.0.1"]));
+    // 拒否の一覧だけでも、それ以外のクライアントは許可される
+    let proxy = spawn_proxy(&[], &["10.0.0.0/8"]);
+    assert_echo(&mut socks5_connect(proxy, spawn_echo()));
+}
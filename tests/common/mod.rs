@@ -0,0 +1,173 @@
+// 結合テストで共有する部品（宛先の代わりのサーバ・プロキシの起動・SOCKS5 のクライアント側の操作）
+// 各テストファイルから `mod common;` で取り込む。ファイルごとに使わない関数もあるため dead_code を許す
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Config, SocksError, handle_connection};
+
+// 受け取ったデータをそのまま返す宛先を 127.0.0.1 の空きポートで立て、そのアドレスを返す
+pub fn spawn_echo() -> SocketAddr {
+    spawn_echo_on("127.0.0.1:0")
+}
+
+// 受け取ったデータをそのまま返す宛先を bind のアドレスで立てる（接続ごとにスレッドで処理する）
+pub fn spawn_echo_on(bind: &str) -> SocketAddr {
+    let listener = TcpListener::bind(bind).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                while let Ok(n @ 1..) = stream.read(&mut buf) {
+                    if stream.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+// 接続を受け付けるだけで、すぐに閉じる宛先
+pub fn spawn_target() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            drop(stream);
+        }
+    });
+    addr
+}
+
+// config のプロキシを空きポートで立て、そのアドレスを返す（接続ごとにスレッドで処理する）
+pub fn spawn_proxy(config: Config) -> SocketAddr {
+    spawn_proxy_with_results(config).0
+}
+
+// spawn_proxy と同じだが、接続ごとの handle_connection の結果も受け取れるようにする
+pub fn spawn_proxy_with_results(
+    config: Config,
+) -> (SocketAddr, Receiver<Result<(), SocksError>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(config);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let (config, tx) = (Arc::clone(&config), tx.clone());
+            thread::spawn(move || {
+                let _ = tx.send(handle_connection(&mut stream, &config));
+            });
+        }
+    });
+    (addr, rx)
+}
+
+pub type Credentials = Arc<RwLock<HashMap<String, Account>>>;
+
+// 認証情報ファイルの代わりに、Config::credentials に入れるアカウントの一覧
+pub fn credentials(accounts: Vec<(&str, Account)>) -> Option<Credentials> {
+    let accounts = accounts.into_iter().map(|(user, a)| (user.to_string(), a)).collect();
+    Some(Arc::new(RwLock::new(accounts)))
+}
+
+// パスワードだけのアカウント
+pub fn account(password: &str) -> Account {
+    Account { password: password.to_string(), ..Account::default() }
+}
+
+// プロキシへ接続する（読み込みは 5 秒で打ち切る）
+pub fn client(proxy: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client
+}
+
+// CONNECT の Request: [VER, CMD=0x01, RSV, ATYP, DST.ADDR, DST.PORT]
+pub fn connect_request(dst: SocketAddr) -> Vec<u8> {
+    let mut request = vec![0x05, 0x01, 0x00];
+    match dst {
+        SocketAddr::V4(a) => {
+            request.push(0x01);
+            request.extend_from_slice(&a.ip().octets());
+        }
+        SocketAddr::V6(a) => {
+            request.push(0x04);
+            request.extend_from_slice(&a.ip().octets());
+        }
+    }
+    request.extend_from_slice(&dst.port().to_be_bytes());
+    request
+}
+
+// ドメイン名（ATYP=0x03）の CONNECT の Request
+pub fn domain_request(host: &[u8], port: u16) -> Vec<u8> {
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    request.extend_from_slice(host);
+    request.extend_from_slice(&port.to_be_bytes());
+    request
+}
+
+// RFC1929 のユーザ/パスワード認証の要求
+pub fn userpass(user: &str, password: &str) -> Vec<u8> {
+    let mut auth = vec![0x01, user.len() as u8];
+    auth.extend_from_slice(user.as_bytes());
+    auth.push(password.len() as u8);
+    auth.extend_from_slice(password.as_bytes());
+    auth
+}
+
+// 応答 [VER, REP, RSV, ATYP, BND.ADDR, BND.PORT] を ATYP に応じた長さだけ読む
+pub fn read_reply<R: Read>(client: &mut R) -> Vec<u8> {
+    let mut reply = vec![0u8; 4];
+    client.read_exact(&mut reply).unwrap();
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        atyp => panic!("unexpected ATYP in reply: 0x{atyp:02X}"),
+    };
+    reply.resize(4 + addr_len + 2, 0);
+    client.read_exact(&mut reply[4..]).unwrap();
+    reply
+}
+
+// No Auth の Greeting に続けて request を送り、メソッド選択の後の応答を返す
+pub fn socks5_request(proxy: SocketAddr, request: &[u8]) -> (TcpStream, Vec<u8>) {
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x00];
+    input.extend_from_slice(request);
+    client.write_all(&input).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    let reply = read_reply(&mut client);
+    (client, reply)
+}
+
+// No Auth で dst へ CONNECT し、成功応答まで読んだ接続を返す
+pub fn socks5_connect(proxy: SocketAddr, dst: SocketAddr) -> TcpStream {
+    let (client, reply) = socks5_request(proxy, &connect_request(dst));
+    assert_eq!(reply[..3], [0x05, 0x00, 0x00], "reply {reply:?}");
+    client
+}
+
+// プロキシ越しに送ったデータが、そのまま返ってくることを確かめる
+pub fn assert_echo(client: &mut TcpStream) {
+    let message = b"hello through the proxy";
+    client.write_all(message).unwrap();
+    let mut echoed = [0u8; 23];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, message);
+}
@@ -0,0 +1,35 @@
+// クライアント IP ごとの新規接続数の上限（Config::conn_rate / conn_burst）の結合テスト
+// 連続で許す数を超えた接続は、Greeting を読まずに閉じられることを確かめる
+use std::io::Read;
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, client, socks5_connect, spawn_echo};
+
+#[test]
+fn connections_beyond_the_burst_are_closed() {
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        // テストの間にはトークンが戻らない速さ
+        conn_rate: 0.01,
+        conn_burst: 2,
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+    let dst = spawn_echo();
+
+    let mut first = socks5_connect(proxy, dst);
+    let mut second = socks5_connect(proxy, dst);
+    assert_echo(&mut first);
+    assert_echo(&mut second);
+
+    let mut third = client(proxy);
+    let mut output = Vec::new();
+    third.read_to_end(&mut output).unwrap();
+    assert!(output.is_empty(), "unexpected reply {output:?}");
+
+    drop((first, second, third));
+    server.shutdown().unwrap();
+}
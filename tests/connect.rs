@@ -0,0 +1,100 @@
+// CONNECT の結合テスト
+// ループバックで待ち受けたプロキシに実際のソケットで接続し、エコーサーバーの宛先との間で
+// データが往復すること（と成功応答）を、ATYP ごとに確かめる
+use std::io::{Read, Write};
+use std::net::{Ipv6Addr, SocketAddr, TcpListener};
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{assert_echo, client, domain_request, socks5_connect, spawn_echo_on};
+
+fn spawn_proxy() -> SocketAddr {
+    common::spawn_proxy(Config::default())
+}
+
+#[test]
+fn connect_ipv4() {
+    let echo = spawn_echo_on("127.0.0.1:0");
+    let mut client = socks5_connect(spawn_proxy(), echo);
+    assert_echo(&mut client);
+}
+
+#[test]
+fn connect_ipv6() {
+    let echo = spawn_echo_on("[::1]:0");
+    let mut client = socks5_connect(spawn_proxy(), echo);
+    assert_echo(&mut client);
+}
+
+#[test]
+fn connect_domain() {
+    let echo = spawn_echo_on("127.0.0.1:0");
+    let (mut client, reply) =
+        common::socks5_request(spawn_proxy(), &domain_request(b"localhost", echo.port()));
+    assert_eq!(reply[..3], [0x05, 0x00, 0x00]);
+    assert_echo(&mut client);
+}
+
+// ゾーンの無いリンクローカル IPv6 の宛先は、接続を試みずに Host unreachable (0x04) で拒否する
+#[test]
+fn link_local_ipv6_without_zone_is_host_unreachable() {
+    let mut client = client(spawn_proxy());
+    let mut request = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x04];
+    request.extend_from_slice(&"fe80::1".parse::<Ipv6Addr>().unwrap().octets());
+    request.extend_from_slice(&80u16.to_be_bytes());
+    client.write_all(&request).unwrap();
+
+    let mut reply = [0u8; 2 + 22];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_eq!(reply[2..6], [0x05, 0x04, 0x00, 0x04]);
+}
+
+// 誰も待ち受けていないポート（一度 bind して閉じたもの）
+fn closed_port(bind: &str) -> u16 {
+    TcpListener::bind(bind).unwrap().local_addr().unwrap().port()
+}
+
+// No Auth で CONNECT を送り、失敗応答の全体（[VER, REP, RSV, ATYP, BND.ADDR, BND.PORT]）を返す
+fn socks5_failure_reply(proxy: SocketAddr, dst: &[u8]) -> Vec<u8> {
+    let mut client = client(proxy);
+    let mut request = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00];
+    request.extend_from_slice(dst);
+    client.write_all(&request).unwrap();
+
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    let mut reply = Vec::new();
+    client.read_to_end(&mut reply).unwrap();
+    reply
+}
+
+// 失敗応答の ATYP は Request に合わせる（IPv6 は 0x04 のゼロアドレス、IPv4・DOMAIN は 0x01 のゼロアドレス）
+#[test]
+fn failure_reply_follows_request_atyp() {
+    let proxy = spawn_proxy();
+
+    let mut dst = vec![0x01, 127, 0, 0, 1];
+    dst.extend_from_slice(&closed_port("127.0.0.1:0").to_be_bytes());
+    let reply = socks5_failure_reply(proxy, &dst);
+    assert_eq!(reply, [0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+
+    let mut dst = vec![0x04];
+    dst.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+    dst.extend_from_slice(&closed_port("[::1]:0").to_be_bytes());
+    let reply = socks5_failure_reply(proxy, &dst);
+    let mut expected = vec![0x05, 0x05, 0x00, 0x04];
+    expected.extend_from_slice(&[0u8; 18]);
+    assert_eq!(reply, expected);
+
+    // localhost の IPv4・IPv6 のどちらのアドレスでも待ち受けていないポート
+    let port = closed_port("127.0.0.1:0");
+    let mut dst = vec![0x03, 9];
+    dst.extend_from_slice(b"localhost");
+    dst.extend_from_slice(&port.to_be_bytes());
+    let reply = socks5_failure_reply(proxy, &dst);
+    assert_eq!(reply[..4], [0x05, 0x05, 0x00, 0x01]);
+    assert_eq!(reply[4..], [0, 0, 0, 0, 0, 0]);
+}
@@ -0,0 +1,96 @@
+// 拒否した接続への応じ方（Config::deny_mode）の結合テスト
+// silent では応答を返さずに FIN で、reset では RST で閉じることを確かめる
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use seccamp2025mini_online::{
+    Account, Authorizer, Config, Decision, DenyMode, Quota, QuotaPeriod,
+};
+
+mod common;
+use common::{account, client, connect_request, credentials, spawn_echo, userpass};
+
+// Greeting と、遮断されたポートへの CONNECT を送り、メソッド選択まで読んだ接続を返す
+fn send_blocked_request(deny_mode: DenyMode) -> TcpStream {
+    let dst = spawn_echo();
+    let proxy = common::spawn_proxy(Config {
+        blocked_ports: vec![dst.port()],
+        deny_mode,
+        ..Config::default()
+    });
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x00];
+    input.extend_from_slice(&connect_request(dst));
+    client.write_all(&input).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    client
+}
+
+#[test]
+fn silent_mode_closes_blocked_requests_without_a_reply() {
+    let mut client = send_blocked_request(DenyMode::Silent);
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "unexpected reply {rest:?}");
+}
+
+#[test]
+fn reset_mode_resets_blocked_requests() {
+    let mut client = send_blocked_request(DenyMode::Reset);
+    let err = client.read(&mut [0u8; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[test]
+fn reset_mode_resets_denied_clients() {
+    let proxy = common::spawn_proxy(Config {
+        client_deny: vec!["127.0.0.1".parse().unwrap()],
+        deny_mode: DenyMode::Reset,
+        ..Config::default()
+    });
+    let mut client = client(proxy);
+    let err = client.read(&mut [0u8; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[test]
+fn silent_mode_applies_to_authorizer_denials() {
+    let proxy = common::spawn_proxy(Config {
+        authorizer: Some(Authorizer::new(|_, _| Decision::Deny(0x05))),
+        deny_mode: DenyMode::Silent,
+        ..Config::default()
+    });
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x00];
+    input.extend_from_slice(&connect_request(spawn_echo()));
+    client.write_all(&input).unwrap();
+    let mut rest = Vec::new();
+    client.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, [0x05, 0x00], "unexpected reply after the method selection");
+}
+
+#[test]
+fn reset_mode_applies_to_quota_denials() {
+    let user = Account {
+        quota: Some(Quota { bytes: 0, period: QuotaPeriod::Day }),
+        ..account("secret")
+    };
+    let proxy = common::spawn_proxy(Config {
+        auth: true,
+        credentials: credentials(vec![("deny-quota-user", user)]),
+        deny_mode: DenyMode::Reset,
+        ..Config::default()
+    });
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x02];
+    input.extend_from_slice(&userpass("deny-quota-user", "secret"));
+    input.extend_from_slice(&connect_request(spawn_echo()));
+    client.write_all(&input).unwrap();
+    let mut selection_and_status = [0u8; 4];
+    client.read_exact(&mut selection_and_status).unwrap();
+    assert_eq!(selection_and_status, [0x05, 0x02, 0x01, 0x00]);
+    let err = client.read(&mut [0u8; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
@@ -0,0 +1,68 @@
+// 宛先の国と AS 番号（Config::geoip_db）の結合テスト
+// データベースに含まれる宛先の国と AS 番号が、アクセスログと国ごとのメトリクスに出ることを確かめる
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, socks5_connect, spawn_echo};
+
+#[test]
+fn destination_country_reaches_the_access_log_and_metrics() {
+    let db = env::temp_dir().join(format!("geoip-{}.csv", process::id()));
+    let log = env::temp_dir().join(format!("geoip-{}.log", process::id()));
+    fs::write(&db, "network,country,asn\n10.0.0.0/8,XA,1\n127.0.0.0/8,ZZ,AS64512\n").unwrap();
+    // 空いているポートを調べてから閉じ、メトリクスの待ち受けに使う
+    let metrics = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        geoip_db: Some(db.to_string_lossy().into_owned()),
+        log_file: Some(log.to_string_lossy().into_owned()),
+        metrics_listen: Some(metrics.to_string()),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let mut client = socks5_connect(server.local_addrs()[0], spawn_echo());
+    assert_echo(&mut client);
+    drop(client);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = fs::read_to_string(&log).unwrap_or_default();
+        if text.contains("access conn=") || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let mut scrape = TcpStream::connect(metrics).unwrap();
+    scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut body = String::new();
+    scrape.read_to_string(&mut body).unwrap();
+    server.shutdown().unwrap();
+    let _ = fs::remove_file(&db);
+    let _ = fs::remove_file(&log);
+
+    assert!(text.contains(" country=ZZ asn=64512 sent="), "{text}");
+    assert!(body.contains("socks5_connections_by_country_total{country=\"ZZ\"} 1\n"), "{body}");
+}
+
+#[test]
+fn malformed_database_stops_the_server() {
+    let db = env::temp_dir().join(format!("geoip-bad-{}.csv", process::id()));
+    fs::write(&db, "10.0.0.0/8,JP,1\n10.1.0.0/16,US,2\n").unwrap();
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        geoip_db: Some(db.to_string_lossy().into_owned()),
+        ..Config::default()
+    });
+    let _ = fs::remove_file(&db);
+    let err = server.err().expect("the server started");
+    assert!(err.to_string().contains("overlaps"), "{err}");
+}
@@ -0,0 +1,126 @@
+// GSSAPI 認証（RFC 1961）のサブネゴシエーションの結合テスト（feature "gssapi" のときだけ）
+// GSS-API の代わりに、決まったトークンを受け付けるだけのコンテキストを差し込んで確かめる
+#![cfg(feature = "gssapi")]
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{
+    Config, GssapiAcceptor, GssapiContext, GssapiStep, handle_connection,
+};
+
+// "hello" を受け取ったら "welcome" を返して確立する。封入・復号は何もしない
+#[derive(Debug)]
+struct MockAcceptor;
+
+struct MockContext;
+
+impl GssapiAcceptor for MockAcceptor {
+    fn new_context(&self) -> Box<dyn GssapiContext> {
+        Box::new(MockContext)
+    }
+}
+
+impl GssapiContext for MockContext {
+    fn accept(&mut self, token: &[u8]) -> io::Result<GssapiStep> {
+        match token {
+            b"hello" => Ok(GssapiStep::Complete(b"welcome".to_vec())),
+            _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "bad token")),
+        }
+    }
+
+    fn wrap(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(token.to_vec())
+    }
+
+    fn principal(&self) -> String {
+        "alice@EXAMPLE.COM".to_string()
+    }
+}
+
+fn spawn_proxy() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(Config {
+        gssapi: Some(Arc::new(MockAcceptor)),
+        ..Config::default()
+    });
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = handle_connection(&mut stream, &config);
+    });
+    addr
+}
+
+// [VER=0x01, MTYP, LEN(2), TOKEN]
+fn message(mtyp: u8, token: &[u8]) -> Vec<u8> {
+    let mut m = vec![0x01, mtyp];
+    m.extend_from_slice(&(token.len() as u16).to_be_bytes());
+    m.extend_from_slice(token);
+    m
+}
+
+fn connect(proxy: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client
+}
+
+#[test]
+fn gssapi_is_chosen_and_negotiated_before_the_request() {
+    let mut client = connect(spawn_proxy());
+    client.write_all(&[0x05, 0x02, 0x00, 0x01]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x01]);
+
+    client.write_all(&message(0x01, b"hello")).unwrap();
+    let mut reply = [0u8; 4 + 7];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..], message(0x01, b"welcome")[..]);
+
+    // 保護なし（0x00）を求め、同じ保護レベルが返ってくる
+    client.write_all(&message(0x02, &[0x00])).unwrap();
+    let mut level = [0u8; 5];
+    client.read_exact(&mut level).unwrap();
+    assert_eq!(level[..], message(0x02, &[0x00])[..]);
+
+    // その後の Request は封入せずに送る（ATYP=0x05 には 0x08 が返る）
+    client.write_all(&[0x05, 0x01, 0x00, 0x05]).unwrap();
+    let mut rep = [0u8; 10];
+    client.read_exact(&mut rep).unwrap();
+    assert_eq!(rep[..2], [0x05, 0x08]);
+}
+
+#[test]
+fn unsupported_protection_level_is_aborted() {
+    let mut client = connect(spawn_proxy());
+    client.write_all(&[0x05, 0x01, 0x01]).unwrap();
+    client.write_all(&message(0x01, b"hello")).unwrap();
+    client.write_all(&message(0x02, &[0x02])).unwrap();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).unwrap();
+    let mut expected = vec![0x05, 0x01];
+    expected.extend_from_slice(&message(0x01, b"welcome"));
+    expected.extend_from_slice(&[0x01, 0xFF]);
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn rejected_token_is_aborted() {
+    let mut client = connect(spawn_proxy());
+    client.write_all(&[0x05, 0x01, 0x01]).unwrap();
+    client.write_all(&message(0x01, b"intruder")).unwrap();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).unwrap();
+    assert_eq!(output, [0x05, 0x01, 0x01, 0xFF]);
+}
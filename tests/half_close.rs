@@ -0,0 +1,76 @@
+// 片方向の終了（half-close）の結合テスト
+// 片側が書き込みを閉じた後も、もう片方向の転送は最後まで続き、データが欠けないことを確かめる
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::socks5_connect;
+
+// 最後に送るデータ（転送バッファより十分大きくする）
+const FINAL_CHUNK_LEN: usize = 256 * 1024;
+
+fn final_chunk() -> Vec<u8> {
+    (0..FINAL_CHUNK_LEN).map(|i| (i % 251) as u8).collect()
+}
+
+fn spawn_proxy() -> SocketAddr {
+    common::spawn_proxy(Config::default())
+}
+
+// クライアントが書き込みを閉じた後に、宛先が返す最後のデータを全て受け取れる
+#[test]
+fn final_chunk_from_destination_arrives_after_client_closes_its_write_side() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dst = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        stream.read_to_end(&mut request).unwrap();
+        assert_eq!(request, b"request");
+        // EOF を受け取ってから少し待って送る
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(&final_chunk()).unwrap();
+    });
+
+    let mut client = socks5_connect(spawn_proxy(), dst);
+    client.write_all(b"request").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).unwrap();
+    assert!(response == final_chunk(), "received {} bytes", response.len());
+}
+
+// 宛先が書き込みを閉じた後に、クライアントが送る最後のデータも宛先へ全て届く
+#[test]
+fn final_chunk_from_client_arrives_after_destination_closes_its_write_side() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dst = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"banner").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        tx.send(received).unwrap();
+    });
+
+    let mut client = socks5_connect(spawn_proxy(), dst);
+    let mut banner = Vec::new();
+    client.read_to_end(&mut banner).unwrap();
+    assert_eq!(banner, b"banner");
+    thread::sleep(Duration::from_millis(100));
+    client.write_all(&final_chunk()).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let received = recv(&rx);
+    assert!(received == final_chunk(), "received {} bytes", received.len());
+}
+
+fn recv(rx: &Receiver<Vec<u8>>) -> Vec<u8> {
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+}
@@ -0,0 +1,183 @@
+// 不正・途中で切れたハンドシェイクの結合テスト
+// プロキシが panic せずにエラーで終わり、RFC1928 に沿って応答するか接続を閉じることを確かめる
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, SocksError, handle_connection};
+
+// 1 本の接続で input を送り（close_write なら送信側を閉じて EOF を伝え）、
+// 接続が閉じられるまでに返ってきたバイト列と、handle_connection の結果を返す
+// 処理スレッドが panic した場合はテストを失敗させる
+fn exchange(input: &[u8], close_write: bool) -> (Vec<u8>, Result<(), SocksError>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        handle_connection(&mut stream, &Arc::new(Config::default()))
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(input).unwrap();
+    if close_write {
+        client.shutdown(Shutdown::Write).unwrap();
+    }
+    // 読み残しのあるまま閉じられると RST になるため、読み込みのエラーも「閉じられた」とみなす
+    let mut output = Vec::new();
+    let mut buf = [0u8; 64];
+    while let Ok(n @ 1..) = client.read(&mut buf) {
+        output.extend_from_slice(&buf[..n]);
+    }
+
+    let result = server.join().expect("connection handler panicked");
+    (output, result)
+}
+
+// No Auth の Greeting の後に、Request ヘッダ以降を続けた入力
+fn request(rest: &[u8]) -> Vec<u8> {
+    let mut input = vec![0x05, 0x01, 0x00];
+    input.extend_from_slice(rest);
+    input
+}
+
+#[test]
+fn wrong_version_is_closed_without_reply() {
+    let (output, result) = exchange(&[0x06, 0x01, 0x00], false);
+    assert!(output.is_empty());
+    assert!(matches!(result, Err(SocksError::UnsupportedVersion(_))), "{result:?}");
+}
+
+#[test]
+fn zero_methods_are_rejected_with_no_acceptable_method() {
+    let (output, result) = exchange(&[0x05, 0x00], false);
+    assert_eq!(output, [0x05, 0xFF]);
+    assert!(matches!(result, Err(SocksError::NoAcceptableMethods)), "{result:?}");
+}
+
+// NMETHODS=0 の直後に Request を続けて送っても、待たずに 0xFF だけを返して閉じる
+#[test]
+fn zero_methods_with_pipelined_request_are_rejected_immediately() {
+    let mut input = vec![0x05, 0x00];
+    input.extend_from_slice(&[0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+    let started = Instant::now();
+    let (output, result) = exchange(&input, false);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(output, [0x05, 0xFF]);
+    assert_eq!(result.unwrap_err().to_string(), "no acceptable method");
+}
+
+// GSSAPI (0x01) しか提示されなければ、選べるメソッドが無いので 0xFF を返して閉じる
+#[test]
+fn gssapi_only_greeting_is_rejected_with_no_acceptable_method() {
+    let (output, result) = exchange(&[0x05, 0x01, 0x01], false);
+    assert_eq!(output, [0x05, 0xFF]);
+    assert_eq!(result.unwrap_err().to_string(), "no acceptable method");
+}
+
+#[test]
+fn truncated_method_list_is_closed_without_reply() {
+    let (output, result) = exchange(&[0x05, 0x03, 0x00], true);
+    assert!(output.is_empty());
+    assert!(
+        matches!(&result, Err(SocksError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof),
+        "{result:?}"
+    );
+}
+
+#[test]
+fn nonzero_rsv_is_closed_after_method_selection() {
+    let input = request(&[0x05, 0x01, 0x01, 0x01, 127, 0, 0, 1, 0, 80]);
+    let (output, result) = exchange(&input, false);
+    assert_eq!(output, [0x05, 0x00]);
+    assert!(matches!(result, Err(SocksError::MalformedRequest(_))), "{result:?}");
+}
+
+#[test]
+fn unsupported_atyp_is_answered_with_address_type_not_supported() {
+    let input = request(&[0x05, 0x01, 0x00, 0x05, 127, 0, 0, 1, 0, 80]);
+    let (output, result) = exchange(&input, false);
+    assert_eq!(output[..2], [0x05, 0x00]);
+    assert_eq!(output[2..], [0x05, 0x08, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    assert!(matches!(result, Err(SocksError::UnsupportedAtyp(0x05))), "{result:?}");
+}
+
+#[test]
+fn domain_longer_than_remaining_bytes_is_closed_without_reply() {
+    let input = request(&[0x05, 0x01, 0x00, 0x03, 50, b'a', b'b', b'c']);
+    let (output, result) = exchange(&input, true);
+    assert_eq!(output, [0x05, 0x00]);
+    assert!(
+        matches!(&result, Err(SocksError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof),
+        "{result:?}"
+    );
+}
+
+// NMETHODS が上限（既定 32）を超えていれば、METHODS を待たずに 0xFF を返して閉じる
+#[test]
+fn too_many_methods_are_rejected_without_reading_them() {
+    let (output, result) = exchange(&[0x05, 0xFF, 0x00], false);
+    assert_eq!(output, [0x05, 0xFF]);
+    assert!(matches!(result, Err(SocksError::MalformedRequest(_))), "{result:?}");
+}
+
+#[test]
+fn zero_length_domain_is_answered_with_host_unreachable() {
+    let input = request(&[0x05, 0x01, 0x00, 0x03, 0, 0, 80]);
+    let (output, result) = exchange(&input, false);
+    assert_eq!(output[..2], [0x05, 0x00]);
+    assert_eq!(output[2..], [0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    assert!(matches!(result, Err(SocksError::MalformedRequest(_))), "{result:?}");
+}
+
+// 上限（既定 253）を超える LEN は、名前のバイトが届くのを待たずに拒否する
+#[test]
+fn oversized_domain_is_rejected_before_reading_the_name() {
+    let input = request(&[0x05, 0x01, 0x00, 0x03, 254]);
+    let (output, result) = exchange(&input, false);
+    assert_eq!(output[..2], [0x05, 0x00]);
+    assert_eq!(output[2..4], [0x05, 0x04]);
+    assert!(matches!(result, Err(SocksError::MalformedRequest(_))), "{result:?}");
+}
+
+// 解析エラーには、失敗した段階（greeting / request header / request）とバイト位置が入る
+#[test]
+fn parse_errors_report_the_phase_and_byte_offset() {
+    let cases: [(Vec<u8>, &str); 5] = [
+        (vec![0x06, 0x01, 0x00], "malformed at greeting byte 0: VER=0x06"),
+        (
+            vec![0x05, 0xFF, 0x00],
+            "malformed at greeting byte 1: NMETHODS=255 exceeds the limit of 32",
+        ),
+        (
+            request(&[0x05, 0x01, 0x01, 0x01, 127, 0, 0, 1, 0, 80]),
+            "malformed at request header byte 2: RSV=0x01",
+        ),
+        (
+            request(&[0x05, 0x01, 0x00, 0x05, 127, 0, 0, 1, 0, 80]),
+            "malformed at request header byte 3: ATYP=0x05",
+        ),
+        (
+            request(&[0x05, 0x01, 0x00, 0x03, 0, 0, 80]),
+            "malformed at request byte 4: domain name LEN=0",
+        ),
+    ];
+    for (input, expected) in cases {
+        let (_, result) = exchange(&input, false);
+        assert_eq!(result.unwrap_err().to_string(), expected);
+    }
+}
+
+// メソッド選択の後の Request の VER が 0x05 でなければ、General failure (0x01) を返してから閉じる
+#[test]
+fn request_with_socks4_version_is_answered_with_general_failure() {
+    let input = request(&[0x04, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+    let (output, result) = exchange(&input, false);
+    assert_eq!(output[..2], [0x05, 0x00]);
+    assert_eq!(output[2..], [0x05, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    let err = result.unwrap_err();
+    assert!(matches!(err, SocksError::UnsupportedVersion(_)), "{err:?}");
+    assert_eq!(err.to_string(), "malformed at request header byte 0: VER=0x04");
+}
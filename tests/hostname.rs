@@ -0,0 +1,157 @@
+// ドメイン名の宛先の結合テスト（国際化ドメイン名の変換と、不正なホスト名の拒否）
+// 変換後のホスト名は、名前解決をしない上位プロキシ（--upstream）の側で受け取って確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Config, handle_connection};
+
+// CONNECT の宛先のホスト名を記録して Connection refused (0x05) を返す、上位プロキシの代わり
+fn spawn_upstream() -> (SocketAddr, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            // [VER, CMD, RSV, ATYP=0x03, LEN, NAME, PORT]
+            let mut hdr = [0u8; 5];
+            stream.read_exact(&mut hdr).unwrap();
+            let mut name = vec![0u8; hdr[4] as usize + 2];
+            stream.read_exact(&mut name).unwrap();
+            name.truncate(hdr[4] as usize);
+            let _ = tx.send(String::from_utf8(name).unwrap());
+            stream.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+        }
+    });
+    (addr, rx)
+}
+
+// config のプロキシで 1 本だけ接続を処理し、そのアドレスを返す
+fn spawn_proxy(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(config);
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = handle_connection(&mut stream, &config);
+    });
+    addr
+}
+
+// No Auth でドメイン名（ATYP=0x03）の CONNECT を送り、応答の REP を返す
+fn connect_domain(proxy: SocketAddr, host: &[u8]) -> u8 {
+    let mut client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut request = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    request.extend_from_slice(host);
+    request.extend_from_slice(&80u16.to_be_bytes());
+    client.write_all(&request).unwrap();
+
+    let mut reply = [0u8; 2 + 10];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    reply[3]
+}
+
+#[test]
+fn unicode_hostname_is_sent_as_punycode() {
+    let (upstream, names) = spawn_upstream();
+    let proxy = spawn_proxy(Config {
+        upstream: Some(upstream.to_string()),
+        ..Config::default()
+    });
+
+    let rep = connect_domain(proxy, "Bücher.例え.テスト".as_bytes());
+    assert_eq!(rep, 0x05);
+    let name = names.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(name, "xn--bcher-kva.xn--r8jz45g.xn--zckzah");
+}
+
+#[test]
+fn ascii_hostname_is_unchanged() {
+    let (upstream, names) = spawn_upstream();
+    let proxy = spawn_proxy(Config {
+        upstream: Some(upstream.to_string()),
+        ..Config::default()
+    });
+
+    assert_eq!(connect_domain(proxy, b"Example.COM"), 0x05);
+    assert_eq!(names.recv_timeout(Duration::from_secs(5)).unwrap(), "Example.COM");
+}
+
+#[test]
+fn empty_hostname_is_rejected_with_host_unreachable() {
+    let proxy = spawn_proxy(Config::default());
+    assert_eq!(connect_domain(proxy, b""), 0x04);
+}
+
+#[test]
+fn hostname_with_nul_is_rejected_with_host_unreachable() {
+    let proxy = spawn_proxy(Config::default());
+    assert_eq!(connect_domain(proxy, b"example.com\0.evil"), 0x04);
+}
+
+#[test]
+fn invalid_utf8_hostname_is_rejected_with_host_unreachable() {
+    let proxy = spawn_proxy(Config::default());
+    assert_eq!(connect_domain(proxy, b"ex\xffample.com"), 0x04);
+}
+
+// validate_hostnames のとき、DNS 名として不正な形のホスト名は名前解決の前に REP=0x04 で拒否する
+#[test]
+fn invalid_dns_names_are_rejected_when_validating() {
+    let long_label = format!("{}.com", "a".repeat(64));
+    let invalid: [&[u8]; 7] = [
+        b"exa_mple.com",
+        b"exa mple.com",
+        b"-example.com",
+        b"example-.com",
+        b"example..com",
+        b".",
+        long_label.as_bytes(),
+    ];
+    for host in invalid {
+        let (upstream, names) = spawn_upstream();
+        let proxy = spawn_proxy(Config {
+            upstream: Some(upstream.to_string()),
+            validate_hostnames: true,
+            ..Config::default()
+        });
+        assert_eq!(connect_domain(proxy, host), 0x04, "{:?}", String::from_utf8_lossy(host));
+        assert!(names.try_recv().is_err());
+    }
+}
+
+#[test]
+fn valid_dns_names_pass_validation() {
+    let longest_label = format!("{}.example.com.", "a".repeat(63));
+    for host in ["example.com", "xn--bcher-kva.example", "a-b.c0", longest_label.as_str()] {
+        let (upstream, names) = spawn_upstream();
+        let proxy = spawn_proxy(Config {
+            upstream: Some(upstream.to_string()),
+            validate_hostnames: true,
+            ..Config::default()
+        });
+        assert_eq!(connect_domain(proxy, host.as_bytes()), 0x05, "{host}");
+        assert_eq!(names.recv_timeout(Duration::from_secs(5)).unwrap(), host);
+    }
+}
+
+// 既定（validate_hostnames なし）では、不正な形のホスト名もそのまま上位プロキシへ渡す
+#[test]
+fn invalid_dns_names_are_forwarded_without_validation() {
+    let (upstream, names) = spawn_upstream();
+    let proxy = spawn_proxy(Config {
+        upstream: Some(upstream.to_string()),
+        ..Config::default()
+    });
+    assert_eq!(connect_domain(proxy, b"exa_mple.com"), 0x05);
+    assert_eq!(names.recv_timeout(Duration::from_secs(5)).unwrap(), "exa_mple.com");
+}
@@ -0,0 +1,74 @@
+// ユーザごとの利用できる時間帯の結合テスト
+// 時間帯の外のユーザは、パスワードが正しくても認証の失敗（STATUS=0x01）で拒否されることを確かめる
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Config, TimeWindow, handle_connection};
+
+// 毎日の終日（どの時刻でも入る）
+const ALWAYS: TimeWindow = TimeWindow { days: [true; 7], start: 0, end: 24 * 60 };
+// どの曜日にも当てはまらない
+const NEVER: TimeWindow = TimeWindow { days: [false; 7], start: 0, end: 24 * 60 };
+
+fn spawn_proxy(hours: Vec<TimeWindow>) -> SocketAddr {
+    let account = Account { password: "pw".to_string(), hours, ..Account::default() };
+    let config = Arc::new(Config {
+        auth: true,
+        credentials: Some(Arc::new(RwLock::new(HashMap::from([(
+            "alice".to_string(),
+            account,
+        )])))),
+        ..Config::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = handle_connection(&mut stream, &config);
+    });
+    addr
+}
+
+// alice として認証し、RFC1929 の応答の STATUS を返す
+fn auth_status(proxy: SocketAddr) -> u8 {
+    let mut client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client
+        .write_all(&[0x05, 0x01, 0x02, 0x01, 5, b'a', b'l', b'i', b'c', b'e', 2, b'p', b'w'])
+        .unwrap();
+    let mut reply = [0u8; 4];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..3], [0x05, 0x02, 0x01]);
+    reply[3]
+}
+
+#[test]
+fn user_inside_the_window_is_accepted() {
+    assert_eq!(auth_status(spawn_proxy(vec![NEVER, ALWAYS])), 0x00);
+}
+
+#[test]
+fn user_outside_the_window_is_rejected() {
+    assert_eq!(auth_status(spawn_proxy(vec![NEVER])), 0x01);
+}
+
+#[test]
+fn user_without_windows_is_always_accepted() {
+    assert_eq!(auth_status(spawn_proxy(Vec::new())), 0x00);
+}
+
+// 日付をまたぐ範囲（金曜日の 22:00 から翌朝 6:00 まで）
+#[test]
+fn overnight_window_continues_into_the_next_day() {
+    let mut days = [false; 7];
+    days[4] = true;
+    let window = TimeWindow { days, start: 22 * 60, end: 6 * 60 };
+    assert!(window.contains(4, 23 * 60));
+    assert!(window.contains(5, 5 * 60));
+    assert!(!window.contains(5, 7 * 60));
+    assert!(!window.contains(4, 5 * 60));
+}
@@ -0,0 +1,122 @@
+// HTTP CONNECT のトンネルの結合テスト
+// http_connect を有効にしたプロキシで、HTTP CONNECT と SOCKS5 の両方を同じポートで受け付けることを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Config, handle_connection};
+
+// 受け取ったデータをそのまま返す宛先を立て、そのアドレスを返す
+fn spawn_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            thread::spawn(move || {
+                let mut buf = [0u8; 1024];
+                while let Ok(n @ 1..) = stream.read(&mut buf) {
+                    if stream.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+fn spawn_proxy() -> SocketAddr {
+    spawn_proxy_with(Config::default())
+}
+
+// config に http_connect を加えたプロキシを立てる
+fn spawn_proxy_with(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Arc::new(Config { http_connect: true, ..config });
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let config = Arc::clone(&config);
+            thread::spawn(move || {
+                let _ = handle_connection(&mut stream, &config);
+            });
+        }
+    });
+    addr
+}
+
+fn connect(proxy: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client
+}
+
+// 応答のヘッダ（空行まで）を読む
+fn read_response_header(client: &mut TcpStream) -> String {
+    let mut header = Vec::new();
+    let mut b = [0u8; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut b).unwrap();
+        header.push(b[0]);
+    }
+    String::from_utf8(header).unwrap()
+}
+
+// ヘッダの直後に続けて送ったデータも、トンネルの先へ届く
+#[test]
+fn http_connect_tunnels_to_the_destination() {
+    let echo = spawn_echo();
+    let mut client = connect(spawn_proxy());
+    let request = format!("CONNECT {echo} HTTP/1.1\r\nHost: {echo}\r\n\r\nping");
+    client.write_all(request.as_bytes()).unwrap();
+
+    let header = read_response_header(&mut client);
+    assert!(header.starts_with("HTTP/1.1 200 "), "{header}");
+    let mut echoed = [0u8; 4];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"ping");
+}
+
+#[test]
+fn other_http_methods_are_refused() {
+    let mut client = connect(spawn_proxy());
+    client.write_all(b"GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+    let header = read_response_header(&mut client);
+    assert!(header.starts_with("HTTP/1.1 405 "), "{header}");
+}
+
+#[test]
+fn socks5_still_works_on_the_same_port() {
+    let echo = spawn_echo();
+    let mut client = connect(spawn_proxy());
+    let mut request = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&echo.port().to_be_bytes());
+    client.write_all(&request).unwrap();
+    let mut reply = [0u8; 2 + 10];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..4], [0x05, 0x00, 0x05, 0x00]);
+}
+
+// HTTP CONNECT には認証が無いため、SOCKS5 で No Auth を選べない設定では 403 で拒否する
+#[test]
+fn http_connect_is_refused_when_no_auth_is_not_selectable() {
+    let echo = spawn_echo();
+    let accounts = HashMap::from([("alice".to_string(), Account::default())]);
+    let credentials = Some(Arc::new(RwLock::new(accounts)));
+    let configs = [
+        Config { require_auth: true, credentials: credentials.clone(), ..Config::default() },
+        Config { methods: vec![0x02], credentials, ..Config::default() },
+    ];
+    for config in configs {
+        let mut client = connect(spawn_proxy_with(Config { auth: true, ..config }));
+        let request = format!("CONNECT {echo} HTTP/1.1\r\nHost: {echo}\r\n\r\n");
+        client.write_all(request.as_bytes()).unwrap();
+        let header = read_response_header(&mut client);
+        assert!(header.starts_with("HTTP/1.1 403 "), "{header}");
+    }
+}
@@ -0,0 +1,75 @@
+// トンネルの寿命（Config::max_lifetime / lifetime_window）の結合テスト
+// 固定方式では転送中でも閉じ、スライディング方式では転送している間は上限まで延び、止まると閉じることを確かめる
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{socks5_connect, spawn_echo};
+
+const WINDOW: Duration = Duration::from_millis(500);
+
+fn connect(max_lifetime: Duration, lifetime_window: Option<Duration>) -> TcpStream {
+    let proxy = common::spawn_proxy(Config {
+        max_lifetime: Some(max_lifetime),
+        lifetime_window,
+        ..Config::default()
+    });
+    let client = socks5_connect(proxy, spawn_echo());
+    client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    client
+}
+
+// until の間、WINDOW より短い間隔で送って折り返しを読む。閉じられたらその時点を返す
+fn keep_busy(client: &mut TcpStream, until: Duration) -> Option<Duration> {
+    let started = Instant::now();
+    let mut buf = [0u8; 4];
+    while started.elapsed() < until {
+        if client.write_all(b"tick").is_err() || client.read_exact(&mut buf).is_err() {
+            return Some(started.elapsed());
+        }
+        thread::sleep(WINDOW / 5);
+    }
+    None
+}
+
+#[test]
+fn fixed_lifetime_closes_an_active_tunnel() {
+    let limit = Duration::from_millis(800);
+    let mut client = connect(limit, None);
+    let closed = keep_busy(&mut client, limit * 4).expect("tunnel outlived its lifetime");
+    assert!(closed >= limit / 2 && closed < limit * 3, "closed after {closed:?}");
+}
+
+#[test]
+fn sliding_lifetime_keeps_an_active_tunnel_and_reaps_an_idle_one() {
+    let ceiling = Duration::from_secs(3);
+    let mut client = connect(ceiling, Some(WINDOW));
+    // 期限の幅の何倍も転送し続けても閉じない
+    assert_eq!(keep_busy(&mut client, WINDOW * 3), None);
+
+    // 止まると、幅が過ぎたところで閉じる
+    let stopped = Instant::now();
+    assert_eq!(client.read(&mut [0u8; 16]).unwrap(), 0);
+    let waited = stopped.elapsed();
+    assert!(waited >= WINDOW / 2 && waited < WINDOW * 3, "closed after {waited:?}");
+}
+
+#[test]
+fn sliding_lifetime_stops_at_the_ceiling() {
+    let ceiling = Duration::from_millis(1500);
+    let mut client = connect(ceiling, Some(WINDOW));
+    let closed = keep_busy(&mut client, ceiling * 3).expect("tunnel outlived its ceiling");
+    assert!(closed >= ceiling / 2 && closed < ceiling * 2, "closed after {closed:?}");
+}
+
+#[test]
+fn sliding_lifetime_needs_a_ceiling() {
+    let args = ["--lifetime-window", "30"].map(String::from);
+    assert!(Config::default().apply_args(args).is_err());
+    let args = ["--lifetime-window", "30", "--max-lifetime", "3600"].map(String::from);
+    assert!(Config::default().apply_args(args).is_ok());
+}
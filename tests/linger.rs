@@ -0,0 +1,52 @@
+// SO_LINGER の結合テスト
+// linger=0 でも、トンネルが開いている間の half-close はそのまま働くことを確かめる
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::socks5_connect;
+
+fn spawn_proxy() -> SocketAddr {
+    common::spawn_proxy(Config { linger: Some(Duration::ZERO), ..Config::default() })
+}
+
+#[test]
+fn zero_linger_still_half_closes_when_the_destination_closes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dst = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"bye").unwrap();
+    });
+
+    let mut client = socks5_connect(spawn_proxy(), dst);
+    let mut received = Vec::new();
+    // 宛先が閉じた後は RST ではなく FIN（EOF）が届く
+    client.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"bye");
+}
+
+#[test]
+fn zero_linger_delivers_the_response_after_the_client_half_closes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dst = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // 要求を最後まで（クライアントの FIN まで）読んでから応答する
+        let mut request = Vec::new();
+        stream.read_to_end(&mut request).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        stream.write_all(&[b"response to ".as_slice(), &request].concat()).unwrap();
+    });
+
+    let mut client = socks5_connect(spawn_proxy(), dst);
+    client.write_all(b"ping").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"response to ping");
+}
@@ -0,0 +1,48 @@
+// 同時接続数の上限（Config::max_connections）の結合テスト
+// 上限に達している間の接続は 0xFF で閉じられ、処理中の接続が終われば再び受け付けることを確かめる
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, client, socks5_connect, spawn_echo};
+
+// Greeting を送り、メソッド選択の応答を返す（拒否されて先に閉じられた場合は None）
+fn method_selection(proxy: SocketAddr) -> Option<[u8; 2]> {
+    let mut client = client(proxy);
+    client.write_all(&[0x05, 0x01, 0x00]).ok()?;
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).ok()?;
+    Some(selection)
+}
+
+#[test]
+fn connections_over_the_limit_are_refused_until_one_ends() {
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        max_connections: 1,
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+
+    let mut tunnel = socks5_connect(proxy, spawn_echo());
+    assert_echo(&mut tunnel);
+    // 上限に達している間は、Greeting を読まずにメソッド選択の拒否を返して閉じる
+    let mut refused = client(proxy);
+    let mut output = Vec::new();
+    refused.read_to_end(&mut output).unwrap();
+    assert_eq!(output, [0x05, 0xFF]);
+
+    // トンネルが閉じられて処理中の接続が無くなれば、新しい接続を受け付ける
+    drop(tunnel);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while method_selection(proxy) != Some([0x05, 0x00]) {
+        assert!(Instant::now() < deadline, "connection was not accepted again");
+        thread::sleep(Duration::from_millis(50));
+    }
+    server.shutdown().unwrap();
+}
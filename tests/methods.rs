@@ -0,0 +1,55 @@
+// 認証方法の選択の結合テスト
+// 設定した優先順で、クライアントが提示した中から最初に一致する方法を選ぶことを確かめる
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Config, handle_connection};
+
+// offered を提示した Greeting を送り、選ばれた方法を返す
+fn select(config: Config, offered: &[u8]) -> u8 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = handle_connection(&mut stream, &Arc::new(config));
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut greeting = vec![0x05, offered.len() as u8];
+    greeting.extend_from_slice(offered);
+    client.write_all(&greeting).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection[0], 0x05);
+    selection[1]
+}
+
+#[test]
+fn default_order_prefers_username_password() {
+    let config = Config { auth: true, ..Config::default() };
+    assert_eq!(select(config, &[0x00, 0x02]), 0x02);
+}
+
+#[test]
+fn configured_order_can_prefer_no_auth() {
+    let config = Config { auth: true, methods: vec![0x00, 0x02], ..Config::default() };
+    assert_eq!(select(config, &[0x02, 0x00]), 0x00);
+}
+
+// 一覧に無い方法は、提示されても選ばない
+#[test]
+fn methods_outside_the_list_are_not_selected() {
+    let config = Config { auth: true, methods: vec![0x02], ..Config::default() };
+    assert_eq!(select(config, &[0x00]), 0xFF);
+}
+
+// 一覧にあっても、使えない方法（認証なしのときのユーザ/パスワード）は飛ばす
+#[test]
+fn unusable_methods_are_skipped() {
+    let config = Config { methods: vec![0x02, 0x00], ..Config::default() };
+    assert_eq!(select(config, &[0x02, 0x00]), 0x00);
+}
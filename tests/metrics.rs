@@ -0,0 +1,71 @@
+// メトリクスのエンドポイント（Config::metrics_listen）の結合テスト
+// 開いたままのトンネルの転送量が見えること・遅いクライアントがいても他の収集に応答することを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, socks5_connect, spawn_echo};
+
+// 空いているポートを探す（エンドポイントのアドレスは設定で渡す必要があるため）
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+// GET /metrics の応答から name の値を取り出す
+fn scrape(endpoint: SocketAddr, name: &str) -> u64 {
+    let mut stream = TcpStream::connect(endpoint).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+        .unwrap_or_else(|| panic!("{name} missing in {response}"))
+}
+
+// name の値が at_least 以上になるまで待つ（転送したバイトは書き込みの後に数えるため、
+// エコーが届いた時点ではまだ加算されていないことがある）
+fn wait_for(endpoint: SocketAddr, name: &str, at_least: u64) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let value = scrape(endpoint, name);
+        if value >= at_least {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{name} stayed at {value}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn bytes_are_counted_while_the_tunnel_is_open() {
+    let endpoint = free_addr();
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        metrics_listen: Some(endpoint.to_string()),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let mut client = socks5_connect(server.local_addrs()[0], spawn_echo());
+    assert_echo(&mut client);
+    // トンネルはまだ開いている
+    assert_eq!(scrape(endpoint, "socks5_connections_active"), 1);
+    wait_for(endpoint, "socks5_bytes_sent_total", 23);
+    wait_for(endpoint, "socks5_bytes_received_total", 23);
+
+    // 何も送らない遅いクライアントがいても、次の収集には応答する
+    let _slow = TcpStream::connect(endpoint).unwrap();
+    let started = Instant::now();
+    assert_eq!(scrape(endpoint, "socks5_connections_total"), 1);
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+
+    drop(client);
+    server.shutdown().unwrap();
+}
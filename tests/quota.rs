@@ -0,0 +1,91 @@
+// ユーザごとの転送量の上限の結合テスト
+// 上限に達するまでの接続は通り、達した後の新しい接続は REP=0x02 で拒否されることを確かめる
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Config, Quota, QuotaPeriod, handle_connection};
+
+// 接続ごとに固定の 100 バイトを送って閉じる宛先を立て、そのアドレスを返す
+fn spawn_target() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let _ = stream.write_all(&[0x42; 100]);
+        }
+    });
+    addr
+}
+
+fn spawn_proxy(accounts: HashMap<String, Account>) -> SocketAddr {
+    let config = Arc::new(Config {
+        auth: true,
+        credentials: Some(Arc::new(RwLock::new(accounts))),
+        ..Config::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { break };
+            let config = Arc::clone(&config);
+            thread::spawn(move || {
+                let _ = handle_connection(&mut stream, &config);
+            });
+        }
+    });
+    addr
+}
+
+// user で認証して target への CONNECT を送り、応答の REP を返す（成功なら宛先のデータを読み切る）
+fn fetch_as(proxy: SocketAddr, user: &str, target: SocketAddr) -> u8 {
+    let mut client = TcpStream::connect(proxy).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut input = vec![0x05, 0x01, 0x02, 0x01, user.len() as u8];
+    input.extend_from_slice(user.as_bytes());
+    input.extend_from_slice(&[2, b'p', b'w', 0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1]);
+    input.extend_from_slice(&target.port().to_be_bytes());
+    client.write_all(&input).unwrap();
+
+    let mut reply = [0u8; 2 + 2 + 10];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[..4], [0x05, 0x02, 0x01, 0x00]);
+    let mut data = Vec::new();
+    client.read_to_end(&mut data).unwrap();
+    if reply[5] == 0x00 {
+        assert_eq!(data.len(), 100);
+    }
+    reply[5]
+}
+
+#[test]
+fn connections_are_refused_once_the_quota_is_used_up() {
+    let target = spawn_target();
+    let quota = Quota { bytes: 150, period: QuotaPeriod::Day };
+    let proxy = spawn_proxy(HashMap::from([(
+        "quota-user".to_string(),
+        Account { password: "pw".to_string(), quota: Some(quota), ..Account::default() },
+    )]));
+
+    assert_eq!(fetch_as(proxy, "quota-user", target), 0x00);
+    assert_eq!(fetch_as(proxy, "quota-user", target), 0x00);
+    assert_eq!(fetch_as(proxy, "quota-user", target), 0x02);
+}
+
+#[test]
+fn users_without_quota_are_not_limited() {
+    let target = spawn_target();
+    let proxy = spawn_proxy(HashMap::from([(
+        "unlimited-user".to_string(),
+        Account { password: "pw".to_string(), ..Account::default() },
+    )]));
+
+    for _ in 0..3 {
+        assert_eq!(fetch_as(proxy, "unlimited-user", target), 0x00);
+    }
+}
@@ -0,0 +1,42 @@
+// 認証の必須化（Config::require_auth）の結合テスト
+// No Auth だけを提示したクライアントは 0xFF で断られ、ユーザ/パスワード認証なら接続できることを確かめる
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{account, assert_echo, client, connect_request, credentials, read_reply, userpass};
+
+fn spawn_proxy() -> SocketAddr {
+    common::spawn_proxy(Config {
+        auth: true,
+        require_auth: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        ..Config::default()
+    })
+}
+
+#[test]
+fn no_auth_is_refused() {
+    let mut client = client(spawn_proxy());
+    client.write_all(&[0x05, 0x01, 0x00]).unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).unwrap();
+    assert_eq!(output, [0x05, 0xFF]);
+}
+
+#[test]
+fn userpass_is_accepted_even_when_no_auth_is_preferred() {
+    let dst = common::spawn_echo();
+    let mut client = client(spawn_proxy());
+    let mut input = vec![0x05, 0x02, 0x00, 0x02];
+    input.extend_from_slice(&userpass("alice", "secret"));
+    input.extend_from_slice(&connect_request(dst));
+    client.write_all(&input).unwrap();
+    let mut selection_and_status = [0u8; 4];
+    client.read_exact(&mut selection_and_status).unwrap();
+    assert_eq!(selection_and_status, [0x05, 0x02, 0x01, 0x00]);
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x00]);
+    assert_echo(&mut client);
+}
@@ -0,0 +1,35 @@
+// 待ち受けソケットの SO_REUSEPORT（Config::reuse_port）の結合テスト
+// reuse_port のインスタンス同士は同じポートで待ち受けられ、どちらも接続を処理することを確かめる
+#![cfg(target_os = "linux")]
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, socks5_connect, spawn_echo};
+
+fn config(listen: String, reuse_port: bool) -> Config {
+    Config { listen: vec![listen], reuse_port, ..Config::default() }
+}
+
+#[test]
+fn instances_share_the_port_with_reuse_port() {
+    let first = spawn_server(config("127.0.0.1:0".to_string(), true)).unwrap();
+    let addr = first.local_addrs()[0];
+    let second = spawn_server(config(addr.to_string(), true)).unwrap();
+    assert_eq!(second.local_addrs(), [addr]);
+
+    // 片方を止めても、残ったインスタンスが同じポートで接続を受け付ける
+    first.shutdown().unwrap();
+    let mut client = socks5_connect(addr, spawn_echo());
+    assert_echo(&mut client);
+    drop(client);
+    second.shutdown().unwrap();
+}
+
+#[test]
+fn port_is_not_shared_without_reuse_port() {
+    let first = spawn_server(config("127.0.0.1:0".to_string(), true)).unwrap();
+    let addr = first.local_addrs()[0];
+    assert!(spawn_server(config(addr.to_string(), false)).is_err());
+    first.shutdown().unwrap();
+}
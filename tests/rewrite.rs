@@ -0,0 +1,82 @@
+// 宛先の書き換え規則（Config::rewrites）の結合テスト
+// 一致した宛先だけが置き換え先に接続され、ポート付きの規則は他のポートに一致しないことを確かめる
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+
+use seccamp2025mini_online::{Authorizer, Config, Decision, Destination, Rewrite};
+
+mod common;
+use common::{domain_request, socks5_request, spawn_echo};
+
+fn spawn_proxy(rewrites: Vec<Rewrite>) -> SocketAddr {
+    common::spawn_proxy(Config { rewrites, ..Config::default() })
+}
+
+#[test]
+fn matching_destination_is_redirected() {
+    let echo = spawn_echo();
+    let rule: Rewrite = format!("EXAMPLE.com:80={echo}").parse().unwrap();
+    assert_eq!(rule.to_string(), format!("EXAMPLE.com:80={echo}"));
+    let request = domain_request(b"example.com", 80);
+    let (mut client, reply) = socks5_request(spawn_proxy(vec![rule]), &request);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    client.write_all(b"redirected").unwrap();
+    let mut buf = [0u8; 10];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"redirected");
+}
+
+#[test]
+fn port_specific_rule_does_not_match_other_ports() {
+    let echo = spawn_echo();
+    let rule = format!("nowhere.invalid:80={echo}").parse().unwrap();
+    // 81 番には一致しないため、書き換えずに名前解決して失敗する
+    let request = domain_request(b"nowhere.invalid", 81);
+    let (_, reply) = socks5_request(spawn_proxy(vec![rule]), &request);
+    assert_eq!(reply[0], 0x05);
+    assert_ne!(reply[1], 0x00);
+}
+
+#[test]
+fn malformed_rules_are_rejected() {
+    for bad in ["example.com", "=example.net", "example.com:0=example.net", "[::1=x"] {
+        assert!(bad.parse::<Rewrite>().is_err(), "{bad}");
+    }
+}
+
+#[test]
+fn rewritten_hostnames_are_normalized_before_connecting() {
+    // 書き換え後の宛先は、authorizer に渡る時点で ASCII 形式になっている
+    let (tx, rx) = mpsc::channel();
+    let proxy = common::spawn_proxy(Config {
+        rewrites: vec!["example.com=Bücher.invalid:8080".parse().unwrap()],
+        authorizer: Some(Authorizer::new(move |_, dst| {
+            let _ = tx.send(dst.clone());
+            Decision::Deny(0x02)
+        })),
+        ..Config::default()
+    });
+    let (_, reply) = socks5_request(proxy, &domain_request(b"example.com", 80));
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    let dst = rx.recv().unwrap();
+    assert_eq!(dst, Destination::Domain("xn--bcher-kva.invalid".to_string(), 8080));
+}
+
+#[test]
+fn rewritten_hostnames_are_validated() {
+    // 不正な名前への書き換えは、authorizer や名前解決に進む前に REP=0x04 で拒否される
+    let (tx, rx) = mpsc::channel();
+    let proxy = common::spawn_proxy(Config {
+        rewrites: vec!["example.com=bad_name.invalid".parse().unwrap()],
+        validate_hostnames: true,
+        authorizer: Some(Authorizer::new(move |_, dst| {
+            let _ = tx.send(dst.clone());
+            Decision::Allow
+        })),
+        ..Config::default()
+    });
+    let (_, reply) = socks5_request(proxy, &domain_request(b"example.com", 80));
+    assert_eq!(reply[..2], [0x05, 0x04]);
+    assert!(rx.try_recv().is_err(), "the authorizer saw the invalid name");
+}
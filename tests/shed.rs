@@ -0,0 +1,89 @@
+// ワーカーの待ち行列が満杯のときの押し出し（Config::shed_policy）の結合テスト
+// ワーカー 1 本・待ち行列 1 件を埋め、newest では新しい接続が、oldest では待っていた接続が閉じられ、
+// どちらもメトリクスに数えられることを確かめる
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Config, ServerHandle, ShedPolicy, spawn_server};
+
+mod common;
+use common::client;
+
+// メトリクスの socks5_connections_shed_total の値
+fn shed_total(metrics: SocketAddr) -> u64 {
+    let mut scrape = TcpStream::connect(metrics).unwrap();
+    scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut body = String::new();
+    scrape.read_to_string(&mut body).unwrap();
+    body.lines()
+        .find_map(|l| l.strip_prefix("socks5_connections_shed_total "))
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| panic!("no shed counter in {body}"))
+}
+
+// 何も返さずに閉じられていれば true（読み込みの時間切れは閉じられていない）
+fn is_closed(client: &mut TcpStream) -> bool {
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    match client.read(&mut [0u8; 2]) {
+        Ok(n) => n == 0,
+        Err(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+    }
+}
+
+// メソッド選択まで進めば、ワーカーに処理された
+fn is_served(client: &mut TcpStream) -> bool {
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut selection = [0u8; 2];
+    client.write_all(&[0x05, 0x01, 0x00]).is_ok()
+        && client.read_exact(&mut selection).is_ok()
+        && selection == [0x05, 0x00]
+}
+
+// ワーカーを 1 本目の接続で塞ぎ、待ち行列に 1 件を入れてから 3 本目を接続する
+// (サーバ, 待っていた接続, 3 本目) を、1 本目を閉じてワーカーを空けた後に返す
+fn saturate(
+    policy: ShedPolicy,
+    metrics: Option<SocketAddr>,
+) -> (ServerHandle, TcpStream, TcpStream) {
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        workers: 1,
+        queue_capacity: 1,
+        shed_policy: policy,
+        metrics_listen: metrics.map(|m| m.to_string()),
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+    let busy = client(proxy);
+    thread::sleep(Duration::from_millis(200));
+    let waiting = client(proxy);
+    thread::sleep(Duration::from_millis(200));
+    let last = client(proxy);
+    thread::sleep(Duration::from_millis(200));
+    drop(busy);
+    (server, waiting, last)
+}
+
+#[test]
+fn full_queue_sheds_by_policy_and_counts() {
+    let metrics = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    // メトリクスのカウンタはプロセス全体で共有するため、2 つ目のサーバの分も同じ所で数える
+    let (newest, mut waiting, mut last) = saturate(ShedPolicy::Newest, Some(metrics));
+    assert!(is_closed(&mut last), "newest connection was not shed");
+    assert!(is_served(&mut waiting), "waiting connection was not served");
+    assert_eq!(shed_total(metrics), 1);
+    drop((waiting, last));
+    newest.shutdown().unwrap();
+
+    let (oldest, mut waiting, mut last) = saturate(ShedPolicy::Oldest, None);
+    assert!(is_closed(&mut waiting), "oldest connection was not shed");
+    assert!(is_served(&mut last), "newest connection was not served");
+    assert_eq!(shed_total(metrics), 2);
+
+    drop((waiting, last));
+    oldest.shutdown().unwrap();
+}
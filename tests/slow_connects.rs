@@ -0,0 +1,46 @@
+// 遅かった接続の一覧（Config::slow_connects）の結合テスト
+// ドメイン名の宛先への接続が、名前解決と接続の時間付きでメトリクスのエンドポイントの /slow に出ることを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, socks5_request, spawn_echo};
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut scrape = TcpStream::connect(addr).unwrap();
+    write!(scrape, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    scrape.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn slow_connects_are_listed_at_slow() {
+    let metrics = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        metrics_listen: Some(metrics.to_string()),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let port = spawn_echo().port();
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, 9];
+    request.extend_from_slice(b"localhost");
+    request.extend_from_slice(&port.to_be_bytes());
+    let (mut client, reply) = socks5_request(server.local_addrs()[0], &request);
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_echo(&mut client);
+
+    drop(client);
+    let response = get(metrics, "/slow");
+    server.shutdown().unwrap();
+    let line = response
+        .lines()
+        .find(|l| l.starts_with(&format!("localhost:{port} total_ms=")))
+        .unwrap_or_else(|| panic!("{response}"));
+    assert!(line.contains(" resolve_ms=") && line.contains(" connect_ms="), "{line}");
+    assert!(line.ends_with(" result=ok"), "{line}");
+}
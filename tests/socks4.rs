@@ -0,0 +1,57 @@
+// SOCKS4 のフォールバックの結合テスト
+// No Auth を選べる設定では CONNECT を中継し、選べない設定（認証必須・methods に none が無い）では
+// 認証を飛ばせないよう CD=91 で拒否することを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{account, assert_echo, client, credentials, spawn_echo};
+
+// SOCKS4 の CONNECT を送り、応答（8 バイト）と接続を返す
+fn socks4_connect(config: Config, dst: SocketAddr) -> (TcpStream, [u8; 8]) {
+    let mut client = client(common::spawn_proxy(config));
+    let SocketAddr::V4(dst) = dst else { panic!("IPv4 only") };
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&dst.port().to_be_bytes());
+    request.extend_from_slice(&dst.ip().octets());
+    request.extend_from_slice(b"alice\0");
+    client.write_all(&request).unwrap();
+    let mut reply = [0u8; 8];
+    client.read_exact(&mut reply).unwrap();
+    (client, reply)
+}
+
+fn with_auth(config: Config) -> Config {
+    Config {
+        auth: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        ..config
+    }
+}
+
+#[test]
+fn connect_is_relayed_when_no_auth_is_selectable() {
+    let (mut client, reply) = socks4_connect(Config::default(), spawn_echo());
+    assert_eq!(reply[..2], [0x00, 0x5A]);
+    assert_echo(&mut client);
+
+    // 認証があっても No Auth を選べるなら、SOCKS5 と同じく受け付ける
+    let (mut client, reply) = socks4_connect(with_auth(Config::default()), spawn_echo());
+    assert_eq!(reply[..2], [0x00, 0x5A]);
+    assert_echo(&mut client);
+}
+
+#[test]
+fn connect_is_refused_when_no_auth_is_not_selectable() {
+    let configs = [
+        with_auth(Config { require_auth: true, ..Config::default() }),
+        with_auth(Config { methods: vec![0x02], ..Config::default() }),
+    ];
+    for config in configs {
+        let (mut client, reply) = socks4_connect(config, spawn_echo());
+        assert_eq!(reply[..2], [0x00, 0x5B]);
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
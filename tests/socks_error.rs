@@ -0,0 +1,103 @@
+// handle_connection が返す SocksError の結合テスト
+// 拒否・認証の失敗・接続の失敗などが、それぞれの種類で返ることを確かめる
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Config, SocksError, handle_connection};
+
+mod common;
+use common::{
+    account, client, connect_request, credentials, read_reply, socks5_request, spawn_echo,
+    spawn_proxy_with_results, userpass,
+};
+
+// config のプロキシへ send で要求を送り、その接続の handle_connection の結果を返す
+fn result_of(config: Config, send: impl FnOnce(SocketAddr)) -> Result<(), SocksError> {
+    let (proxy, results) = spawn_proxy_with_results(config);
+    send(proxy);
+    results.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+#[test]
+fn blocked_destination_is_destination_denied() {
+    let dst = spawn_echo();
+    let config = Config { blocked_ports: vec![dst.port()], ..Config::default() };
+    let result = result_of(config, |proxy| {
+        let (_, reply) = socks5_request(proxy, &connect_request(dst));
+        assert_eq!(reply[..2], [0x05, 0x02]);
+    });
+    assert!(matches!(result, Err(SocksError::DestinationDenied(_))), "{result:?}");
+}
+
+#[test]
+fn refused_connection_is_connect_failed() {
+    // 閉じた待ち受けのポートへの接続は拒否される
+    let dst = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let result = result_of(Config::default(), |proxy| {
+        let (_, reply) = socks5_request(proxy, &connect_request(dst));
+        assert_eq!(reply[..2], [0x05, 0x05]);
+    });
+    match result {
+        Err(SocksError::ConnectFailed(e)) => assert_eq!(e.kind(), ErrorKind::ConnectionRefused),
+        other => panic!("expected ConnectFailed, got {other:?}"),
+    }
+}
+
+#[test]
+fn unsupported_command_is_reported_with_its_code() {
+    let result = result_of(Config::default(), |proxy| {
+        let (_, reply) = socks5_request(proxy, &[0x05, 0x09, 0x00, 0x01, 127, 0, 0, 1, 0, 80]);
+        assert_eq!(reply[..2], [0x05, 0x07]);
+    });
+    assert!(matches!(result, Err(SocksError::UnsupportedCommand(0x09))), "{result:?}");
+}
+
+#[test]
+fn wrong_password_is_auth_failed() {
+    let config = Config {
+        auth: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        ..Config::default()
+    };
+    let result = result_of(config, |proxy| {
+        let mut client = client(proxy);
+        client.write_all(&[0x05, 0x01, 0x02]).unwrap();
+        let mut selection = [0u8; 2];
+        client.read_exact(&mut selection).unwrap();
+        client.write_all(&userpass("alice", "wrong")).unwrap();
+        let mut status = [0u8; 2];
+        client.read_exact(&mut status).unwrap();
+        assert_ne!(status[1], 0x00);
+    });
+    assert!(matches!(result, Err(SocksError::AuthFailed(_))), "{result:?}");
+}
+
+#[test]
+fn denied_client_is_destination_denied() {
+    let config = Config { client_deny: vec!["127.0.0.1".parse().unwrap()], ..Config::default() };
+    let result = result_of(config, |proxy| {
+        let mut client = client(proxy);
+        let mut rest = Vec::new();
+        let _ = client.read_to_end(&mut rest);
+        assert!(rest.is_empty(), "unexpected reply {rest:?}");
+    });
+    assert!(matches!(result, Err(SocksError::DestinationDenied(_))), "{result:?}");
+}
+
+#[test]
+fn bind_over_a_unix_socket_is_unsupported_command() {
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let config = Arc::new(Config::default());
+    let handle = thread::spawn(move || handle_connection(&mut server, &config));
+    client.write_all(&[0x05, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x07]);
+    let result = handle.join().unwrap();
+    assert!(matches!(result, Err(SocksError::UnsupportedCommand(0x02))), "{result:?}");
+}
@@ -0,0 +1,54 @@
+// spawn_server で組み込んだサーバの結合テスト
+// shutdown で新規の受付が止まり、処理中の接続が終わるまで待ってから返ることを確かめる
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, Parsed, spawn_server};
+
+mod common;
+use common::{socks5_connect, spawn_echo};
+
+#[test]
+fn shutdown_stops_accepting_and_drains_connections() {
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        shutdown_grace: Duration::from_secs(10),
+        ..Config::default()
+    })
+    .unwrap();
+    let proxy = server.local_addrs()[0];
+
+    let mut client = socks5_connect(proxy, spawn_echo());
+
+    let stopping = thread::spawn(move || server.shutdown());
+    thread::sleep(Duration::from_millis(300));
+    // 処理中の接続は続いていて、shutdown はその終了を待っている
+    client.write_all(b"ping").unwrap();
+    let mut echoed = [0u8; 4];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"ping");
+    assert!(!stopping.is_finished());
+    // 新しい接続はもう受け付けない
+    assert!(TcpStream::connect(proxy).is_err());
+
+    drop(client);
+    stopping.join().unwrap().unwrap();
+}
+
+#[test]
+fn shutdown_grace_can_be_set_on_the_command_line() {
+    let args = ["--listen", "127.0.0.1:0", "--shutdown-grace", "1"].map(String::from);
+    let Parsed::Run(config) = Config::default().apply_args(args).unwrap() else {
+        panic!("expected a configuration to run");
+    };
+    assert_eq!(config.shutdown_grace, Duration::from_secs(1));
+    let server = spawn_server(*config).unwrap();
+    let _client = socks5_connect(server.local_addrs()[0], spawn_echo());
+
+    // 接続が残っていても、1 秒待ったところで shutdown は返る
+    let started = Instant::now();
+    server.shutdown().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+}
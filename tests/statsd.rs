@@ -0,0 +1,52 @@
+// StatsD への送信（Config::statsd）の結合テスト
+// 接続の数が、カウンタの増分とゲージとして UDP で届くことを確かめる
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{socks5_connect, spawn_echo};
+
+#[test]
+fn counters_are_sent_as_statsd_packets() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        statsd: Some(receiver.local_addr().unwrap().to_string()),
+        statsd_interval: Duration::from_millis(100),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let client = socks5_connect(server.local_addrs()[0], spawn_echo());
+
+    // 接続の前のパケットもあるため、処理中の接続が 1 と届くまで読み、カウンタの増分を足し合わせる
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut packets = Vec::new();
+    let mut total = 0;
+    let mut buf = [0u8; 1500];
+    while Instant::now() < deadline {
+        let Ok(n) = receiver.recv(&mut buf) else { continue };
+        let packet = String::from_utf8_lossy(&buf[..n]).into_owned();
+        for line in packet.lines() {
+            assert!(line.ends_with("|c") || line.ends_with("|g"), "{packet}");
+            if let Some(delta) = line.strip_prefix("socks5_connections_total:") {
+                total += delta.trim_end_matches("|c").parse::<u64>().unwrap();
+            }
+        }
+        let active = packet.lines().any(|l| l == "socks5_connections_active:1|g");
+        packets.push(packet);
+        if active {
+            break;
+        }
+    }
+    drop(client);
+    server.shutdown().unwrap();
+
+    let last = packets.last().expect("no StatsD packet received");
+    assert!(last.lines().any(|l| l == "socks5_connections_active:1|g"), "{packets:?}");
+    assert!(last.lines().any(|l| l.starts_with("socks5_auth_failures_total:")), "{last}");
+    assert_eq!(total, 1, "{packets:?}");
+}
@@ -0,0 +1,46 @@
+// 要求と応答の順序（Config::strict_ordering）の結合テスト
+// Request の直後に続けてデータを送るクライアントを、strict では拒否し、既定ではそのまま転送することを確かめる
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use seccamp2025mini_online::{Config, SocksError};
+
+mod common;
+use common::{client, connect_request, read_reply, spawn_echo, spawn_proxy_with_results};
+
+// Greeting・dst への CONNECT・データを 1 度に送り、メソッド選択まで読んだ接続を返す
+fn pipeline(proxy: SocketAddr, dst: SocketAddr) -> TcpStream {
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x00];
+    input.extend_from_slice(&connect_request(dst));
+    input.extend_from_slice(b"early data");
+    client.write_all(&input).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    client
+}
+
+#[test]
+fn strict_mode_rejects_data_sent_before_the_reply() {
+    let (proxy, results) =
+        spawn_proxy_with_results(Config { strict_ordering: true, ..Config::default() });
+    let target = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = pipeline(proxy, target.local_addr().unwrap());
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x02]);
+    let err = results.recv().unwrap().unwrap_err();
+    assert!(matches!(err, SocksError::MalformedRequest(_)), "{err:?}");
+    assert_eq!(err.to_string(), "client sent data before the reply (strict ordering)");
+    // 拒否は接続する前に決まるため、宛先には接続が来ていない
+    target.set_nonblocking(true).unwrap();
+    assert_eq!(target.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+}
+
+#[test]
+fn lenient_mode_forwards_pipelined_data() {
+    let mut client = pipeline(common::spawn_proxy(Config::default()), spawn_echo());
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x00]);
+    let mut echoed = [0u8; 10];
+    client.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"early data");
+}
@@ -0,0 +1,69 @@
+// テナントタグモード（Config::tenant_tags）の結合テスト
+// 名乗ったユーザ名がタグとして転送のログに残ること・認証の設定と組み合わせられないことを確かめる
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{account, assert_echo, client, connect_request, credentials, read_reply, spawn_echo};
+
+#[test]
+fn tag_reaches_the_transfer_log() {
+    let log = env::temp_dir().join(format!("tenant-tags-{}.log", process::id()));
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        auth: true,
+        tenant_tags: true,
+        log_file: Some(log.to_string_lossy().into_owned()),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let mut client = client(server.local_addrs()[0]);
+    client.write_all(&[0x05, 0x01, 0x02]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x02]);
+    // パスワードは検証されない
+    client.write_all(&common::userpass("acme", "anything")).unwrap();
+    let mut status = [0u8; 2];
+    client.read_exact(&mut status).unwrap();
+    assert_eq!(status, [0x01, 0x00]);
+    client.write_all(&connect_request(spawn_echo())).unwrap();
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x00]);
+    assert_echo(&mut client);
+    drop(client);
+
+    // 接続が閉じた後の転送量の行に、タグが付く
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = fs::read_to_string(&log).unwrap_or_default();
+        if text.contains("client -> remote [tenant acme]") || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    server.shutdown().unwrap();
+    let _ = fs::remove_file(&log);
+    assert!(text.contains("client -> remote [tenant acme]: 23 bytes"), "{text}");
+    assert!(text.contains("user=\"acme\""), "{text}");
+}
+
+#[test]
+fn tenant_tags_cannot_be_combined_with_authentication() {
+    let args = ["--tenant-tags", "--require-auth"].map(String::from);
+    assert!(Config::default().apply_args(args).is_err());
+
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        tenant_tags: true,
+        credentials: credentials(vec![("alice", account("secret"))]),
+        ..Config::default()
+    });
+    assert!(server.is_err());
+}
@@ -0,0 +1,45 @@
+// プロセス全体の転送速度の制限（Config::total_rate_limit）の結合テスト
+// 複数の接続が同時に転送しても、合計の速度が上限（と 1 秒分のバースト）を超えないことを確かめる
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::{socks5_connect, spawn_echo};
+
+const RATE: u64 = 100_000;
+const PER_CONNECTION: usize = 50_000;
+
+#[test]
+fn aggregate_throughput_stays_under_the_cap() {
+    let proxy = common::spawn_proxy(Config { total_rate_limit: RATE, ..Config::default() });
+    let dst = spawn_echo();
+
+    let started = Instant::now();
+    let clients: Vec<_> = (0..3)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut client = socks5_connect(proxy, dst);
+                client.set_read_timeout(Some(Duration::from_secs(20))).unwrap();
+                client.write_all(&vec![0x5A; PER_CONNECTION]).unwrap();
+                client.shutdown(Shutdown::Write).unwrap();
+                let mut echoed = Vec::new();
+                client.read_to_end(&mut echoed).unwrap();
+                echoed.len()
+            })
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(client.join().unwrap(), PER_CONNECTION);
+    }
+    let elapsed = started.elapsed();
+
+    // 3 本 × 往復で 300,000 バイト。最初の 1 秒分（100,000 バイト）を除き、上限の速度でしか流れない
+    let total = (3 * PER_CONNECTION * 2) as u64;
+    let minimum = Duration::from_secs_f64((total - RATE) as f64 / RATE as f64);
+    assert!(elapsed >= minimum - Duration::from_millis(50), "{elapsed:?}");
+    assert!(elapsed < minimum + Duration::from_secs(4), "{elapsed:?}");
+}
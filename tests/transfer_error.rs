@@ -0,0 +1,88 @@
+// 転送の途中で失敗した接続の結合テスト
+// 失敗を返すときも、それまでに転送したバイト数がエラーやログに含まれることを確かめる
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, SocksError, spawn_server};
+
+mod common;
+use common::{socks5_connect, spawn_proxy_with_results};
+
+// 接続されたら、相手が切るまで送り続ける宛先
+fn spawn_flood() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let chunk = [0x55u8; 16 * 1024];
+        while stream.write_all(&chunk).is_ok() {}
+    });
+    addr
+}
+
+#[test]
+fn failed_transfer_reports_bytes_so_far() {
+    let dst = spawn_flood();
+    let (proxy, rx) = spawn_proxy_with_results(Config::default());
+
+    let mut client = socks5_connect(proxy, dst);
+    let mut head = [0u8; 1024];
+    client.read_exact(&mut head).unwrap();
+    // 読み残しがある状態で閉じると RST になり、プロキシのクライアントへの書き込みが失敗する
+    drop(client);
+
+    let err = rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap_err();
+    // 転送中の失敗はプロトコルの失敗ではなく、読み書きの失敗として返る
+    assert!(matches!(err, SocksError::Io(_)), "{err:?}");
+    let msg = err.to_string();
+    assert!(msg.contains("bytes sent, ") && msg.contains(" bytes received)"), "{msg}");
+    assert!(!msg.contains(" 0 bytes received"), "{msg}");
+}
+
+#[test]
+fn remote_reset_is_logged_with_bytes_so_far() {
+    // "partial" を送った後、読み残しのあるまま閉じて RST を返す宛先
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dst = listener.local_addr().unwrap();
+    let (sent_tx, sent_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"partial").unwrap();
+        sent_rx.recv().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        drop(stream);
+    });
+
+    let log = env::temp_dir().join(format!("transfer-reset-{}.log", process::id()));
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        log_file: Some(log.to_string_lossy().into_owned()),
+        ..Config::default()
+    })
+    .unwrap();
+    let mut client = socks5_connect(server.local_addrs()[0], dst);
+    let mut head = [0u8; 7];
+    client.read_exact(&mut head).unwrap();
+    client.write_all(b"never read").unwrap();
+    sent_tx.send(()).unwrap();
+
+    let expected = "remote -> client: connection reset mid-transfer after 7 bytes";
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = fs::read_to_string(&log).unwrap_or_default();
+        if text.contains(expected) || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    drop(client);
+    server.shutdown().unwrap();
+    let _ = fs::remove_file(&log);
+    assert!(text.contains(expected), "{text}");
+}
@@ -0,0 +1,100 @@
+// トンネル全体の無通信タイムアウトの結合テスト
+// 片方向だけでもデータが流れていればトンネルを保ち、両方向とも止まったら両側を閉じることを確かめる
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, Parsed};
+
+mod common;
+use common::socks5_connect;
+
+const TIMEOUT: Duration = Duration::from_millis(400);
+
+// 受け取るだけで何も返さない宛先。閉じられたら受け取ったバイト数を送る
+fn spawn_sink() -> (SocketAddr, mpsc::Receiver<usize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        let _ = stream.read_to_end(&mut received);
+        tx.send(received.len()).unwrap();
+    });
+    (addr, rx)
+}
+
+fn spawn_proxy() -> SocketAddr {
+    common::spawn_proxy(Config {
+        idle_timeout: None,
+        tunnel_idle_timeout: Some(TIMEOUT),
+        ..Config::default()
+    })
+}
+
+#[test]
+fn one_way_traffic_keeps_the_tunnel_open_until_both_directions_are_idle() {
+    let (dst, sink) = spawn_sink();
+    let mut client = socks5_connect(spawn_proxy(), dst);
+
+    // 宛先からは何も返らないが、クライアントから送り続けている間は閉じない
+    let started = Instant::now();
+    let mut sent = 0;
+    while started.elapsed() < TIMEOUT * 3 {
+        client.write_all(b"tick").unwrap();
+        sent += 4;
+        thread::sleep(TIMEOUT / 4);
+    }
+
+    // 送るのをやめると、タイムアウトの後に両側が閉じられる
+    let stopped = Instant::now();
+    let mut buf = [0u8; 16];
+    assert_eq!(client.read(&mut buf).unwrap(), 0);
+    let waited = stopped.elapsed();
+    assert!(waited >= TIMEOUT / 2 && waited < TIMEOUT * 4, "closed after {waited:?}");
+    assert_eq!(sink.recv_timeout(Duration::from_secs(5)).unwrap(), sent);
+}
+
+// keepalive のプローブはデータを運ばないため、どちらの無通信タイムアウトも延ばさない
+// （プローブが 1 秒ごとに応答されて TCP の接続が生きていても、データが流れなければ閉じる）
+#[test]
+fn keepalive_probes_do_not_reset_the_idle_timers() {
+    let timeout = Duration::from_millis(2500);
+    let keepalive = Config {
+        keepalive_idle: Some(Duration::from_secs(1)),
+        keepalive_interval: Duration::from_secs(1),
+        ..Config::default()
+    };
+    let configs = [
+        Config { idle_timeout: Some(timeout), ..keepalive.clone() },
+        Config { idle_timeout: None, tunnel_idle_timeout: Some(timeout), ..keepalive },
+    ];
+    for config in configs {
+        let (dst, sink) = spawn_sink();
+        let mut client = socks5_connect(common::spawn_proxy(config), dst);
+        client.write_all(b"tick").unwrap();
+
+        let started = Instant::now();
+        let mut buf = [0u8; 16];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        let waited = started.elapsed();
+        assert!(waited >= timeout / 2 && waited < timeout * 2, "closed after {waited:?}");
+        assert_eq!(sink.recv_timeout(Duration::from_secs(5)).unwrap(), 4);
+    }
+}
+
+#[test]
+fn idle_timeouts_can_be_set_on_the_command_line() {
+    let parse = |args: [&str; 2]| match Config::default().apply_args(args.map(String::from)) {
+        Ok(Parsed::Run(config)) => Ok((config.idle_timeout, config.tunnel_idle_timeout)),
+        Ok(_) => panic!("expected a configuration to run"),
+        Err(e) => Err(e),
+    };
+    assert_eq!(parse(["--idle-timeout", "5"]).unwrap(), (Some(Duration::from_secs(5)), None));
+    assert_eq!(parse(["--idle-timeout", "0"]).unwrap(), (None, None));
+    assert_eq!(parse(["--tunnel-idle-timeout", "7"]).unwrap().1, Some(Duration::from_secs(7)));
+    assert!(parse(["--idle-timeout", "soon"]).is_err());
+}
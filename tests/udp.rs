@@ -0,0 +1,145 @@
+// UDP ASSOCIATE (CMD=0x03) の結合テスト
+// 中継の往復・宛先の規則の適用・送ったことのない相手からの破棄・転送量の上限への算入を確かめる
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use seccamp2025mini_online::{Account, Config, Quota, QuotaPeriod};
+
+mod common;
+use common::{account, client, credentials, read_reply, userpass};
+
+// 受け取ったデータグラムをそのまま送り返す宛先を立て、そのアドレスを返す
+fn spawn_udp_echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        while let Ok((n, from)) = socket.recv_from(&mut buf) {
+            let _ = socket.send_to(&buf[..n], from);
+        }
+    });
+    addr
+}
+
+// DST.ADDR を 0.0.0.0:0 として UDP ASSOCIATE を送り、制御用の接続と中継のアドレスを返す
+// auth があれば RFC1929 で認証する
+fn associate(proxy: SocketAddr, auth: Option<(&str, &str)>) -> (TcpStream, SocketAddr) {
+    let mut client = client(proxy);
+    let mut input = match auth {
+        Some(_) => vec![0x05, 0x01, 0x02],
+        None => vec![0x05, 0x01, 0x00],
+    };
+    if let Some((user, password)) = auth {
+        input.extend_from_slice(&userpass(user, password));
+    }
+    input.extend_from_slice(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    client.write_all(&input).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    if auth.is_some() {
+        let mut status = [0u8; 2];
+        client.read_exact(&mut status).unwrap();
+        assert_eq!(status, [0x01, 0x00]);
+    }
+    let reply = read_reply(&mut client);
+    assert_eq!(reply[..2], [0x05, 0x00], "reply {reply:?}");
+    let ip = Ipv4Addr::new(reply[4], reply[5], reply[6], reply[7]);
+    let port = u16::from_be_bytes([reply[8], reply[9]]);
+    (client, SocketAddr::from((ip, port)))
+}
+
+// クライアント側の UDP ソケット（受信は 1 秒で打ち切る）
+fn udp_client() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    socket
+}
+
+// target 宛ての SOCKS5 UDP ヘッダを付けたデータグラム
+fn datagram(target: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let SocketAddr::V4(target) = target else { panic!("IPv4 only") };
+    let mut packet = vec![0x00, 0x00, 0x00, 0x01];
+    packet.extend_from_slice(&target.ip().octets());
+    packet.extend_from_slice(&target.port().to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// 中継から 1 つ受け取る（来なければ None）
+fn receive(socket: &UdpSocket) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 2048];
+    let n = socket.recv(&mut buf).ok()?;
+    Some(buf[..n].to_vec())
+}
+
+#[test]
+fn datagrams_are_relayed_both_ways() {
+    let echo = spawn_udp_echo();
+    // DST が 0.0.0.0:0 の ASSOCIATE は allowed_ports に関わらず受け付け、データグラムごとに確かめる
+    let config = Config { allowed_ports: vec![echo.port()], ..Config::default() };
+    let (_control, relay) = associate(common::spawn_proxy(config), None);
+    let socket = udp_client();
+
+    socket.send_to(&datagram(echo, b"ping"), relay).unwrap();
+    assert_eq!(receive(&socket), Some(datagram(echo, b"ping")));
+    socket.send_to(&datagram(echo, b"again"), relay).unwrap();
+    assert_eq!(receive(&socket), Some(datagram(echo, b"again")));
+}
+
+#[test]
+fn datagrams_to_disallowed_ports_are_dropped() {
+    let (allowed, blocked) = (spawn_udp_echo(), spawn_udp_echo());
+    let config = Config { allowed_ports: vec![allowed.port()], ..Config::default() };
+    let (_control, relay) = associate(common::spawn_proxy(config), None);
+    let socket = udp_client();
+
+    socket.send_to(&datagram(blocked, b"blocked"), relay).unwrap();
+    assert_eq!(receive(&socket), None);
+    socket.send_to(&datagram(allowed, b"allowed"), relay).unwrap();
+    assert_eq!(receive(&socket), Some(datagram(allowed, b"allowed")));
+}
+
+#[test]
+fn datagrams_from_other_hosts_are_not_forwarded() {
+    let echo = spawn_udp_echo();
+    let (_control, relay) = associate(common::spawn_proxy(Config::default()), None);
+    let socket = udp_client();
+    socket.send_to(&datagram(echo, b"ping"), relay).unwrap();
+    assert_eq!(receive(&socket), Some(datagram(echo, b"ping")));
+
+    // クライアントが送ったことのない相手からのデータグラムは届かない
+    let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+    stranger.send_to(b"unsolicited", relay).unwrap();
+    assert_eq!(receive(&socket), None);
+}
+
+#[test]
+fn relayed_bytes_count_towards_the_quota() {
+    let echo = spawn_udp_echo();
+    let quota = Quota { bytes: 100, period: QuotaPeriod::Day };
+    let user = Account { quota: Some(quota), ..account("secret") };
+    let credentials = credentials(vec![("udp-user", user)]);
+    let config = Config { auth: true, credentials, ..Config::default() };
+    let proxy = common::spawn_proxy(config);
+    let (control, relay) = associate(proxy, Some(("udp-user", "secret")));
+    let socket = udp_client();
+    for _ in 0..2 {
+        socket.send_to(&datagram(echo, &[0x42; 40]), relay).unwrap();
+        assert_eq!(receive(&socket), Some(datagram(echo, &[0x42; 40])));
+    }
+    drop(control);
+
+    // 送った 80 バイトだけでは上限に届かず、受け取った分も数えられて上限を超える
+    // （2 回目の応答が届いた時点で、1 回目の受信は加算済み）
+    let mut client = client(proxy);
+    let mut input = vec![0x05, 0x01, 0x02];
+    input.extend_from_slice(&userpass("udp-user", "secret"));
+    input.extend_from_slice(&common::connect_request(echo));
+    client.write_all(&input).unwrap();
+    let mut status = [0u8; 4];
+    client.read_exact(&mut status).unwrap();
+    assert_eq!(status, [0x05, 0x02, 0x01, 0x00]);
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x02]);
+}
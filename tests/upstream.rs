@@ -0,0 +1,45 @@
+// 上位プロキシ経由の接続（Config::upstream）の結合テスト
+// 接続の相手が上位プロキシでも、ログには要求された本当の宛先が残ることを確かめる
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, spawn_server};
+
+mod common;
+use common::{assert_echo, socks5_connect, spawn_echo};
+
+#[test]
+fn logs_show_the_real_destination_when_chaining() {
+    let upstream = common::spawn_proxy(Config::default());
+    let dst = spawn_echo();
+    let log = env::temp_dir().join(format!("upstream-{}.log", process::id()));
+    let server = spawn_server(Config {
+        listen: vec!["127.0.0.1:0".to_string()],
+        upstream: Some(upstream.to_string()),
+        log_file: Some(log.to_string_lossy().into_owned()),
+        ..Config::default()
+    })
+    .unwrap();
+
+    let mut client = socks5_connect(server.local_addrs()[0], dst);
+    assert_echo(&mut client);
+    drop(client);
+
+    let access = format!("dst=\"{dst}\"");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let text = loop {
+        let text = fs::read_to_string(&log).unwrap_or_default();
+        if text.contains(&access) || Instant::now() > deadline {
+            break text;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    server.shutdown().unwrap();
+    let _ = fs::remove_file(&log);
+    let connected = format!("Connected to destination: {dst} via upstream {upstream}");
+    assert!(text.contains(&connected), "{text}");
+    assert!(text.contains(&access), "{text}");
+}
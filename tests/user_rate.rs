@@ -0,0 +1,65 @@
+// ユーザごとの転送速度の上限（認証情報ファイルの rate=）の結合テスト
+// 読み直し（ServerHandle::reload）で下げた上限が、転送中の接続にも効くことを確かめる
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::{Config, Parsed, spawn_server};
+
+mod common;
+use common::{client, connect_request, read_reply, spawn_echo, userpass};
+
+// len バイトを送り、同じだけ返ってくるまでの時間を返す
+fn echo(client: &mut TcpStream, len: usize) -> Duration {
+    let started = Instant::now();
+    let mut writer = client.try_clone().unwrap();
+    let sender = thread::spawn(move || writer.write_all(&vec![b'x'; len]).unwrap());
+    let mut received = vec![0u8; len];
+    client.read_exact(&mut received).unwrap();
+    sender.join().unwrap();
+    started.elapsed()
+}
+
+#[test]
+fn reloaded_rate_throttles_an_active_connection() {
+    let path = env::temp_dir().join(format!("user-rate-{}.txt", process::id()));
+    fs::write(&path, "alice:secret rate=10M\n").unwrap();
+    let args = ["--listen", "127.0.0.1:0", "--allow-plaintext-passwords", "--auth-file"]
+        .into_iter()
+        .map(String::from)
+        .chain([path.to_string_lossy().into_owned()]);
+    let Parsed::Run(config) = Config::default().apply_args(args).unwrap() else {
+        panic!("expected a configuration to run");
+    };
+    let server = spawn_server(*config).unwrap();
+
+    let mut client = client(server.local_addrs()[0]);
+    client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    client.write_all(&[0x05, 0x01, 0x02]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x02]);
+    client.write_all(&userpass("alice", "secret")).unwrap();
+    let mut status = [0u8; 2];
+    client.read_exact(&mut status).unwrap();
+    assert_eq!(status, [0x01, 0x00]);
+    client.write_all(&connect_request(spawn_echo())).unwrap();
+    assert_eq!(read_reply(&mut client)[..2], [0x05, 0x00]);
+
+    // 10 MB/s では 200 KB はすぐに届く
+    let fast = echo(&mut client, 200 * 1024);
+    assert!(fast < Duration::from_secs(1), "{fast:?}");
+
+    // 20 KB/s に下げると、同じ接続でも最初の 1 秒分の後は上限の速さでしか届かない
+    fs::write(&path, "alice:secret rate=20K\n").unwrap();
+    server.reload();
+    let slow = echo(&mut client, 60 * 1024);
+    fs::remove_file(&path).unwrap();
+    assert!(slow >= Duration::from_millis(1500), "{slow:?}");
+    drop(client);
+    server.shutdown().unwrap();
+}
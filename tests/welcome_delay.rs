@@ -0,0 +1,37 @@
+// 接続直後の遅延（Config::welcome_delay）の結合テスト
+// Greeting を読む前に指定の時間だけ待つこと・既定では待たないことを、時間の幅を持たせて確かめる
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use seccamp2025mini_online::Config;
+
+mod common;
+use common::client;
+
+// 接続してからメソッド選択の応答が届くまでの時間
+fn time_to_selection(proxy: SocketAddr) -> Duration {
+    let started = Instant::now();
+    let mut client = client(proxy);
+    client.write_all(&[0x05, 0x01, 0x00]).unwrap();
+    let mut selection = [0u8; 2];
+    client.read_exact(&mut selection).unwrap();
+    assert_eq!(selection, [0x05, 0x00]);
+    started.elapsed()
+}
+
+#[test]
+fn greeting_is_answered_after_the_welcome_delay() {
+    let delay = Duration::from_millis(300);
+    let proxy = common::spawn_proxy(Config { welcome_delay: delay, ..Config::default() });
+    let elapsed = time_to_selection(proxy);
+    // sleep は指定より短くならないため下限は厳密に、上限はスケジューラの遅れを見込んで緩くする
+    assert!(elapsed >= delay, "{elapsed:?}");
+    assert!(elapsed < delay + Duration::from_secs(2), "{elapsed:?}");
+}
+
+#[test]
+fn greeting_is_answered_immediately_by_default() {
+    let elapsed = time_to_selection(common::spawn_proxy(Config::default()));
+    assert!(elapsed < Duration::from_millis(250), "{elapsed:?}");
+}